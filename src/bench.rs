@@ -0,0 +1,162 @@
+use crate::{
+    FrameRequest, RenderAcceptResponse, RenderResponse, RenderSettings, Request, config, inherit,
+    logging::{error, log},
+    output::{self, Color, paint},
+    pool, profile, protocol, read_header, to_header, transfer, user,
+};
+use clap::Args;
+use std::{io::Write, sync::Mutex, thread, time::Instant};
+
+#[derive(Args)]
+pub struct Options {
+    #[arg(value_parser = config::parse_ips)]
+    ips: String,
+
+    #[arg(value_parser = protocol::parse_id)]
+    id: String,
+
+    frame: usize,
+
+    #[command(flatten)]
+    settings: RenderSettings,
+}
+
+/// Renders the same frame on every server and saves the timings to the server profiles, which
+/// `render` uses to weight its scheduling. Returns whether every server finished the frame.
+pub fn bench(options: Options) -> bool {
+    let Options {
+        ips,
+        id,
+        frame,
+        settings,
+    } = options;
+
+    log!("Benchmarking frame {} of \"{}\"", frame, id);
+
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for ip in ips.split_terminator(',') {
+            let (id, settings, results) = (&id, &settings, &results);
+            scope.spawn(inherit(move || {
+                let result = bench_server(ip, id, frame, settings);
+                match &result {
+                    Ok(seconds) => log!(
+                        "{}: Rendered frame {} in {:.1}s",
+                        output::server(ip),
+                        frame,
+                        seconds
+                    ),
+                    Err(message) => error!(
+                        "{}: {}\nReason: {}",
+                        output::server(ip),
+                        paint("Benchmark failed", Color::Red),
+                        message
+                    ),
+                }
+
+                results.lock().unwrap().push((ip, result));
+            }));
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by(|(_, a), (_, b)| {
+        let seconds = |result: &Result<f64, String>| result.clone().unwrap_or(f64::INFINITY);
+        seconds(a).total_cmp(&seconds(b))
+    });
+
+    let fastest = results
+        .first()
+        .and_then(|(_, result)| result.clone().ok())
+        .unwrap_or(f64::INFINITY);
+
+    let mut profiles = profile::load();
+    let rows: Vec<Vec<String>> = results
+        .iter()
+        .map(|(ip, result)| match result {
+            Ok(seconds) => {
+                profile::benchmark(&mut profiles, ip, *seconds);
+
+                vec![
+                    output::server(ip),
+                    format!("{:.1}s", seconds),
+                    format!("{:.2}", fastest / seconds.max(f64::EPSILON)),
+                ]
+            }
+            Err(_) => vec![output::server(ip), String::from("-"), String::from("-")],
+        })
+        .collect();
+    profile::save(&profiles);
+
+    log!(
+        "Benchmark for \"{}\":\n{}",
+        id,
+        output::table(&["SERVER", "TIME", "SPEED"], &rows)
+    );
+
+    results.iter().all(|(_, result)| result.is_ok())
+}
+
+fn bench_server(
+    ip: &str,
+    id: &str,
+    frame: usize,
+    settings: &RenderSettings,
+) -> Result<f64, String> {
+    let mut server = pool::try_checkout(ip, None).map_err(|error| error.to_string())?;
+
+    let request = to_header(
+        serde_json::to_vec(&Request::Render {
+            id: Some(String::from(id)),
+            prefetch: false,
+            addons: settings.addons.clone(),
+            heartbeat: false,
+            blender: settings.blender_version.clone(),
+            compression: None,
+            priority: None,
+            user: user(),
+        })
+        .unwrap(),
+    );
+    server
+        .write_all(&request)
+        .map_err(|error| error.to_string())?;
+
+    let response = read_header(&mut server).map_err(|error| error.to_string())?;
+    if let RenderAcceptResponse::Reject { .. } = serde_json::from_slice(&response).unwrap() {
+        return Err(String::from("Render request was rejected"));
+    }
+
+    let request = to_header(
+        serde_json::to_vec(&FrameRequest {
+            id: String::from(id),
+            frame,
+            settings: RenderSettings {
+                passes: false,
+                ..settings.clone()
+            },
+        })
+        .unwrap(),
+    );
+    let start = Instant::now();
+    server
+        .write_all(&request)
+        .map_err(|error| error.to_string())?;
+
+    loop {
+        let header = read_header(&mut server).map_err(|error| error.to_string())?;
+
+        match serde_json::from_slice(&header).unwrap() {
+            RenderResponse::Okay { size, .. } => {
+                let seconds = start.elapsed().as_secs_f64();
+                transfer::read_exact(&mut server, size).map_err(|error| error.to_string())?;
+
+                return Ok(seconds);
+            }
+            RenderResponse::Fail { .. } => {
+                return Err(String::from("Server failed to render frame"));
+            }
+            RenderResponse::Progress(_) => {}
+        }
+    }
+}
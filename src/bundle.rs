@@ -0,0 +1,177 @@
+use crate::report::FrameTime;
+use image::{DynamicImage, RgbImage, imageops};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    fs::{copy, create_dir_all, write},
+    path::{Path, PathBuf},
+};
+
+const THUMBNAIL_SIZE: u32 = 256;
+const CONTACT_SHEET_COLUMNS: u32 = 8;
+
+#[derive(Serialize)]
+struct FrameEntry<'a> {
+    frame: usize,
+    file: String,
+    server: &'a str,
+    seconds: f64,
+    bytes: usize,
+}
+
+#[derive(Serialize)]
+struct BundleManifest<'a> {
+    id: &'a str,
+    frames: Vec<FrameEntry<'a>>,
+    failed: &'a [usize],
+}
+
+#[derive(Serialize)]
+struct FailedFrame {
+    frame: usize,
+    attempts: usize,
+}
+
+#[derive(Serialize)]
+struct FailedServer<'a> {
+    server: &'a str,
+    reason: &'a str,
+}
+
+#[derive(Serialize)]
+struct FailureReport<'a> {
+    frames: Vec<FailedFrame>,
+    servers: Vec<FailedServer<'a>>,
+}
+
+pub struct Bundle<'a> {
+    pub id: &'a str,
+    pub times: &'a [FrameTime],
+    pub failed: &'a [usize],
+    pub attempts: &'a HashMap<usize, usize>,
+    pub failed_hosts: &'a [(String, String)],
+    pub extra: Vec<PathBuf>,
+}
+
+impl Bundle<'_> {
+    /// Writes the frames manifest, per-frame metadata, failure report, statistics CSV and
+    /// contact sheet of a finished job into `dir`, alongside copies of `extra`.
+    pub fn write(&self, dir: &Path) -> Result<(), String> {
+        create_dir_all(dir.join("frames")).map_err(|error| error.to_string())?;
+
+        let entries: Vec<FrameEntry> = self
+            .times
+            .iter()
+            .map(|time| FrameEntry {
+                frame: time.frame,
+                file: time
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned(),
+                server: &time.server,
+                seconds: time.seconds,
+                bytes: time.bytes,
+            })
+            .collect();
+
+        for entry in &entries {
+            write_json(
+                &dir.join("frames").join(format!("{:04}.json", entry.frame)),
+                entry,
+            )?;
+        }
+
+        let mut csv = String::from("frame,file,server,seconds,bytes\n");
+        for entry in &entries {
+            csv += &format!(
+                "{},{},{},{:.3},{}\n",
+                entry.frame,
+                csv_field(&entry.file),
+                csv_field(entry.server),
+                entry.seconds,
+                entry.bytes
+            );
+        }
+        write(dir.join("stats.csv"), csv).map_err(|error| error.to_string())?;
+
+        write_json(
+            &dir.join("failures.json"),
+            &FailureReport {
+                frames: self
+                    .failed
+                    .iter()
+                    .map(|frame| FailedFrame {
+                        frame: *frame,
+                        attempts: self.attempts.get(frame).copied().unwrap_or_default(),
+                    })
+                    .collect(),
+                servers: self
+                    .failed_hosts
+                    .iter()
+                    .map(|(server, reason)| FailedServer { server, reason })
+                    .collect(),
+            },
+        )?;
+
+        write_json(
+            &dir.join("manifest.json"),
+            &BundleManifest {
+                id: self.id,
+                frames: entries,
+                failed: self.failed,
+            },
+        )?;
+
+        if !self.times.is_empty() {
+            self.contact_sheet()
+                .save(dir.join("contact_sheet.jpg"))
+                .map_err(|error| error.to_string())?;
+        }
+
+        for path in &self.extra {
+            if path.is_file() {
+                copy(path, dir.join(path.file_name().unwrap()))
+                    .map_err(|error| error.to_string())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn contact_sheet(&self) -> DynamicImage {
+        let count = self.times.len() as u32;
+        let columns = count.min(CONTACT_SHEET_COLUMNS);
+        let rows = count.div_ceil(CONTACT_SHEET_COLUMNS);
+
+        let mut sheet = RgbImage::new(columns * THUMBNAIL_SIZE, rows * THUMBNAIL_SIZE);
+        for (index, time) in self.times.iter().enumerate() {
+            let thumbnail = match image::open(&time.path) {
+                Ok(image) => image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE).to_rgb8(),
+                Err(_) => {
+                    continue;
+                }
+            };
+
+            let (column, row) = (index as u32 % columns, index as u32 / columns);
+            let x = column * THUMBNAIL_SIZE + (THUMBNAIL_SIZE - thumbnail.width()) / 2;
+            let y = row * THUMBNAIL_SIZE + (THUMBNAIL_SIZE - thumbnail.height()) / 2;
+            imageops::replace(&mut sheet, &thumbnail, x as i64, y as i64);
+        }
+
+        DynamicImage::ImageRgb8(sheet)
+    }
+}
+
+fn write_json(path: &Path, value: &impl Serialize) -> Result<(), String> {
+    write(path, serde_json::to_vec_pretty(value).unwrap()).map_err(|error| error.to_string())
+}
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        String::from(field)
+    }
+}
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    env,
+    fs::read,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+#[derive(Deserialize, Default)]
+pub struct Config {
+    #[serde(default)]
+    pub pools: HashMap<String, Vec<String>>,
+
+    #[serde(default)]
+    pub output_dirs: HashMap<String, PathBuf>,
+
+    #[serde(default)]
+    pub auth_token: Option<String>,
+
+    #[serde(default)]
+    pub user: Option<String>,
+
+    #[serde(default)]
+    templates: Map<String, Value>,
+}
+
+fn file() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_dir.join("brsp").join("config.json"))
+}
+
+/// Loads `config.json` from the client config directory. A missing file yields the defaults.
+pub fn load() -> Result<Config, String> {
+    let file = match file() {
+        Some(file) => file,
+        None => {
+            return Ok(Config::default());
+        }
+    };
+
+    let config = match read(&file) {
+        Ok(config) => config,
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            return Ok(Config::default());
+        }
+        Err(error) => {
+            return Err(format!("Could not read {}: {}", file.display(), error));
+        }
+    };
+
+    serde_json::from_slice(&config)
+        .map_err(|error| format!("Could not parse {}: {}", file.display(), error))
+}
+
+/// Value parser for server lists, replacing every `@pool` entry with the servers of that pool.
+pub fn parse_ips(ips: &str) -> Result<String, String> {
+    if !ips.contains('@') {
+        return Ok(String::from(ips));
+    }
+
+    let config = load()?;
+    let mut expanded = Vec::new();
+    for ip in ips.split_terminator(',') {
+        match ip.strip_prefix('@') {
+            Some(pool) => match config.pools.get(pool) {
+                Some(servers) => expanded.extend(servers.iter().cloned()),
+                None => {
+                    return Err(format!("Unknown server pool \"{}\"", pool));
+                }
+            },
+            None => expanded.push(String::from(ip)),
+        }
+    }
+
+    Ok(expanded.join(","))
+}
+
+/// Value parser for output directories, resolving `@name` and `@name/sub/dir` against the named
+/// output directories.
+pub fn parse_output_dir(path: &str) -> Result<PathBuf, String> {
+    let named = match path.strip_prefix('@') {
+        Some(named) => named,
+        None => {
+            return Ok(PathBuf::from(path));
+        }
+    };
+
+    let (name, rest) = named.split_once(['/', '\\']).unwrap_or((named, ""));
+    match load()?.output_dirs.get(name) {
+        Some(output_dir) if rest.is_empty() => Ok(output_dir.clone()),
+        Some(output_dir) => Ok(output_dir.join(Path::new(rest))),
+        None => Err(format!("Unknown output directory \"{}\"", name)),
+    }
+}
+
+/// Resolves a named job template from `config.json` in the client config directory. Templates
+/// may name another template in `extends`, whose values they override.
+pub fn template(name: &str) -> Result<Value, String> {
+    let templates = load()?.templates;
+
+    let mut chain: Vec<(String, Value)> = Vec::new();
+    let mut next = Some(String::from(name));
+    while let Some(name) = next {
+        if chain.iter().any(|(extended, _)| *extended == name) {
+            return Err(format!("Template \"{}\" extends itself", name));
+        }
+
+        let template = templates
+            .get(&name)
+            .cloned()
+            .ok_or_else(|| format!("Unknown template \"{}\"", name))?;
+        next = template
+            .get("extends")
+            .and_then(Value::as_str)
+            .map(String::from);
+        chain.push((name, template));
+    }
+
+    let mut resolved = Value::Object(Map::new());
+    for (_, template) in chain.into_iter().rev() {
+        merge(&mut resolved, template);
+    }
+
+    if let Value::Object(resolved) = &mut resolved {
+        resolved.remove("extends");
+    }
+
+    Ok(resolved)
+}
+
+/// Recursively merges `overrides` into `base`, replacing everything but objects.
+pub fn merge(base: &mut Value, overrides: Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                merge(base.entry(key).or_insert(Value::Null), value);
+            }
+        }
+        (base, overrides) => *base = overrides,
+    }
+}
+
+/// Fills in `value` from the `key` section of a resolved template. Fields of `value` that differ
+/// from their default, i.e. were given on the command line, take precedence.
+pub fn apply<T: Serialize + DeserializeOwned + Default>(
+    template: &Value,
+    key: &str,
+    value: T,
+) -> Result<T, String> {
+    let mut merged = match template.get(key) {
+        Some(section) => section.clone(),
+        None => {
+            return Ok(value);
+        }
+    };
+
+    let defaults = serde_json::to_value(T::default()).unwrap();
+    match serde_json::to_value(&value).unwrap() {
+        Value::Object(mut overrides) => {
+            if let Value::Object(defaults) = &defaults {
+                overrides.retain(|key, value| defaults.get(key) != Some(value));
+            }
+            merge(&mut merged, Value::Object(overrides));
+        }
+        overrides => {
+            if overrides != defaults {
+                merged = overrides;
+            }
+        }
+    }
+
+    serde_json::from_value(merged)
+        .map_err(|error| format!("Invalid \"{}\" in template: {}", key, error))
+}
@@ -0,0 +1,247 @@
+use crate::{SlotInfo, protocol, report::escape};
+use serde::Serialize;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+    time::Duration,
+};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+const REFRESH_SECONDS: u64 = 5;
+const MAX_CONNECTIONS: usize = 16;
+const MAX_HEADER_SIZE: u64 = 16 * 1024;
+
+#[derive(Serialize)]
+pub struct Dashboard {
+    pub version: String,
+    pub compute_device_type: String,
+    pub devices: Vec<String>,
+    pub slots: Vec<SlotInfo>,
+    pub queued: usize,
+    pub jobs: Vec<JobUsage>,
+}
+
+#[derive(Serialize)]
+pub struct JobUsage {
+    pub id: String,
+    pub bytes: u64,
+    pub completed: usize,
+    pub failed: usize,
+}
+
+/// Answers HTTP requests on `listener` with an HTML page at `/` and the same data as JSON at
+/// `/status`, both built from a fresh `dashboard()` per request. With a `token`, requests must
+/// carry it as `Authorization: Bearer <token>`.
+pub fn serve(listener: TcpListener, token: Option<&str>, dashboard: impl Fn() -> Dashboard + Sync) {
+    let open = AtomicUsize::new(0);
+
+    thread::scope(|scope| {
+        for mut stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(REQUEST_TIMEOUT));
+            let _ = stream.set_write_timeout(Some(REQUEST_TIMEOUT));
+
+            if open.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                open.fetch_sub(1, Ordering::SeqCst);
+                let _ = reply(
+                    &mut stream,
+                    "503 Service Unavailable",
+                    "text/plain",
+                    "Busy\n",
+                );
+                continue;
+            }
+
+            let (open, dashboard) = (&open, &dashboard);
+            scope.spawn(move || {
+                let _ = respond(stream, token, dashboard);
+                open.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+}
+
+fn respond(
+    mut stream: TcpStream,
+    token: Option<&str>,
+    dashboard: impl Fn() -> Dashboard,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?.take(MAX_HEADER_SIZE));
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    let mut bearer = None;
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 2 {
+        if let Some((name, value)) = line.split_once(':')
+            && name.eq_ignore_ascii_case("authorization")
+        {
+            bearer = value.trim().strip_prefix("Bearer ").map(String::from);
+        }
+        line.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next();
+    let path = parts
+        .next()
+        .map(|target| target.split_once('?').map_or(target, |(path, _)| path));
+
+    if let Some(token) = token
+        && !bearer.is_some_and(|bearer| protocol::tokens_match(&bearer, token))
+    {
+        return reply(
+            &mut stream,
+            "401 Unauthorized",
+            "text/plain",
+            "Missing or invalid token\n",
+        );
+    }
+
+    let (status, content_type, body) = match (method, path) {
+        (Some("GET"), Some("/")) => ("200 OK", "text/html; charset=utf-8", html(&dashboard())),
+        (Some("GET"), Some("/status")) => (
+            "200 OK",
+            "application/json",
+            serde_json::to_string(&dashboard()).unwrap(),
+        ),
+        (Some("GET"), _) => ("404 Not Found", "text/plain", String::from("Not found\n")),
+        _ => (
+            "405 Method Not Allowed",
+            "text/plain",
+            String::from("Method not allowed\n"),
+        ),
+    };
+
+    reply(&mut stream, status, content_type, &body)
+}
+
+fn reply(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> std::io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+fn html(dashboard: &Dashboard) -> String {
+    let mut slots = String::new();
+    for slot in &dashboard.slots {
+        slots += &format!(
+            "<tr><td>{}</td><td>{}</td><td>{}</td><td>{:.1}s</td><td>{}</td><td>{}</td></tr>\n",
+            slot.slot,
+            escape(&slot.address),
+            slot.state.label(),
+            slot.seconds_in_state,
+            slot.id.as_deref().map(escape).unwrap_or_default(),
+            slot.frame
+                .map(|frame| frame.to_string())
+                .unwrap_or_default()
+        );
+    }
+
+    if slots.is_empty() {
+        slots = String::from("<tr><td colspan=\"6\">No render requesters</td></tr>\n");
+    }
+
+    let mut jobs = String::new();
+    for job in &dashboard.jobs {
+        jobs += &format!(
+            "<tr><td>{}</td><td>{:.1} MiB</td><td>{}</td><td>{}</td></tr>\n",
+            escape(&job.id),
+            job.bytes as f64 / (1024 * 1024) as f64,
+            job.completed,
+            job.failed
+        );
+    }
+
+    if jobs.is_empty() {
+        jobs = String::from("<tr><td colspan=\"4\">No jobs</td></tr>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<meta http-equiv=\"refresh\" content=\"{refresh}\">
+<title>brsp server</title>
+<style>body {{ font-family: sans-serif; }} td, th {{ padding: 2px 12px; text-align: left; }}</style>
+</head>
+<body>
+<h1>brsp server</h1>
+<p>Blender {version}, {device_type}: {devices}</p>
+<p>{queued} render requester(s) queued</p>
+<h2>Slots</h2>
+<table>
+<tr><th>SLOT</th><th>ADDRESS</th><th>STATE</th><th>FOR</th><th>JOB</th><th>FRAME</th></tr>
+{slots}</table>
+<h2>Jobs</h2>
+<table>
+<tr><th>JOB</th><th>DISK</th><th>COMPLETED</th><th>FAILED</th></tr>
+{jobs}</table>
+</body>
+</html>
+",
+        refresh = REFRESH_SECONDS,
+        version = escape(&dashboard.version),
+        device_type = escape(&dashboard.compute_device_type),
+        devices = escape(&dashboard.devices.join(", ")),
+        queued = dashboard.queued,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get(address: &str, request: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn requires_bearer_tokens() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            serve(listener, Some("secret"), || Dashboard {
+                version: String::from("4.2.0"),
+                compute_device_type: String::from("NONE"),
+                devices: Vec::new(),
+                slots: Vec::new(),
+                queued: 0,
+                jobs: Vec::new(),
+            })
+        });
+
+        let status = |request: &str| get(&address, request).lines().next().unwrap().to_string();
+
+        assert_eq!(
+            status("GET /status HTTP/1.1\r\nAuthorization: Bearer secret\r\n\r\n"),
+            "HTTP/1.1 200 OK"
+        );
+        assert_eq!(
+            status("GET /status HTTP/1.1\r\nAuthorization: Bearer wrong\r\n\r\n"),
+            "HTTP/1.1 401 Unauthorized"
+        );
+        assert_eq!(
+            status("GET /status?token=secret HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 401 Unauthorized"
+        );
+    }
+}
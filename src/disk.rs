@@ -0,0 +1,44 @@
+use std::{fs::read_dir, path::Path, process};
+
+pub fn used(path: &Path) -> u64 {
+    let entries = match read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => {
+            return 0;
+        }
+    };
+
+    entries
+        .flatten()
+        .map(|entry| match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => used(&entry.path()),
+            Ok(file_type) if file_type.is_file() => {
+                entry.metadata().map(|metadata| metadata.len()).unwrap_or(0)
+            }
+            _ => 0,
+        })
+        .sum()
+}
+
+pub fn available(path: &Path) -> Option<u64> {
+    let output = process::Command::new("df")
+        .arg("-Pk")
+        .arg(path)
+        .output()
+        .ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let kilobytes: u64 = String::from_utf8(output.stdout)
+        .ok()?
+        .lines()
+        .nth(1)?
+        .split_whitespace()
+        .nth(3)?
+        .parse()
+        .ok()?;
+
+    Some(kilobytes * 1024)
+}
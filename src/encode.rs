@@ -0,0 +1,96 @@
+use crate::report::FrameTime;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{remove_file, write},
+    path::{Path, PathBuf},
+    process,
+};
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum VideoCodec {
+    Mp4,
+    Prores,
+}
+
+impl VideoCodec {
+    pub fn extension(self) -> &'static str {
+        match self {
+            VideoCodec::Mp4 => "mp4",
+            VideoCodec::Prores => "mov",
+        }
+    }
+
+    fn args(self) -> &'static [&'static str] {
+        match self {
+            VideoCodec::Mp4 => &["-c:v", "libx264", "-pix_fmt", "yuv420p", "-crf", "18"],
+            VideoCodec::Prores => &[
+                "-c:v",
+                "prores_ks",
+                "-profile:v",
+                "3",
+                "-pix_fmt",
+                "yuv422p10le",
+            ],
+        }
+    }
+}
+
+fn concat_path(path: &Path) -> String {
+    format!("'{}'", path.to_string_lossy().replace('\'', "'\\''"))
+}
+
+/// Stitches the frames in `times` into a video at `output` with ffmpeg, in frame order. The frames
+/// are passed as a concat list, so versioned file names and gaps in the sequence are fine.
+pub fn encode(
+    times: &[FrameTime],
+    output: &Path,
+    framerate: f64,
+    codec: VideoCodec,
+) -> Result<(), String> {
+    let last = match times.last() {
+        Some(last) => last,
+        None => {
+            return Err(String::from("No frames were rendered"));
+        }
+    };
+
+    let mut list = String::from("ffconcat version 1.0\n");
+    for time in times {
+        list += &format!(
+            "file {}\nduration {}\n",
+            concat_path(&time.path),
+            1.0 / framerate
+        );
+    }
+    list += &format!("file {}\n", concat_path(&last.path));
+
+    let list_path = PathBuf::from(format!("{}.ffconcat", output.display()));
+    write(&list_path, list).map_err(|error| error.to_string())?;
+
+    let status = process::Command::new("ffmpeg")
+        .args([
+            "-y",
+            "-loglevel",
+            "error",
+            "-f",
+            "concat",
+            "-safe",
+            "0",
+            "-i",
+        ])
+        .arg(&list_path)
+        .args(["-r", &framerate.to_string()])
+        .args(codec.args())
+        .arg(output)
+        .status();
+    let _ = remove_file(&list_path);
+
+    let status = status.map_err(|error| format!("Could not run ffmpeg: {}", error))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(format!("ffmpeg exited with {}", status))
+    }
+}
@@ -0,0 +1,28 @@
+use std::{fs::read_to_string, process, time::Duration};
+
+pub fn load_average() -> Option<f64> {
+    read_to_string("/proc/loadavg")
+        .ok()?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+pub fn input_idle() -> Option<Duration> {
+    let output = process::Command::new("xprintidle").output().ok()?;
+
+    if !output.status.success() {
+        return None;
+    }
+
+    let milliseconds = String::from_utf8(output.stdout).ok()?.trim().parse().ok()?;
+    Some(Duration::from_millis(milliseconds))
+}
+
+pub fn signal_process(pid: u32, signal: &str) {
+    let _ = process::Command::new("kill")
+        .arg(format!("-{}", signal))
+        .arg(pid.to_string())
+        .status();
+}
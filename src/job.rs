@@ -0,0 +1,266 @@
+use crate::{Client, ClientOptions, OverwritePolicy, RenderSettings, config};
+use std::{
+    fmt,
+    fs::create_dir_all,
+    future::Future,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+    thread::{self, JoinHandle},
+};
+
+type FrameCallback<'a> = Box<dyn Fn(usize, &Path) + Send + Sync + 'a>;
+
+/// Builder for rendering an uploaded job, e.g.
+/// `RenderJob::new("shot").frames(1..=250).servers("@farm").run()`.
+pub struct RenderJob<'a> {
+    id: String,
+    frames: Vec<usize>,
+    servers: String,
+    output_dir: PathBuf,
+    settings: RenderSettings,
+    overwrite: OverwritePolicy,
+    options: ClientOptions,
+    on_frame: FrameCallback<'a>,
+}
+
+#[derive(Debug)]
+pub enum RenderError {
+    /// The servers could not be resolved or the output directory not be created.
+    Setup(String),
+    /// These frames could not be rendered.
+    Frames(Vec<usize>),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RenderError::Setup(message) => write!(f, "{}", message),
+            RenderError::Frames(frames) => {
+                let frames: Vec<String> = frames.iter().map(usize::to_string).collect();
+                write!(f, "Frames {} failed", frames.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for RenderError {}
+
+impl<'a> RenderJob<'a> {
+    pub fn new(id: &str) -> RenderJob<'a> {
+        RenderJob {
+            id: String::from(id),
+            frames: Vec::new(),
+            servers: String::new(),
+            output_dir: PathBuf::from("."),
+            settings: RenderSettings::default(),
+            overwrite: OverwritePolicy::default(),
+            options: ClientOptions::default(),
+            on_frame: Box::new(|_, _| {}),
+        }
+    }
+
+    /// Adds frames to render, e.g. `1..=250` or `[1, 5, 9]`.
+    pub fn frames(mut self, frames: impl IntoIterator<Item = usize>) -> Self {
+        self.frames.extend(frames);
+        self
+    }
+
+    /// Adds servers, as comma-separated addresses or `@pool` names from the config.
+    pub fn servers(mut self, servers: &str) -> Self {
+        if !self.servers.is_empty() {
+            self.servers.push(',');
+        }
+        self.servers.push_str(servers);
+        self
+    }
+
+    pub fn output_dir(mut self, output_dir: impl Into<PathBuf>) -> Self {
+        self.output_dir = output_dir.into();
+        self
+    }
+
+    /// Overrides render settings of the .blend file, like samples or resolution.
+    pub fn overrides(mut self, settings: RenderSettings) -> Self {
+        self.settings = settings;
+        self
+    }
+
+    pub fn overwrite(mut self, overwrite: OverwritePolicy) -> Self {
+        self.overwrite = overwrite;
+        self
+    }
+
+    pub fn options(mut self, options: ClientOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Calls `on_frame` with each saved frame and its path.
+    pub fn on_frame(mut self, on_frame: impl Fn(usize, &Path) + Send + Sync + 'a) -> Self {
+        self.on_frame = Box::new(on_frame);
+        self
+    }
+
+    /// Renders all frames, blocking until they are saved or failed.
+    pub fn run(self) -> Result<(), RenderError> {
+        let servers = config::parse_ips(&self.servers).map_err(RenderError::Setup)?;
+        if servers.is_empty() {
+            return Err(RenderError::Setup(String::from("No servers given")));
+        }
+        create_dir_all(&self.output_dir).map_err(|error| RenderError::Setup(error.to_string()))?;
+
+        Client::with_options(&servers, self.options)
+            .render(
+                &self.id,
+                &self.output_dir,
+                &self.frames,
+                &self.settings,
+                self.overwrite,
+                &*self.on_frame,
+            )
+            .map_err(RenderError::Frames)
+    }
+}
+
+impl RenderJob<'static> {
+    /// Renders on a background thread. The returned task can be awaited or waited on.
+    pub fn spawn(self) -> RenderTask {
+        RenderTask::spawn(move || self.run())
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    result: Option<Result<(), RenderError>>,
+    waker: Option<Waker>,
+}
+
+/// A render started with `RenderJob::spawn`.
+pub struct RenderTask {
+    shared: Arc<Mutex<Shared>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl RenderTask {
+    /// Runs `render` on a new thread, finishing with an error instead of hanging if it panics.
+    fn spawn(render: impl FnOnce() -> Result<(), RenderError> + Send + 'static) -> RenderTask {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(render)).unwrap_or_else(|_| {
+                    Err(RenderError::Setup(String::from("Render thread panicked")))
+                });
+
+                let mut shared = shared.lock().unwrap();
+                shared.result = Some(result);
+                if let Some(waker) = shared.waker.take() {
+                    waker.wake();
+                }
+            })
+        };
+
+        RenderTask {
+            shared,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.shared.lock().unwrap().result.is_some()
+    }
+
+    /// Blocks until the render finished.
+    pub fn wait(mut self) -> Result<(), RenderError> {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+
+        self.shared.lock().unwrap().result.take().unwrap()
+    }
+}
+
+impl Future for RenderTask {
+    type Output = Result<(), RenderError>;
+
+    fn poll(self: Pin<&mut Self>, context: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(context.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::mpsc, task::Wake, thread::Thread, time::Duration};
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut context = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+
+        loop {
+            match future.as_mut().poll(&mut context) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn waits_for_spawned_renders() {
+        let rendered = RenderJob::new("shot").frames(1..=2).spawn().wait();
+
+        assert!(
+            matches!(rendered, Err(RenderError::Setup(message)) if message == "No servers given")
+        );
+    }
+
+    #[test]
+    fn awaits_spawned_renders() {
+        let (release, released) = mpsc::channel();
+        let task = RenderTask::spawn(move || {
+            released.recv().unwrap();
+            Err(RenderError::Frames(vec![3]))
+        });
+        assert!(!task.is_finished());
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            release.send(()).unwrap();
+        });
+
+        assert!(matches!(block_on(task), Err(RenderError::Frames(frames)) if frames == [3]));
+    }
+
+    #[test]
+    fn reports_panicking_renders() {
+        let failed = |result: Result<(), RenderError>| {
+            result.is_err_and(|error| error.to_string() == "Render thread panicked")
+        };
+
+        assert!(failed(RenderTask::spawn(|| panic!("render failed")).wait()));
+
+        let task = RenderTask::spawn(|| panic!("render failed"));
+        assert!(failed(block_on(task)));
+    }
+}
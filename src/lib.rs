@@ -0,0 +1,4519 @@
+pub mod bench;
+mod bundle;
+pub mod config;
+mod dashboard;
+mod disk;
+mod encode;
+mod idle;
+mod job;
+pub mod logging;
+pub mod manifest;
+mod mdns;
+pub mod output;
+pub mod paths;
+mod pool;
+mod profile;
+pub mod protocol;
+mod report;
+pub mod server;
+pub mod spool;
+pub mod still;
+mod storage;
+pub mod stress;
+mod transfer;
+mod upnp;
+
+use bundle::Bundle;
+use clap::{Args, Subcommand, ValueEnum};
+pub use encode::VideoCodec;
+pub use job::{RenderError, RenderJob, RenderTask};
+use logging::{Event, emit, error, log, warn};
+use output::{Color, paint};
+use report::FrameTime;
+pub use report::ReportFormat;
+use serde::{Deserialize, Serialize};
+use std::{
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
+    fmt,
+    fs::{File, create_dir_all, read, read_dir, remove_file, rename, write},
+    io::{ErrorKind, Read, Write, stdin},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Condvar, Mutex, Once},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub const EPHEMERAL_TTL: u64 = 24 * 60 * 60;
+const FRAME_RETRIES: usize = 3;
+const RECONNECT_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_CLOCK_SKEW: u64 = 30;
+const DISCOVERY_TIMEOUT: Duration = Duration::from_secs(3);
+const HEARTBEAT_TIMEOUT: u64 = 60;
+const SERVER_SHUTDOWN: &str = "server is shutting down";
+const SAVE_FAILED: &str = "could not save frames to the output directory";
+const BATCH_SECONDS: f64 = 20.0;
+const MAX_BATCH_SIZE: usize = 32;
+pub const CONNECT_ATTEMPTS: u32 = 3;
+pub const CONNECT_TIMEOUT: u64 = 10;
+const CONNECT_BACKOFF: Duration = Duration::from_millis(500);
+const PACK_SCRIPT: &str = "import bpy, sys
+bpy.ops.file.pack_libraries()
+bpy.ops.file.pack_all()
+bpy.ops.wm.save_as_mainfile(filepath=sys.argv[-1], copy=True)
+";
+
+/// Client for a set of render servers, given as a comma-separated list of addresses.
+pub struct Client {
+    ips: String,
+    options: Arc<ClientOptions>,
+}
+
+/// How `Client::upload` distributes a .blend file.
+#[derive(Clone, Default)]
+pub struct UploadOptions {
+    /// Seconds of disuse after which servers delete the upload.
+    pub ttl: Option<u64>,
+    pub max_concurrent_uploads: Option<usize>,
+    /// Skips servers that already store an identical file.
+    pub skip_present: bool,
+    /// Uploads to one server and lets the others copy from it.
+    pub seed: bool,
+    pub blender: Option<BlenderRequirement>,
+}
+
+impl Client {
+    pub fn new(ips: &str) -> Client {
+        Client::with_options(ips, ClientOptions::default())
+    }
+
+    pub fn with_options(ips: &str, options: ClientOptions) -> Client {
+        Client {
+            ips: String::from(ips),
+            options: Arc::new(options),
+        }
+    }
+
+    fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        with_options(self.options.clone(), f)
+    }
+
+    /// Uploads a .blend file to every server under `id`. Fails naming the servers that did not
+    /// receive it.
+    pub fn upload(&self, id: &str, blend: &Path, options: &UploadOptions) -> Result<(), String> {
+        self.run(|| {
+            if options.seed {
+                seed_blend(&self.ips, id, blend, options.ttl, options.blender.as_ref())
+            } else {
+                upload_blend(
+                    &self.ips,
+                    String::from(id),
+                    blend,
+                    options.max_concurrent_uploads,
+                    options.ttl,
+                    options.skip_present,
+                    options.blender.as_ref(),
+                )
+            }
+        })
+    }
+
+    /// Renders `frames` of an uploaded job into `output_dir`, calling `on_frame` with each saved
+    /// frame and its path. Fails with the frames that could not be rendered.
+    pub fn render(
+        &self,
+        id: &str,
+        output_dir: &Path,
+        frames: &[usize],
+        settings: &RenderSettings,
+        overwrite: OverwritePolicy,
+        on_frame: &(dyn Fn(usize, &Path) + Sync),
+    ) -> Result<(), Vec<usize>> {
+        let mut frames = frames.to_vec();
+        frames.sort();
+        frames.dedup();
+        frames.reverse();
+
+        let failed = self.run(|| {
+            render_frames(
+                &self.ips,
+                output_dir,
+                id,
+                settings,
+                overwrite,
+                &Mutex::new(frames),
+                on_frame,
+            )
+        });
+
+        if failed.is_empty() {
+            Ok(())
+        } else {
+            Err(failed)
+        }
+    }
+
+    /// Queries every server for its Blender version, compute devices and storage usage.
+    pub fn query(&self, timeout: Duration) -> Vec<(String, Result<QueryResponse, std::io::Error>)> {
+        self.run(|| query_all(&self.ips, timeout))
+    }
+
+    /// Lists the jobs every server stores for this client's user.
+    pub fn list(&self) -> Vec<(String, Result<Vec<StoredJob>, String>)> {
+        self.run(|| list_all(&self.ips))
+    }
+
+    /// Deletes the stored job `id` from every server. Fails naming the servers that refused or
+    /// could not be reached.
+    pub fn delete(&self, id: &str) -> Result<(), String> {
+        let errors: Vec<String> = self.run(|| {
+            self.ips
+                .split_terminator(',')
+                .filter_map(|ip| {
+                    delete(ip, id)
+                        .err()
+                        .map(|message| format!("{}: {}", ip, message))
+                })
+                .collect()
+        });
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors.join("\n"))
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum AdminAction {
+    Dump,
+    Slots,
+    Disconnect {
+        slot: usize,
+
+        #[arg(long)]
+        ban: Option<u64>,
+    },
+    Unban {
+        address: IpAddr,
+    },
+}
+
+/// Message a client sends to a server, framed with `to_header`.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Request {
+    Upload {
+        id: String,
+        size: usize,
+
+        #[serde(default)]
+        ttl: Option<u64>,
+
+        #[serde(default)]
+        hash: Option<String>,
+
+        #[serde(default)]
+        blender: Option<BlenderRequirement>,
+
+        #[serde(default)]
+        compressed: bool,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    Render {
+        #[serde(default)]
+        id: Option<String>,
+
+        #[serde(default)]
+        prefetch: bool,
+
+        #[serde(default)]
+        addons: Vec<String>,
+
+        #[serde(default)]
+        heartbeat: bool,
+
+        #[serde(default)]
+        blender: Option<BlenderRequirement>,
+
+        #[serde(default)]
+        compression: Option<i32>,
+
+        #[serde(default)]
+        priority: Option<u8>,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    Reconnect {
+        session: String,
+    },
+    Seed {
+        id: String,
+        source: String,
+        size: usize,
+        checksum: String,
+
+        #[serde(default)]
+        ttl: Option<u64>,
+
+        #[serde(default)]
+        blender: Option<BlenderRequirement>,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    Fetch {
+        id: String,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    UploadInput {
+        id: String,
+        pass: String,
+        name: String,
+        size: usize,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    BakeTextures {
+        id: String,
+        objects: Vec<String>,
+        maps: Vec<BakeMap>,
+        resolution: u32,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    Thumbnail {
+        id: String,
+        frame: usize,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    FetchFrame {
+        id: String,
+        frame: usize,
+
+        #[serde(default)]
+        compressed: bool,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    Status {
+        id: String,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    Fingerprint {
+        id: String,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    FrameRate {
+        id: String,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    NullRender {
+        size: usize,
+    },
+    CancelFrame {
+        id: String,
+        frame: Option<usize>,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    Cancel {
+        id: String,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    Delete {
+        id: String,
+
+        #[serde(default)]
+        user: Option<String>,
+    },
+    List {
+        #[serde(default)]
+        user: Option<String>,
+    },
+    Query {
+        #[serde(default)]
+        user: Option<String>,
+    },
+    Auth {
+        token: String,
+    },
+    Admin {
+        token: Option<String>,
+        request: AdminRequest,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AdminRequest {
+    Dump,
+    Slots,
+    Disconnect { slot: usize, ban: Option<u64> },
+    Unban { address: IpAddr },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum AdminResponse {
+    Okay,
+    Slots { slots: Vec<SlotInfo> },
+    Fail { message: String },
+}
+
+#[derive(Serialize, Deserialize)]
+struct SlotInfo {
+    slot: usize,
+    address: String,
+    id: Option<String>,
+    frame: Option<usize>,
+    state: SlotState,
+    seconds_in_state: f64,
+    bytes_pending: usize,
+
+    #[serde(default)]
+    priority: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "snake_case")]
+enum SlotState {
+    Queued,
+    AwaitingFrameRequest,
+    Rendering,
+    Sending,
+}
+
+impl SlotState {
+    fn label(self) -> &'static str {
+        match self {
+            SlotState::Queued => "queued",
+            SlotState::AwaitingFrameRequest => "awaiting frame request",
+            SlotState::Rendering => "rendering",
+            SlotState::Sending => "sending",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct FrameRequest {
+    id: String,
+    frame: usize,
+
+    #[serde(default)]
+    settings: RenderSettings,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FrameRangeRequest {
+    id: String,
+    start: usize,
+    end: usize,
+
+    #[serde(default)]
+    settings: RenderSettings,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum FrameRequests {
+    Single(FrameRequest),
+    Range(FrameRangeRequest),
+}
+
+#[derive(Args, Serialize, Deserialize, Clone, Default)]
+pub struct RenderSettings {
+    #[arg(long)]
+    #[serde(default)]
+    pub persistent_data: bool,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub static_bvh: bool,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub viewport: bool,
+
+    #[arg(long, value_enum, default_value_t)]
+    #[serde(default)]
+    pub fallback_format: ImageFormat,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub prefetch: bool,
+
+    #[arg(long, value_parser = parse_pin)]
+    #[serde(default)]
+    pub pin: Vec<(usize, String)>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub deterministic: bool,
+
+    #[arg(long, value_delimiter = ',')]
+    #[serde(default)]
+    pub addons: Vec<String>,
+
+    #[arg(long, value_enum)]
+    #[serde(default)]
+    pub report: Option<ReportFormat>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub max_downloads: Option<usize>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub retries: Option<usize>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub retention: Option<u64>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub chunk_size: Option<usize>,
+
+    #[arg(skip)]
+    #[serde(default)]
+    pub region: Option<Region>,
+
+    #[arg(long)]
+    #[serde(skip)]
+    pub session: Option<PathBuf>,
+
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..))]
+    #[serde(default)]
+    pub samples: Option<u32>,
+
+    #[arg(long, value_parser = parse_resolution)]
+    #[serde(default)]
+    pub resolution: Option<(u32, u32)>,
+
+    #[arg(long, value_parser = clap::value_parser!(u16).range(1..=32767))]
+    #[serde(default)]
+    pub percentage: Option<u16>,
+
+    #[arg(long, value_enum)]
+    #[serde(default)]
+    pub engine: Option<RenderEngine>,
+
+    #[arg(long, value_enum)]
+    #[serde(default)]
+    pub output_format: Option<ImageFormat>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub bundle: bool,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub batch: bool,
+
+    #[arg(long, value_enum)]
+    #[serde(default)]
+    pub encode: Option<VideoCodec>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub heartbeat_timeout: Option<u64>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub placeholder: bool,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub blender_version: Option<BlenderRequirement>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub passes: bool,
+
+    #[arg(long, value_parser = clap::value_parser!(u8).range(0..=9), default_value_t)]
+    #[serde(default)]
+    pub priority: u8,
+
+    #[arg(long, value_parser = parse_name_pattern)]
+    #[serde(default)]
+    pub name_pattern: Option<String>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub scene: Option<String>,
+
+    #[arg(long)]
+    #[serde(default)]
+    pub view_layer: Option<String>,
+}
+
+/// Which of a server's Blender installations a job needs: one registered under a name, a version
+/// like `4.2` (matching 4.2.x) or a minimum version like `>=4.1`.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BlenderRequirement {
+    Name { name: String },
+    Version { version: Vec<u8> },
+    Minimum { version: Vec<u8> },
+}
+
+impl BlenderRequirement {
+    fn matches(&self, installation: &BlenderInstallation) -> bool {
+        match self {
+            BlenderRequirement::Name { name } => *name == installation.name,
+            BlenderRequirement::Version { version } => installation.version.starts_with(version),
+            BlenderRequirement::Minimum { version } => installation.version[..] >= version[..],
+        }
+    }
+}
+
+impl FromStr for BlenderRequirement {
+    type Err = String;
+
+    fn from_str(requirement: &str) -> Result<BlenderRequirement, String> {
+        let parse = |version: &str| -> Result<Vec<u8>, String> {
+            version
+                .split('.')
+                .map(|part| part.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format!("Invalid Blender version \"{}\"", version))
+        };
+
+        if let Some(version) = requirement.strip_prefix(">=") {
+            Ok(BlenderRequirement::Minimum {
+                version: parse(version)?,
+            })
+        } else if requirement.starts_with(|first: char| first.is_ascii_digit()) {
+            Ok(BlenderRequirement::Version {
+                version: parse(requirement)?,
+            })
+        } else if requirement.is_empty() {
+            Err(String::from("Expected a Blender name or version"))
+        } else {
+            Ok(BlenderRequirement::Name {
+                name: String::from(requirement),
+            })
+        }
+    }
+}
+
+impl fmt::Display for BlenderRequirement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = |version: &[u8]| {
+            version
+                .iter()
+                .map(u8::to_string)
+                .collect::<Vec<_>>()
+                .join(".")
+        };
+
+        match self {
+            BlenderRequirement::Name { name } => write!(f, "{}", name),
+            BlenderRequirement::Version { version: required } => {
+                write!(f, "{}", version(required))
+            }
+            BlenderRequirement::Minimum { version: required } => {
+                write!(f, ">={}", version(required))
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct BlenderInstallation {
+    pub name: String,
+    pub version: [u8; 3],
+}
+
+impl fmt::Display for BlenderInstallation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} ({}.{}.{})",
+            self.name, self.version[0], self.version[1], self.version[2]
+        )
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Region {
+    pub min_x: f64,
+    pub min_y: f64,
+    pub max_x: f64,
+    pub max_y: f64,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum OverwritePolicy {
+    #[default]
+    Overwrite,
+    Skip,
+    Version,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum ImageFormat {
+    #[default]
+    Png,
+    #[value(alias = "exr")]
+    OpenExr,
+    Jpeg,
+    Tiff,
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderEngine {
+    Cycles,
+    Eevee,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FormatOverride {
+    from: String,
+    to: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Response {
+    Okay,
+    Fail { message: String },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum UploadResponse {
+    Present,
+    Send {
+        #[serde(default)]
+        compressed: bool,
+    },
+    Reject {
+        message: String,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+enum RenderAcceptResponse {
+    Accept {
+        #[serde(default)]
+        session: Option<String>,
+
+        #[serde(default)]
+        batch: bool,
+
+        #[serde(default)]
+        heartbeat: Option<u64>,
+    },
+    Reject {
+        reason: RejectReason,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Heartbeat {
+    Ping,
+    Pong,
+    Shutdown,
+    Queued { position: usize },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum RejectReason {
+    MissingAssets {
+        libraries: Vec<String>,
+        images: Vec<String>,
+    },
+    MissingAddons {
+        addons: Vec<String>,
+    },
+    Busy,
+    UnknownSession,
+    MissingBlender {
+        required: String,
+        available: Vec<String>,
+    },
+    Failed {
+        message: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct RenderProgress {
+    frame: usize,
+
+    #[serde(default)]
+    percent: Option<f64>,
+
+    #[serde(default)]
+    sample: Option<usize>,
+
+    #[serde(default)]
+    memory: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct RenderStats {
+    seconds: f64,
+
+    #[serde(default)]
+    peak_memory: Option<u64>,
+
+    #[serde(default)]
+    device: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum RenderResponse {
+    Okay {
+        size: usize,
+        extension: String,
+
+        #[serde(default)]
+        format_override: Option<FormatOverride>,
+
+        #[serde(default)]
+        checksum: Option<String>,
+
+        #[serde(default)]
+        compressed: bool,
+
+        #[serde(default)]
+        stats: Option<RenderStats>,
+
+        #[serde(default)]
+        passes: Vec<PassOutput>,
+    },
+    Fail {
+        #[serde(default)]
+        cancelled: bool,
+
+        #[serde(default)]
+        job_cancelled: bool,
+    },
+    Progress(RenderProgress),
+}
+
+/// An additional output of a frame, like a compositor file output or a multilayer EXR, whose data
+/// follows the main image in the order listed.
+#[derive(Serialize, Deserialize)]
+struct PassOutput {
+    name: String,
+    size: usize,
+    extension: String,
+
+    #[serde(default)]
+    checksum: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Fingerprint {
+    brsp_version: String,
+    blender_version: [u8; 3],
+    blender_build_hash: String,
+    addons: Vec<String>,
+    gpu_driver: Option<String>,
+    blend_hash: String,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ListResponse {
+    Okay { jobs: Vec<StoredJob> },
+    Fail { message: String },
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct StoredJob {
+    pub id: String,
+    pub bytes: u64,
+    pub last_used: u64,
+
+    #[serde(default)]
+    pub expires: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Default, Clone, Copy)]
+struct StatusResponse {
+    pending: usize,
+    active: usize,
+    completed: usize,
+    failed: usize,
+    started_ago: Option<u64>,
+    updated_ago: Option<u64>,
+
+    #[serde(default)]
+    cache_size: u64,
+
+    #[serde(default)]
+    cache_original: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum FingerprintResponse {
+    Okay { fingerprint: Fingerprint },
+    Fail { message: String },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum FrameRateResponse {
+    Okay { framerate: f64 },
+    Fail { message: String },
+}
+
+#[derive(Serialize)]
+struct Sidecar<'a> {
+    id: &'a str,
+    brsp_version: &'a str,
+    settings: &'a RenderSettings,
+    servers: HashMap<&'a str, Fingerprint>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum ThumbnailResponse {
+    Okay { size: usize },
+    Fail { message: String },
+}
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BakeMap {
+    Ao,
+    Normal,
+    Combined,
+}
+
+impl fmt::Display for BakeMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BakeMap::Ao => write!(f, "ao"),
+            BakeMap::Normal => write!(f, "normal"),
+            BakeMap::Combined => write!(f, "combined"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum BakeResponse {
+    Okay { images: Vec<BakedImage> },
+    Fail { message: String },
+}
+
+#[derive(Serialize, Deserialize)]
+struct BakedImage {
+    object: String,
+    map: BakeMap,
+    size: usize,
+    extension: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct QueryResponse {
+    pub version: [u8; 3],
+    pub compute_device_type: String,
+    pub devices: ComputeDeviceList,
+
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+
+    #[serde(default)]
+    pub roots: Vec<RootUsage>,
+
+    #[serde(default)]
+    pub blends: Vec<StoredBlend>,
+
+    #[serde(default)]
+    pub time: Option<u64>,
+
+    #[serde(default)]
+    pub installations: Vec<BlenderInstallation>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct StoredBlend {
+    pub id: String,
+    pub hash: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RootUsage {
+    pub role: String,
+    pub path: String,
+    pub used: u64,
+    pub available: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ComputeDeviceList {
+    pub active: Vec<String>,
+    pub inactive: Vec<String>,
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum BrpyRequest {
+    Render {
+        blend: PathBuf,
+        frame: usize,
+        output: PathBuf,
+        inputs: PathBuf,
+        thumbnail: PathBuf,
+        settings: Box<RenderSettings>,
+    },
+    Bake {
+        blend: PathBuf,
+        objects: Vec<String>,
+        maps: Vec<BakeMap>,
+        resolution: u32,
+        output: PathBuf,
+    },
+    CheckAssets {
+        blend: PathBuf,
+        addons: Vec<String>,
+    },
+    FrameRate {
+        blend: PathBuf,
+    },
+    Query,
+    Environment,
+    Cancel,
+}
+
+#[derive(Deserialize)]
+struct BrpyFrameRate {
+    fps: u32,
+    fps_base: f64,
+}
+
+#[derive(Deserialize)]
+struct BrpyEnvironment {
+    build_hash: String,
+    addons: Vec<String>,
+    gpu_driver: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BrpyAssetReport {
+    libraries: Vec<String>,
+    images: Vec<String>,
+
+    #[serde(default)]
+    addons: Vec<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum BrpyRenderResponse {
+    Okay {
+        image: PathBuf,
+
+        #[serde(default)]
+        format_override: Option<FormatOverride>,
+
+        #[serde(default)]
+        passes: Vec<BrpyPassOutput>,
+    },
+    Fail,
+    Cancelled,
+    Progress(RenderProgress),
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum BrpyBakeResponse {
+    Okay { images: Vec<BrpyBakedImage> },
+    Fail { message: String },
+}
+
+#[derive(Deserialize)]
+struct BrpyPassOutput {
+    name: String,
+    image: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct BrpyBakedImage {
+    object: String,
+    map: BakeMap,
+    image: PathBuf,
+}
+
+fn parse_pin(pin: &str) -> Result<(usize, String), String> {
+    match pin.split_once('=') {
+        Some((frame, host)) if !host.is_empty() => match frame.parse() {
+            Ok(frame) => Ok((frame, String::from(host))),
+            Err(_) => Err(format!("Invalid frame number \"{}\"", frame)),
+        },
+        _ => Err(String::from("Expected frame=host")),
+    }
+}
+
+fn parse_resolution(resolution: &str) -> Result<(u32, u32), String> {
+    let (width, height) = resolution
+        .split_once('x')
+        .ok_or_else(|| String::from("Expected WIDTHxHEIGHT"))?;
+
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("Invalid resolution \"{}\"", resolution)),
+    }
+}
+
+/// Continues a render recorded with `--session`, skipping frames whose outputs already exist.
+/// Returns false if the session file cannot be read or frames failed.
+pub fn resume(path: &Path) -> bool {
+    let session = match RenderSession::load(path) {
+        Some(session) => session,
+        None => {
+            error!("Could not read session {}", path.display());
+            return false;
+        }
+    };
+
+    let remaining: Vec<usize> = session
+        .frames
+        .iter()
+        .copied()
+        .filter(|frame| !session.completed.contains(frame))
+        .filter(|frame| {
+            existing_frame(
+                &session.output_dir,
+                session.settings.name_pattern.as_deref(),
+                *frame,
+            )
+            .is_none()
+        })
+        .collect();
+
+    log!(
+        "Resuming \"{}\": {} of {} frames left",
+        session.id,
+        remaining.len(),
+        session.frames.len()
+    );
+
+    let settings = RenderSettings {
+        session: Some(path.to_path_buf()),
+        ..session.settings
+    };
+
+    if let Err(error) = create_dir_all(&session.output_dir) {
+        error!(
+            "Could not create {}\nReason: {}",
+            session.output_dir.display(),
+            error
+        );
+        return false;
+    }
+
+    render_frames(
+        &session.ips,
+        &session.output_dir,
+        &session.id,
+        &settings,
+        session.overwrite,
+        &Mutex::new(remaining),
+        &|_, _| {},
+    )
+    .is_empty()
+}
+
+/// Parses frames like `1,5..10,20..30/2` into a list in descending order.
+pub fn parse_frames(frames: &str) -> Result<Vec<usize>, String> {
+    let mut list = Vec::new();
+
+    for spec in frames.split_terminator(',') {
+        if let Ok(frame) = spec.parse::<usize>() {
+            list.push(frame);
+            continue;
+        }
+
+        let invalid = || format!("Invalid frames \"{}\"", spec);
+
+        let (range, step) = match spec.split_once('/') {
+            Some((range, step)) => (range, step.parse::<usize>().map_err(|_| invalid())?),
+            None => (spec, 1),
+        };
+        if step == 0 {
+            return Err(format!("Step of \"{}\" must be at least 1", spec));
+        }
+
+        let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+        let start: usize = start.parse().map_err(|_| invalid())?;
+        let end: usize = end.parse().map_err(|_| invalid())?;
+        if start > end {
+            return Err(format!("Range \"{}\" is empty", spec));
+        }
+
+        list.extend((start..=end).step_by(step));
+    }
+
+    list.sort();
+    list.dedup();
+    list.reverse();
+
+    Ok(list)
+}
+
+pub fn upload_blend(
+    ips: &str,
+    id: String,
+    blend: &Path,
+    max_concurrent_uploads: Option<usize>,
+    ttl: Option<u64>,
+    skip_present: bool,
+    blender: Option<&BlenderRequirement>,
+) -> Result<(), String> {
+    let data = read_stdin(blend).map_err(|error| format!("Could not read stdin: {}", error))?;
+    let (size, hash) = blend_checksum(blend, data.as_deref())
+        .map_err(|error| format!("Could not read {}: {}", blend.display(), error))?;
+
+    let free_uploads = Mutex::new(max_concurrent_uploads.map_or(usize::MAX, |max| max.max(1)));
+    let upload_finished = Condvar::new();
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for ip in ips.split_terminator(',') {
+            let (id, hash, data) = (&id, &hash, &data);
+            let (free_uploads, upload_finished, results) =
+                (&free_uploads, &upload_finished, &results);
+            scope.spawn(inherit(move || {
+                if skip_present && has_blend(ip, id, hash) {
+                    log!(
+                        "{}: Already stores \"{}\", skipping upload",
+                        output::server(ip),
+                        id
+                    );
+                    results.lock().unwrap().push((ip, Some(true)));
+                    return;
+                }
+
+                let _permit = UploadPermit::acquire(free_uploads, upload_finished);
+
+                let uploaded = match data {
+                    Some(data) => upload(ip, id, ttl, blender, size, hash, &data[..]),
+                    None => upload_file(ip, id, ttl, blender, size, hash, blend),
+                };
+                results.lock().unwrap().push((ip, Some(uploaded)));
+            }));
+        }
+    });
+
+    upload_summary(ips, &id, results.into_inner().unwrap())
+}
+
+/// Hands an upload slot back when dropped, even if the upload panicked.
+struct UploadPermit<'a> {
+    free: &'a Mutex<usize>,
+    returned: &'a Condvar,
+}
+
+impl<'a> UploadPermit<'a> {
+    /// Waits until fewer than the allowed number of uploads are running.
+    fn acquire(free: &'a Mutex<usize>, returned: &'a Condvar) -> UploadPermit<'a> {
+        *returned
+            .wait_while(free.lock().unwrap(), |free| *free == 0)
+            .unwrap() -= 1;
+
+        UploadPermit { free, returned }
+    }
+}
+
+impl Drop for UploadPermit<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut free) = self.free.lock() {
+            *free += 1;
+        }
+        self.returned.notify_one();
+    }
+}
+
+/// Logs which servers received the .blend file, with `None` for servers that were never tried,
+/// and fails naming the ones that did not.
+fn upload_summary(
+    ips: &str,
+    id: &str,
+    mut results: Vec<(&str, Option<bool>)>,
+) -> Result<(), String> {
+    results.sort_by_key(|(ip, _)| ips.find(ip));
+
+    let rows: Vec<Vec<String>> = results
+        .iter()
+        .map(|(ip, uploaded)| {
+            vec![
+                output::server(ip),
+                match uploaded {
+                    Some(true) => paint("uploaded", Color::Green),
+                    Some(false) => paint("failed", Color::Red),
+                    None => paint("not attempted", Color::Yellow),
+                },
+            ]
+        })
+        .collect();
+    log!(
+        "Upload summary for \"{}\":\n{}",
+        id,
+        output::table(&["SERVER", "RESULT"], &rows)
+    );
+
+    let missing: Vec<&str> = results
+        .iter()
+        .filter(|(_, uploaded)| *uploaded != Some(true))
+        .map(|(ip, _)| *ip)
+        .collect();
+
+    if missing.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("\"{}\" is missing on {}", id, missing.join(", ")))
+    }
+}
+
+/// Packs external data and linked libraries of `blend` into a temporary copy using a local
+/// Blender installation and returns the path of that copy.
+pub fn pack_blend(blend: &Path, blender: &Path) -> Result<PathBuf, String> {
+    if blend == Path::new("-") {
+        return Err(String::from(
+            "Packing is not supported when reading from stdin",
+        ));
+    }
+
+    let packed = std::env::temp_dir().join(format!(
+        "brsp-{}-{}",
+        std::process::id(),
+        blend.file_name().unwrap().to_str().unwrap()
+    ));
+
+    log!("Packing external data of {}", blend.display());
+
+    let output = std::process::Command::new(blender)
+        .arg("--background")
+        .arg("--factory-startup")
+        .arg(blend)
+        .arg("--python-expr")
+        .arg(PACK_SCRIPT)
+        .arg("--")
+        .arg(&packed)
+        .output()
+        .map_err(|error| format!("Could not run {}: {}", blender.display(), error))?;
+
+    if !output.status.success() || !packed.is_file() {
+        let _ = remove_file(&packed);
+        return Err(format!(
+            "Blender exited ({}) without writing a packed file\n{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+
+    Ok(packed)
+}
+
+pub fn seed_blend(
+    ips: &str,
+    id: &str,
+    blend: &Path,
+    ttl: Option<u64>,
+    blender: Option<&BlenderRequirement>,
+) -> Result<(), String> {
+    let data = read_stdin(blend).map_err(|error| format!("Could not read stdin: {}", error))?;
+    let (size, checksum) = blend_checksum(blend, data.as_deref())
+        .map_err(|error| format!("Could not read {}: {}", blend.display(), error))?;
+
+    let mut pending: Vec<&str> = ips.split_terminator(',').rev().collect();
+    let mut sources = Vec::new();
+    let mut results = Vec::new();
+
+    while let Some(ip) = pending.pop() {
+        let uploaded = match &data {
+            Some(data) => upload(ip, id, ttl, blender, size, &checksum, &data[..]),
+            None => upload_file(ip, id, ttl, blender, size, &checksum, blend),
+        };
+
+        results.push((ip, Some(uploaded)));
+        if uploaded {
+            sources.push(ip);
+            break;
+        }
+    }
+
+    while !pending.is_empty() && !sources.is_empty() {
+        let round: Vec<(&str, &str)> = sources
+            .iter()
+            .filter_map(|source| pending.pop().map(|target| (*source, target)))
+            .collect();
+
+        let seeded: Vec<&str> = thread::scope(|scope| {
+            let handles: Vec<_> = round
+                .iter()
+                .map(|(source, target)| {
+                    let checksum = &checksum;
+                    scope.spawn(inherit(move || {
+                        seed(source, target, id, size, checksum, ttl, blender)
+                    }))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .zip(&round)
+                .filter_map(|(handle, (_, target))| {
+                    let seeded = handle.join().unwrap();
+                    results.push((*target, Some(seeded)));
+                    seeded.then_some(*target)
+                })
+                .collect()
+        });
+
+        sources.extend(seeded);
+    }
+
+    results.extend(pending.into_iter().map(|ip| (ip, None)));
+    upload_summary(ips, id, results)
+}
+
+fn seed(
+    source: &str,
+    target: &str,
+    id: &str,
+    size: usize,
+    checksum: &str,
+    ttl: Option<u64>,
+    blender: Option<&BlenderRequirement>,
+) -> bool {
+    emit(Event::UploadStarted {
+        server: target,
+        id,
+        bytes: size,
+    });
+
+    let request = to_header(
+        serde_json::to_vec(&Request::Seed {
+            id: String::from(id),
+            source: String::from(source),
+            size,
+            checksum: String::from(checksum),
+            ttl,
+            blender: blender.cloned(),
+            user: user(),
+        })
+        .unwrap(),
+    );
+
+    let start = Instant::now();
+    let response = pool::try_checkout(target, None).and_then(|mut server| {
+        server.write_all(&request)?;
+        let header = read_header(&mut server)?;
+        pool::checkin(target, server);
+        Ok(serde_json::from_slice(&header)?)
+    });
+
+    let duration = start.elapsed();
+    let response = response.unwrap_or_else(|error: std::io::Error| Response::Fail {
+        message: error.to_string(),
+    });
+
+    match response {
+        Response::Okay => {
+            log!(
+                "{}: File seeded from {} in {:.1}s ({})",
+                output::server(target),
+                output::server(source),
+                duration.as_secs_f64(),
+                format_speed(size, duration)
+            );
+            emit(Event::UploadCompleted {
+                server: target,
+                id,
+                bytes: size,
+                duration: duration.as_secs_f64(),
+            });
+            true
+        }
+        Response::Fail { message } => {
+            error!(
+                "{}: {}\nReason: {}",
+                output::server(target),
+                paint("File seeding failed", Color::Red),
+                message
+            );
+            emit(Event::UploadFailed {
+                server: target,
+                id,
+                reason: &message,
+            });
+            false
+        }
+    }
+}
+
+fn read_stdin(blend: &Path) -> Result<Option<Vec<u8>>, std::io::Error> {
+    if blend == Path::new("-") {
+        let mut data = Vec::new();
+        stdin().read_to_end(&mut data)?;
+        Ok(Some(data))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Size and checksum of the .blend file, or of `data` if it was read from stdin.
+fn blend_checksum(blend: &Path, data: Option<&[u8]>) -> Result<(usize, String), std::io::Error> {
+    match data {
+        Some(data) => Ok((data.len(), transfer::checksum(data)?)),
+        None => Ok((
+            blend.metadata()?.len() as usize,
+            transfer::checksum(File::open(blend)?)?,
+        )),
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ChunkState {
+    frames: Vec<usize>,
+    state: ChunkStatus,
+
+    #[serde(default)]
+    unrendered: Vec<usize>,
+}
+
+#[derive(Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum ChunkStatus {
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RenderSession {
+    ips: String,
+    output_dir: PathBuf,
+    id: String,
+    settings: RenderSettings,
+    overwrite: OverwritePolicy,
+    frames: Vec<usize>,
+    completed: Vec<usize>,
+    failures: HashMap<usize, usize>,
+}
+
+impl RenderSession {
+    fn load(path: &Path) -> Option<RenderSession> {
+        serde_json::from_slice(&read(path).ok()?).ok()
+    }
+}
+
+struct RenderState<'a> {
+    ips: Vec<&'a str>,
+    id: &'a str,
+    output_dir: &'a Path,
+    settings: &'a RenderSettings,
+    overwrite: OverwritePolicy,
+    frames: &'a Mutex<Vec<usize>>,
+    chunk: Option<(usize, usize)>,
+    total: usize,
+    completed: Mutex<HashSet<usize>>,
+    rendered: Mutex<HashMap<String, (usize, usize, f64, f64)>>,
+    times: Mutex<Vec<FrameTime>>,
+    downloads: transfer::Scheduler,
+    failures: Mutex<HashMap<usize, usize>>,
+    outstanding: Mutex<usize>,
+    settled: Condvar,
+    failed_hosts: Mutex<Vec<(String, String)>>,
+    format_warning: Once,
+    on_frame: &'a (dyn Fn(usize, &Path) + Sync),
+    session: Option<&'a Mutex<RenderSession>>,
+    seconds_per_frame: HashMap<&'a str, f64>,
+    claimed: Mutex<HashSet<usize>>,
+}
+
+impl RenderState<'_> {
+    fn assigned(&self, ip: &str, frame: usize) -> bool {
+        let failed_hosts = self.failed_hosts.lock().unwrap();
+        let ips: Vec<&str> = self
+            .ips
+            .iter()
+            .copied()
+            .filter(|ip| !failed_hosts.iter().any(|(host, _)| host == ip))
+            .collect();
+        drop(failed_hosts);
+
+        let pin = self
+            .settings
+            .pin
+            .iter()
+            .find(|(pinned, host)| *pinned == frame && ips.contains(&host.as_str()));
+
+        match pin {
+            Some((_, host)) => host == ip,
+            None => {
+                let index = (frame as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+                !self.settings.deterministic
+                    || ips.is_empty()
+                    || ips[index as usize % ips.len()] == ip
+            }
+        }
+    }
+
+    /// Whether `ip` should leave the last frames to faster servers, which are expected to finish
+    /// all `remaining` frames before `ip` would finish one of them.
+    fn yields(&self, ip: &str, remaining: usize) -> bool {
+        let seconds = match self.seconds_per_frame.get(ip) {
+            Some(seconds) if !self.settings.deterministic => *seconds,
+            _ => {
+                return false;
+            }
+        };
+
+        let failed_hosts = self.failed_hosts.lock().unwrap();
+        let frames: f64 = self
+            .seconds_per_frame
+            .iter()
+            .filter(|(host, faster)| {
+                **faster < seconds && !failed_hosts.iter().any(|(failed, _)| failed == **host)
+            })
+            .map(|(_, faster)| (seconds / faster.max(f64::EPSILON)).floor())
+            .sum();
+
+        frames >= remaining as f64
+    }
+
+    fn takes(&self, ip: &str, frame: usize, remaining: usize) -> bool {
+        let pinned = self
+            .settings
+            .pin
+            .iter()
+            .any(|(pinned, host)| *pinned == frame && host == ip);
+
+        self.assigned(ip, frame) && (pinned || !self.yields(ip, remaining))
+    }
+
+    fn has_frames(&self, ip: &str) -> bool {
+        let frames = self.frames.lock().unwrap();
+        frames
+            .iter()
+            .any(|frame| self.takes(ip, *frame, frames.len()))
+    }
+
+    /// Writes an empty placeholder for `frame` so other clients sharing the output directory
+    /// leave it alone. Returns false if another client already claimed it.
+    fn claim(&self, frame: usize) -> bool {
+        if !self.settings.placeholder || self.claimed.lock().unwrap().contains(&frame) {
+            return true;
+        }
+
+        match File::create_new(placeholder(
+            self.output_dir,
+            self.settings.name_pattern.as_deref(),
+            frame,
+        )) {
+            Ok(_) => {
+                self.claimed.lock().unwrap().insert(frame);
+                true
+            }
+            Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+                log!("Skipping frame {}, claimed by another client", frame);
+                false
+            }
+            Err(error) => {
+                warn!("Writing placeholder for frame {} failed: {}", frame, error);
+                true
+            }
+        }
+    }
+
+    fn next_frame(&self, ip: &str) -> Option<usize> {
+        let mut frames = self.frames.lock().unwrap();
+        let frame = loop {
+            let remaining = frames.len();
+            let index = frames
+                .iter()
+                .rposition(|frame| self.takes(ip, *frame, remaining))?;
+            let frame = frames.remove(index);
+
+            if self.claim(frame) {
+                break frame;
+            }
+        };
+        drop(frames);
+
+        *self.outstanding.lock().unwrap() += 1;
+
+        Some(frame)
+    }
+
+    fn next_frames(&self, ip: &str, count: usize) -> Vec<usize> {
+        let mut frames = self.frames.lock().unwrap();
+        let remaining = frames.len();
+        let mut batch: Vec<usize> = Vec::new();
+
+        while batch.len() < count {
+            let index = match batch.last() {
+                None => frames
+                    .iter()
+                    .rposition(|frame| self.takes(ip, *frame, remaining)),
+                Some(last) => frames
+                    .iter()
+                    .rposition(|frame| *frame == last + 1 && self.assigned(ip, *frame)),
+            };
+
+            match index {
+                Some(index) => {
+                    let frame = frames.remove(index);
+                    if self.claim(frame) {
+                        batch.push(frame);
+                    }
+                }
+                None => break,
+            }
+        }
+        drop(frames);
+
+        *self.outstanding.lock().unwrap() += batch.len();
+
+        batch
+    }
+
+    fn finish(&self) {
+        *self.outstanding.lock().unwrap() -= 1;
+        self.settled.notify_all();
+    }
+
+    fn requeue(&self, frames: impl IntoIterator<Item = usize>) {
+        let mut queue = self.frames.lock().unwrap();
+        let len = queue.len();
+        queue.extend(frames);
+        let requeued = queue.len() - len;
+        drop(queue);
+
+        *self.outstanding.lock().unwrap() -= requeued;
+        self.settled.notify_all();
+    }
+
+    fn wait_for_frames(&self, ip: &str) -> bool {
+        let mut outstanding = self.outstanding.lock().unwrap();
+
+        loop {
+            if self.failed(ip) {
+                return false;
+            }
+
+            if self.has_frames(ip) {
+                return true;
+            }
+
+            if *outstanding == 0 {
+                return false;
+            }
+
+            outstanding = self.settled.wait(outstanding).unwrap();
+        }
+    }
+
+    fn fail_host(&self, ip: &str, reason: String) {
+        self.failed_hosts
+            .lock()
+            .unwrap()
+            .push((String::from(ip), reason));
+
+        drop(self.outstanding.lock().unwrap());
+        self.settled.notify_all();
+    }
+
+    fn save_session(&self, update: impl FnOnce(&mut RenderSession)) {
+        if let (Some(session), Some(path)) = (self.session, &self.settings.session) {
+            let mut session = session.lock().unwrap();
+            update(&mut session);
+
+            if let Err(error) = write_atomic(path, &serde_json::to_vec_pretty(&*session).unwrap()) {
+                error!("Saving session {} failed: {}", path.display(), error);
+            }
+        }
+    }
+
+    fn failed(&self, ip: &str) -> bool {
+        self.failed_hosts
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|(host, _)| host == ip)
+    }
+}
+
+/// Renders the queued `frames` across `ips` and returns the frames that could not be rendered.
+pub fn render_frames(
+    ips: &str,
+    output_dir: &Path,
+    id: &str,
+    settings: &RenderSettings,
+    overwrite: OverwritePolicy,
+    frames: &Mutex<Vec<usize>>,
+    on_frame: &(dyn Fn(usize, &Path) + Sync),
+) -> Vec<usize> {
+    remove_incomplete(output_dir);
+
+    let session = settings.session.as_ref().map(|path| {
+        let mut session = RenderSession {
+            ips: String::from(ips),
+            output_dir: output_dir.to_path_buf(),
+            id: String::from(id),
+            settings: settings.clone(),
+            overwrite,
+            frames: frames.lock().unwrap().clone(),
+            completed: Vec::new(),
+            failures: HashMap::new(),
+        };
+
+        if let Some(previous) = RenderSession::load(path)
+            && previous.id == id
+        {
+            session.completed = previous.completed;
+            session.failures = previous.failures;
+            for frame in previous.frames {
+                if !session.frames.contains(&frame) {
+                    session.frames.push(frame);
+                }
+            }
+        }
+
+        if let Err(error) = write_atomic(path, &serde_json::to_vec_pretty(&session).unwrap()) {
+            error!("Saving session {} failed: {}", path.display(), error);
+        }
+
+        Mutex::new(session)
+    });
+
+    let ips = if settings.viewport {
+        viewport_servers(ips)
+    } else {
+        String::from(ips)
+    };
+    let ips = ips.as_str();
+    if ips.is_empty() {
+        error!("No server supports viewport renders");
+        return frames.lock().unwrap().clone();
+    }
+
+    let profiles = profile::load();
+    let seconds_per_frame: HashMap<&str, f64> = ips
+        .split_terminator(',')
+        .filter_map(|ip| profile::seconds_per_frame(&profiles, ip).map(|seconds| (ip, seconds)))
+        .collect();
+
+    let chunks = settings.chunk_size.map(|size| {
+        let mut all = frames.lock().unwrap().clone();
+        all.sort();
+
+        all.chunks(size.max(1))
+            .map(<[usize]>::to_vec)
+            .collect::<Vec<_>>()
+    });
+
+    if overwrite == OverwritePolicy::Skip {
+        frames.lock().unwrap().retain(|frame| {
+            let exists =
+                existing_frame(output_dir, settings.name_pattern.as_deref(), *frame).is_some();
+            if exists {
+                log!("Skipping frame {}, output already exists", frame);
+            }
+
+            !exists
+        });
+    }
+
+    let job = |frames, chunk| RenderState {
+        ips: ips.split_terminator(',').collect(),
+        id,
+        output_dir,
+        settings,
+        overwrite,
+        frames,
+        chunk,
+        total: frames.lock().unwrap().len(),
+        completed: Mutex::new(HashSet::new()),
+        rendered: Mutex::new(HashMap::new()),
+        times: Mutex::new(Vec::new()),
+        downloads: transfer::Scheduler::new(settings.max_downloads),
+        failures: Mutex::new(
+            session
+                .as_ref()
+                .map(|session| session.lock().unwrap().failures.clone())
+                .unwrap_or_default(),
+        ),
+        outstanding: Mutex::new(0),
+        settled: Condvar::new(),
+        failed_hosts: Mutex::new(Vec::new()),
+        format_warning: Once::new(),
+        on_frame,
+        session: session.as_ref(),
+        seconds_per_frame: seconds_per_frame.clone(),
+        claimed: Mutex::new(HashSet::new()),
+    };
+
+    let queue = Mutex::new(Vec::new());
+    let chunks = match chunks {
+        None => {
+            return render_job(job(frames, None));
+        }
+        Some(chunks) => chunks,
+    };
+
+    let mut failed = Vec::new();
+
+    for (index, chunk) in chunks.iter().enumerate() {
+        let (first, last) = (chunk[0], chunk[chunk.len() - 1]);
+        let state_file = output_dir.join(format!("{}.chunk-{:04}-{:04}.json", id, first, last));
+
+        let done = read(&state_file)
+            .ok()
+            .and_then(|state| serde_json::from_slice::<ChunkState>(&state).ok())
+            .is_some_and(|state| state.state == ChunkStatus::Done);
+
+        let pending: Vec<usize> = {
+            let mut frames = frames.lock().unwrap();
+            let (pending, rest) = frames.iter().partition(|frame| chunk.contains(frame));
+            *frames = rest;
+            pending
+        };
+
+        if done {
+            log!("Skipping frames {}-{}, chunk is already done", first, last);
+            continue;
+        }
+
+        if pending.is_empty() {
+            continue;
+        }
+
+        log!(
+            "Rendering chunk {} of {} (frames {}-{})",
+            index + 1,
+            chunks.len(),
+            first,
+            last
+        );
+
+        let mut state = ChunkState {
+            frames: chunk.clone(),
+            state: ChunkStatus::Running,
+            unrendered: Vec::new(),
+        };
+        save_chunk_state(&state_file, &state);
+
+        *queue.lock().unwrap() = pending;
+        state.unrendered = render_job(job(&queue, Some((first, last))));
+        state.state = if state.unrendered.is_empty() {
+            ChunkStatus::Done
+        } else {
+            ChunkStatus::Failed
+        };
+        save_chunk_state(&state_file, &state);
+        failed.extend(state.unrendered);
+    }
+
+    failed
+}
+
+fn save_chunk_state(state_file: &Path, state: &ChunkState) {
+    if let Err(error) = write_atomic(state_file, &serde_json::to_vec_pretty(state).unwrap()) {
+        error!("Saving {} failed: {}", state_file.display(), error);
+    }
+}
+
+fn render_job(job: RenderState) -> Vec<usize> {
+    let (ips, id, output_dir, settings, frames) = (
+        job.ips.clone(),
+        job.id,
+        job.output_dir,
+        job.settings,
+        job.frames,
+    );
+    let name = match job.chunk {
+        None => String::from(id),
+        Some((first, last)) => format!("{}-{:04}-{:04}", id, first, last),
+    };
+
+    for (frame, host) in &settings.pin {
+        if !job.ips.contains(&host.as_str()) {
+            warn!(
+                "{}",
+                paint(
+                    format!("Ignoring pin of frame {} to unknown server {}", frame, host),
+                    Color::Yellow
+                )
+            );
+        }
+    }
+
+    let mut profiles = profile::load();
+    let remaining = frames.lock().unwrap().len();
+    if let Some(seconds) = profile::estimate(&profiles, &job.ips, remaining) {
+        log!(
+            "Estimated time for {} frames based on server profiles: {:.1} min",
+            remaining,
+            seconds / 60.0
+        );
+    }
+
+    thread::scope(|scope| {
+        for ip in &ips {
+            let job = &job;
+            scope.spawn(inherit(move || {
+                render(ip, job);
+            }));
+        }
+    });
+
+    for frame in job.claimed.lock().unwrap().iter() {
+        let _ = remove_file(placeholder(
+            output_dir,
+            settings.name_pattern.as_deref(),
+            *frame,
+        ));
+    }
+
+    let retries = settings.retries.unwrap_or(FRAME_RETRIES);
+    let completed = job.completed.lock().unwrap();
+    let mut failed: Vec<usize> = job
+        .failures
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|(frame, failures)| **failures > retries && !completed.contains(frame))
+        .map(|(frame, _)| *frame)
+        .collect();
+    drop(completed);
+
+    failed.sort();
+    if !failed.is_empty() {
+        let failed: Vec<String> = failed.iter().map(usize::to_string).collect();
+
+        error!(
+            "{}",
+            paint(
+                format!(
+                    "Gave up on {} frame(s) after {} retries: {}",
+                    failed.len(),
+                    retries,
+                    failed.join(", ")
+                ),
+                Color::Red
+            )
+        );
+    }
+
+    let failed_hosts = job.failed_hosts.lock().unwrap();
+    if !failed_hosts.is_empty() {
+        let mut output = paint(
+            format!("{} server(s) failed during the render:", failed_hosts.len()),
+            Color::Red,
+        );
+
+        for (ip, reason) in failed_hosts.iter() {
+            output += &format!("\n    {}: {}", output::server(ip), reason);
+        }
+
+        let remaining = frames.lock().unwrap().len();
+        if remaining > 0 {
+            output += &format!("\n{} frame(s) were not rendered", remaining);
+        }
+
+        error!("{}", output);
+    }
+    drop(failed_hosts);
+
+    let mut times = job.times.into_inner().unwrap();
+    times.sort_by_key(|time| time.frame);
+
+    let median = report::median(&times);
+    for time in report::outliers(&times) {
+        warn!(
+            "{}",
+            paint(
+                format!(
+                    "Frame {} took {:.1}s on {}, {:.0}x the median of {:.1}s",
+                    time.frame,
+                    time.seconds,
+                    time.server,
+                    time.seconds / median,
+                    median
+                ),
+                Color::Yellow
+            )
+        );
+    }
+
+    let report = output_dir.join(format!("{}.report.html", name));
+    if let Some(ReportFormat::Html) = settings.report {
+        match write(&report, report::html(id, &times)) {
+            Ok(()) => log!("Saved frame time report as {}", report.display()),
+            Err(error) => error!("Saving {} failed: {}", report.display(), error),
+        }
+    }
+
+    let rendered = job.rendered.into_inner().unwrap();
+    for (ip, (frames, bytes, seconds, transfer_seconds)) in &rendered {
+        if *frames > 0 {
+            profile::record(
+                &mut profiles,
+                ip,
+                seconds / *frames as f64,
+                *bytes as f64 / transfer_seconds.max(f64::EPSILON),
+            );
+        }
+    }
+    profile::save(&profiles);
+
+    let servers = report::servers(&times);
+    let rows: Vec<Vec<String>> = ips
+        .iter()
+        .map(|ip| {
+            let (frames, bytes, seconds, _) = rendered.get(*ip).copied().unwrap_or_default();
+            let server = servers.iter().find(|server| server.server == *ip);
+
+            vec![
+                output::server(ip),
+                frames.to_string(),
+                format!("{:.1} MiB", bytes as f64 / (1024 * 1024) as f64),
+                format!("{:.1}s", seconds),
+                server
+                    .filter(|server| server.render_seconds > 0.0)
+                    .map(|server| format!("{:.1}s", server.render_seconds / server.frames as f64))
+                    .unwrap_or_else(|| String::from("-")),
+                server
+                    .and_then(|server| server.peak_memory)
+                    .map(format_size)
+                    .unwrap_or_else(|| String::from("-")),
+                server
+                    .map(|server| server.devices.join(", "))
+                    .unwrap_or_default(),
+            ]
+        })
+        .collect();
+
+    let title = match job.chunk {
+        None => format!("\"{}\"", id),
+        Some((first, last)) => format!("\"{}\" (frames {}-{})", id, first, last),
+    };
+    log!(
+        "Summary for {}:\n{}",
+        title,
+        output::table(
+            &[
+                "SERVER",
+                "FRAMES",
+                "SIZE",
+                "TIME",
+                "RENDER/FRAME",
+                "PEAK MEMORY",
+                "DEVICE"
+            ],
+            &rows
+        )
+    );
+
+    let render_report = output_dir.join(format!("{}.render_report.json", name));
+    let json = report::json(id, &times);
+    if let Err(error) = write(&render_report, serde_json::to_vec_pretty(&json).unwrap()) {
+        error!("Saving {} failed: {}", render_report.display(), error);
+    }
+    logging::document(&json);
+
+    let sidecar = Sidecar {
+        id,
+        brsp_version: env!("CARGO_PKG_VERSION"),
+        settings,
+        servers: ips
+            .iter()
+            .copied()
+            .filter(|ip| rendered.contains_key(*ip))
+            .filter_map(|ip| fingerprint(ip, id).map(|fingerprint| (ip, fingerprint)))
+            .collect(),
+    };
+
+    let sidecar_path = output_dir.join(format!("{}.sidecar.json", name));
+    if let Err(error) = write(&sidecar_path, serde_json::to_vec_pretty(&sidecar).unwrap()) {
+        error!("Saving {} failed: {}", sidecar_path.display(), error);
+    }
+
+    failed.extend(frames.lock().unwrap().iter());
+    failed.sort();
+
+    if settings.bundle {
+        let bundle_dir = output_dir.join(format!("{}.bundle", name));
+        let bundle = Bundle {
+            id,
+            times: &times,
+            failed: &failed,
+            attempts: &job.failures.lock().unwrap(),
+            failed_hosts: &job.failed_hosts.lock().unwrap(),
+            extra: vec![sidecar_path, report, render_report],
+        };
+
+        match bundle.write(&bundle_dir) {
+            Ok(()) => log!("Saved job bundle as {}", bundle_dir.display()),
+            Err(message) => error!(
+                "{}\nReason: {}",
+                paint("Writing the job bundle failed", Color::Red),
+                message
+            ),
+        }
+    }
+
+    if let Some(codec) = settings.encode {
+        let framerate = ips
+            .iter()
+            .copied()
+            .filter(|ip| rendered.contains_key(*ip))
+            .find_map(|ip| frame_rate(ip, id));
+
+        let video = output_dir.join(format!("{}.{}", name, codec.extension()));
+        let result = if !failed.is_empty() {
+            Err(format!("{} frame(s) are missing", failed.len()))
+        } else {
+            framerate
+                .ok_or_else(|| String::from("Could not determine the frame rate"))
+                .and_then(|framerate| encode::encode(&times, &video, framerate, codec))
+        };
+
+        match result {
+            Ok(()) => log!("Saved video as {}", video.display()),
+            Err(message) => error!(
+                "{}\nReason: {}",
+                paint("Encoding the video failed", Color::Red),
+                message
+            ),
+        }
+    }
+
+    failed
+}
+
+fn fingerprint(ip: &str, id: &str) -> Option<Fingerprint> {
+    let request = to_header(
+        serde_json::to_vec(&Request::Fingerprint {
+            id: String::from(id),
+            user: user(),
+        })
+        .unwrap(),
+    );
+
+    let response = pool::try_checkout(ip, None).and_then(|mut server| {
+        server.write_all(&request)?;
+        let header = read_header(&mut server)?;
+        pool::checkin(ip, server);
+        Ok(serde_json::from_slice(&header)?)
+    });
+    let response = response.unwrap_or_else(|error: std::io::Error| FingerprintResponse::Fail {
+        message: error.to_string(),
+    });
+
+    match response {
+        FingerprintResponse::Okay { fingerprint } => Some(fingerprint),
+        FingerprintResponse::Fail { message } => {
+            error!(
+                "{}: {}\nReason: {}",
+                output::server(ip),
+                paint("Could not fetch environment fingerprint", Color::Red),
+                message
+            );
+            None
+        }
+    }
+}
+
+fn frame_rate(ip: &str, id: &str) -> Option<f64> {
+    let request = to_header(
+        serde_json::to_vec(&Request::FrameRate {
+            id: String::from(id),
+            user: user(),
+        })
+        .unwrap(),
+    );
+
+    let response = pool::try_checkout(ip, None).and_then(|mut server| {
+        server.write_all(&request)?;
+        let header = read_header(&mut server)?;
+        pool::checkin(ip, server);
+        Ok(serde_json::from_slice(&header)?)
+    });
+    let response = response.unwrap_or_else(|error: std::io::Error| FrameRateResponse::Fail {
+        message: error.to_string(),
+    });
+
+    match response {
+        FrameRateResponse::Okay { framerate } => Some(framerate),
+        FrameRateResponse::Fail { message } => {
+            error!(
+                "{}: {}\nReason: {}",
+                output::server(ip),
+                paint("Could not query the frame rate", Color::Red),
+                message
+            );
+            None
+        }
+    }
+}
+
+/// Accepts patterns like `shot010_####.{ext}`: one run of `#` for the zero-padded frame number,
+/// optionally followed by `.{ext}`.
+fn parse_name_pattern(pattern: &str) -> Result<String, String> {
+    let stem = pattern.strip_suffix(".{ext}").unwrap_or(pattern);
+
+    let runs = stem
+        .split(|character| character != '#')
+        .filter(|run| !run.is_empty())
+        .count();
+    if runs != 1 {
+        return Err(String::from(
+            "Expected exactly one run of '#' for the frame number",
+        ));
+    }
+
+    protocol::validate_id(&stem.replace('#', "0")).map_err(|error| error.to_string())?;
+
+    Ok(String::from(pattern))
+}
+
+/// File name of `frame` without extension, `0001` unless a `--name-pattern` is set.
+fn frame_stem(pattern: Option<&str>, frame: usize) -> String {
+    match pattern {
+        None => format!("{:04}", frame),
+        Some(pattern) => {
+            let stem = pattern.strip_suffix(".{ext}").unwrap_or(pattern);
+            let width = stem.matches('#').count();
+
+            stem.replacen(&"#".repeat(width), &format!("{:0width$}", frame), 1)
+        }
+    }
+}
+
+fn existing_frame(output_dir: &Path, pattern: Option<&str>, frame: usize) -> Option<PathBuf> {
+    let stem = frame_stem(pattern, frame);
+
+    read_dir(output_dir)
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| {
+            path.file_stem() == Some(stem.as_ref()) && path.extension() != Some("part".as_ref())
+        })
+}
+
+fn placeholder(output_dir: &Path, pattern: Option<&str>, frame: usize) -> PathBuf {
+    output_dir.join(format!("{}.placeholder", frame_stem(pattern, frame)))
+}
+
+fn remove_incomplete(output_dir: &Path) {
+    if let Ok(entries) = read_dir(output_dir) {
+        for path in entries.flatten().map(|entry| entry.path()) {
+            if path.extension() == Some("part".as_ref()) && remove_file(&path).is_ok() {
+                log!("Removed incomplete frame {}", path.display());
+            }
+        }
+    }
+}
+
+fn render(ip: &str, job: &RenderState) {
+    let mut server = match pool::try_checkout(ip, None) {
+        Ok(server) => server,
+        Err(error) => {
+            error!(
+                "{}: {}",
+                output::server(ip),
+                paint(format!("Could not connect\nReason: {}", error), Color::Red)
+            );
+            job.fail_host(ip, format!("Could not connect ({})", error));
+            return;
+        }
+    };
+    let mut request = render_request(job);
+    let mut session = None;
+
+    loop {
+        let mut in_flight = VecDeque::new();
+        let result = server
+            .write_all(&request)
+            .and_then(|()| render_session(ip, job, &mut server, &mut session, &mut in_flight));
+
+        let error = match result {
+            Ok(()) => {
+                if !job.wait_for_frames(ip) {
+                    return;
+                }
+
+                log!("{}: Picking up requeued frames", output::server(ip));
+
+                server = match pool::try_checkout(ip, None) {
+                    Ok(server) => server,
+                    Err(error) => {
+                        job.fail_host(ip, format!("Could not connect ({})", error));
+                        return;
+                    }
+                };
+                request = render_request(job);
+                session = None;
+                continue;
+            }
+            Err(error) => error,
+        };
+
+        if error.to_string() == SAVE_FAILED {
+            job.requeue(in_flight.into_iter().map(|(frame, _)| frame));
+            job.fail_host(ip, String::from("Could not save frames"));
+            return;
+        }
+
+        if error.to_string() == SERVER_SHUTDOWN {
+            warn!(
+                "{}: {}",
+                output::server(ip),
+                paint(
+                    format!(
+                        "Server is shutting down, requeueing {} frame(s)",
+                        in_flight.len()
+                    ),
+                    Color::Yellow
+                )
+            );
+            job.requeue(in_flight.into_iter().map(|(frame, _)| frame));
+            job.fail_host(ip, String::from("Server shut down"));
+            return;
+        }
+
+        let token = match session.clone() {
+            None => {
+                error!(
+                    "{}: {}",
+                    output::server(ip),
+                    paint(format!("Connection lost\nReason: {}", error), Color::Red)
+                );
+                job.fail_host(ip, format!("Connection lost ({})", error));
+                job.requeue(in_flight.into_iter().map(|(frame, _)| frame));
+                return;
+            }
+            Some(token) => token,
+        };
+
+        job.requeue(in_flight.into_iter().map(|(frame, _)| frame));
+
+        warn!(
+            "{}: {}",
+            output::server(ip),
+            paint(
+                format!("Connection lost ({}), reconnecting", error),
+                Color::Yellow
+            )
+        );
+
+        server = match reconnect(ip) {
+            None => {
+                error!(
+                    "{}: {}",
+                    output::server(ip),
+                    paint("Could not reconnect, giving up", Color::Red)
+                );
+                job.fail_host(ip, format!("Connection lost ({}), reconnect failed", error));
+                return;
+            }
+            Some(server) => server,
+        };
+
+        log!("{}: Reconnected, resuming session", output::server(ip));
+        request = to_header(serde_json::to_vec(&Request::Reconnect { session: token }).unwrap());
+    }
+}
+
+fn batch_size(seconds_per_frame: f64) -> usize {
+    ((BATCH_SECONDS / seconds_per_frame.max(f64::EPSILON)) as usize).clamp(1, MAX_BATCH_SIZE)
+}
+
+fn render_session(
+    ip: &str,
+    job: &RenderState,
+    server: &mut TcpStream,
+    session: &mut Option<String>,
+    in_flight: &mut VecDeque<(usize, Instant)>,
+) -> Result<(), std::io::Error> {
+    let depth = if job.settings.prefetch { 2 } else { 1 };
+    let mut accepted = false;
+    let mut batched = false;
+    let mut seconds_per_frame = job.seconds_per_frame.get(ip).copied();
+    let mut batch_size = seconds_per_frame.map_or(1, batch_size);
+    let mut last_progress = Instant::now();
+
+    loop {
+        if !accepted && in_flight.is_empty() {
+            if !job.has_frames(ip) {
+                return Ok(());
+            }
+
+            let response = read_session_header(ip, server)?;
+            let response = serde_json::from_slice(&response).unwrap();
+
+            let reason = match response {
+                RenderAcceptResponse::Accept {
+                    session: token,
+                    batch,
+                    heartbeat,
+                } => {
+                    if token.is_some() {
+                        *session = token;
+                    }
+                    batched = job.settings.batch && batch;
+
+                    if heartbeat.is_some() {
+                        let timeout = job.settings.heartbeat_timeout.unwrap_or(HEARTBEAT_TIMEOUT);
+                        server.set_read_timeout(Some(Duration::from_secs(timeout.max(1))))?;
+                    }
+
+                    None
+                }
+                RenderAcceptResponse::Reject { reason } => Some(reason),
+            };
+
+            if let Some(reason) = reason {
+                match reason {
+                    RejectReason::MissingAssets { libraries, images } => {
+                        let mut output = format!(
+                            "{}: {}",
+                            output::server(ip),
+                            paint(format!("Missing assets in \"{}\"", job.id), Color::Red)
+                        );
+
+                        for library in libraries {
+                            output += &format!("\n    Library: {}", library);
+                        }
+
+                        for image in images {
+                            output += &format!("\n    Image: {}", image);
+                        }
+
+                        error!("{}\nAborting render", output);
+                        job.frames.lock().unwrap().clear();
+                    }
+                    RejectReason::MissingAddons { addons } => {
+                        error!(
+                            "{}: {}\n    {}\nAborting render",
+                            output::server(ip),
+                            paint(format!("Missing add-ons for \"{}\"", job.id), Color::Red),
+                            addons.join("\n    ")
+                        );
+                        job.frames.lock().unwrap().clear();
+                    }
+                    RejectReason::Busy => {
+                        warn!(
+                            "{}: {}",
+                            output::server(ip),
+                            paint("All render slots are taken", Color::Yellow)
+                        );
+                        job.fail_host(ip, String::from("All render slots are taken"));
+                    }
+                    RejectReason::MissingBlender {
+                        required,
+                        available,
+                    } => {
+                        warn!(
+                            "{}: {}\n    Available: {}",
+                            output::server(ip),
+                            paint(format!("No Blender matching {}", required), Color::Yellow),
+                            available.join(", ")
+                        );
+                        job.fail_host(ip, format!("No Blender matching {}", required));
+                    }
+                    RejectReason::Failed { message } => {
+                        warn!("{}: {}", output::server(ip), paint(&message, Color::Yellow));
+                        job.fail_host(ip, message);
+                    }
+                    RejectReason::UnknownSession => {
+                        log!(
+                            "{}: Session is no longer known, starting a new one",
+                            output::server(ip)
+                        );
+
+                        *session = None;
+                        server.write_all(&render_request(job))?;
+                        continue;
+                    }
+                }
+
+                return Ok(());
+            }
+
+            log!("{}: Render request accepted", output::server(ip));
+            accepted = job.settings.prefetch;
+        }
+
+        if batched && in_flight.is_empty() {
+            let frames = job.next_frames(ip, batch_size);
+
+            if let (Some(start), Some(end)) = (frames.first(), frames.last()) {
+                let request = to_header(
+                    serde_json::to_vec(&FrameRangeRequest {
+                        id: String::from(job.id),
+                        start: *start,
+                        end: *end,
+                        settings: job.settings.clone(),
+                    })
+                    .unwrap(),
+                );
+                server.write_all(&request)?;
+            }
+
+            for frame in frames {
+                emit(Event::FrameStarted {
+                    server: ip,
+                    id: job.id,
+                    frame,
+                });
+                in_flight.push_back((frame, Instant::now()));
+            }
+        }
+
+        while !batched && in_flight.len() < depth {
+            let frame = match job.next_frame(ip) {
+                None => {
+                    break;
+                }
+                Some(frame) => frame,
+            };
+
+            let request = to_header(
+                serde_json::to_vec(&FrameRequest {
+                    id: String::from(job.id),
+                    frame,
+                    settings: job.settings.clone(),
+                })
+                .unwrap(),
+            );
+            server.write_all(&request)?;
+
+            emit(Event::FrameStarted {
+                server: ip,
+                id: job.id,
+                frame,
+            });
+            in_flight.push_back((frame, Instant::now()));
+        }
+
+        let (frame, start) = match in_flight.front() {
+            None => {
+                return Ok(());
+            }
+            Some(frame) => *frame,
+        };
+
+        let header = read_session_header(ip, server)?;
+        let header = serde_json::from_slice(&header).unwrap();
+
+        match header {
+            RenderResponse::Okay {
+                size,
+                extension,
+                format_override,
+                checksum,
+                compressed,
+                stats,
+                passes,
+            } => {
+                if let Some(format_override) = format_override {
+                    job.format_warning.call_once(|| {
+                        warn!(
+                            "{}: {}",
+                            output::server(ip),
+                            paint(
+                                format!(
+                                    "\"{}\" outputs {}, rendering frames as {} instead",
+                                    job.id, format_override.from, format_override.to
+                                ),
+                                Color::Yellow
+                            )
+                        );
+                    });
+                }
+
+                let permit = job.downloads.acquire(frame);
+                let transfer_start = Instant::now();
+                let image = transfer::read_exact(&mut *server, size)?;
+                let mut pass_images = Vec::new();
+                for pass in &passes {
+                    pass_images.push(transfer::read_exact(&mut *server, pass.size)?);
+                }
+                let transfer_duration = transfer_start.elapsed().as_secs_f64();
+                drop(permit);
+                next_in_flight(in_flight, batched);
+
+                let decode = |data: &[u8]| {
+                    if compressed {
+                        zstd::decode_all(data).unwrap_or_default()
+                    } else {
+                        data.to_vec()
+                    }
+                };
+                let image = decode(&image);
+                let passes: Vec<_> = passes
+                    .into_iter()
+                    .zip(pass_images)
+                    .map(|(pass, data)| (pass, decode(&data)))
+                    .collect();
+
+                let corrupt = checksum.is_some_and(|checksum| checksum != hash(&image))
+                    || passes.iter().any(|(pass, data)| {
+                        pass.checksum
+                            .as_ref()
+                            .is_some_and(|checksum| *checksum != hash(data))
+                    });
+                if corrupt {
+                    error!(
+                        "{}: {}",
+                        output::server(ip),
+                        paint(
+                            format!("Checksum mismatch for frame {}, requeueing", frame),
+                            Color::Red
+                        )
+                    );
+                    emit(Event::FrameFailed {
+                        server: ip,
+                        id: job.id,
+                        frame,
+                        reason: "Checksum mismatch",
+                    });
+
+                    job.requeue([frame]);
+                    continue;
+                }
+
+                if !job.completed.lock().unwrap().insert(frame) {
+                    job.finish();
+                    log!(
+                        "{}: Discarding duplicate result for frame {} of \"{}\"",
+                        output::server(ip),
+                        frame,
+                        job.id
+                    );
+                    continue;
+                }
+
+                let frame_stem = frame_stem(job.settings.name_pattern.as_deref(), frame);
+                let mut image_name = format!("{}.{}", frame_stem, extension);
+                if job.overwrite == OverwritePolicy::Version {
+                    let mut version = 1;
+                    while job.output_dir.join(&image_name).exists() {
+                        version += 1;
+                        image_name = format!("{}_v{:03}.{}", frame_stem, version, extension);
+                    }
+                }
+
+                let image_path = job.output_dir.join(&image_name);
+                let stem = &image_name[..image_name.len() - extension.len() - 1];
+                if let Err(error) = save_frame(ip, job, frame, &image_path, &image, stem, &passes) {
+                    error!(
+                        "{}: {}\nReason: {}",
+                        output::server(ip),
+                        paint(format!("Could not save frame {}", frame), Color::Red),
+                        error
+                    );
+
+                    job.completed.lock().unwrap().remove(&frame);
+                    job.requeue([frame]);
+                    return Err(std::io::Error::new(error.kind(), SAVE_FAILED));
+                }
+                job.finish();
+
+                job.save_session(|session| session.completed.push(frame));
+                log!(
+                    "{}: Saved frame {} as {}",
+                    output::server(ip),
+                    frame,
+                    image_name
+                );
+                let duration = start.elapsed().as_secs_f64();
+                if batched {
+                    let average = match seconds_per_frame {
+                        Some(average) => average * 0.7 + duration * 0.3,
+                        None => duration,
+                    };
+                    seconds_per_frame = Some(average);
+                    batch_size = self::batch_size(average);
+                }
+
+                emit(Event::FrameCompleted {
+                    server: ip,
+                    id: job.id,
+                    frame,
+                    bytes: size,
+                    duration,
+                });
+
+                {
+                    let mut rendered = job.rendered.lock().unwrap();
+                    let rendered = rendered.entry(String::from(ip)).or_default();
+                    rendered.0 += 1;
+                    rendered.1 += size;
+                    rendered.2 += duration;
+                    rendered.3 += transfer_duration;
+                }
+
+                job.times.lock().unwrap().push(FrameTime {
+                    frame,
+                    server: String::from(ip),
+                    seconds: duration,
+                    path: image_path.clone(),
+                    bytes: size,
+                    stats,
+                });
+
+                (job.on_frame)(frame, &image_path);
+            }
+            RenderResponse::Progress(progress) => {
+                if last_progress.elapsed() >= Duration::from_secs(1) {
+                    last_progress = Instant::now();
+                    log!(
+                        "{}: {} ({}/{} frames done)",
+                        output::server(ip),
+                        format_progress(&progress),
+                        job.completed.lock().unwrap().len(),
+                        job.total
+                    );
+                }
+            }
+            RenderResponse::Fail {
+                cancelled: true,
+                job_cancelled,
+            } => {
+                next_in_flight(in_flight, batched);
+                job.finish();
+                emit(Event::FrameFailed {
+                    server: ip,
+                    id: job.id,
+                    frame,
+                    reason: "Frame was cancelled",
+                });
+                warn!(
+                    "{}: {}",
+                    output::server(ip),
+                    paint(format!("Frame {} was cancelled", frame), Color::Yellow)
+                );
+
+                if job_cancelled {
+                    let mut frames = job.frames.lock().unwrap();
+                    if !frames.is_empty() {
+                        warn!(
+                            "{}: {}",
+                            output::server(ip),
+                            paint(
+                                format!(
+                                    "\"{}\" was cancelled, dropping {} queued frame(s)",
+                                    job.id,
+                                    frames.len()
+                                ),
+                                Color::Yellow
+                            )
+                        );
+                        frames.clear();
+                    }
+                }
+            }
+            RenderResponse::Fail {
+                cancelled: false, ..
+            } => {
+                next_in_flight(in_flight, batched);
+                emit(Event::FrameFailed {
+                    server: ip,
+                    id: job.id,
+                    frame,
+                    reason: "Server failed to render frame",
+                });
+
+                let failures = {
+                    let mut failures = job.failures.lock().unwrap();
+                    let failures = failures.entry(frame).or_default();
+                    *failures += 1;
+                    *failures
+                };
+                job.save_session(|session| {
+                    session.failures.insert(frame, failures);
+                });
+
+                let retries = job.settings.retries.unwrap_or(FRAME_RETRIES);
+                if failures <= retries {
+                    error!(
+                        "{}: {}",
+                        output::server(ip),
+                        paint(
+                            format!(
+                                "Frame {} failed (attempt {} of {}), requeueing",
+                                frame,
+                                failures,
+                                retries + 1
+                            ),
+                            Color::Red
+                        )
+                    );
+                    job.requeue([frame]);
+                } else {
+                    job.finish();
+                    error!(
+                        "{}: {}",
+                        output::server(ip),
+                        paint(
+                            format!("Frame {} failed {} times, giving up", frame, failures),
+                            Color::Red
+                        )
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Writes a received frame and its passes to the output directory.
+fn save_frame(
+    ip: &str,
+    job: &RenderState,
+    frame: usize,
+    image_path: &Path,
+    image: &[u8],
+    stem: &str,
+    passes: &[(PassOutput, Vec<u8>)],
+) -> Result<(), std::io::Error> {
+    write_atomic(image_path, image)?;
+
+    for (pass, data) in passes {
+        let pass_dir = match protocol::namespace_path(job.output_dir, &pass.name) {
+            Ok(pass_dir) => pass_dir,
+            Err(error) => {
+                warn!(
+                    "{}: Discarding pass \"{}\" of frame {}: {}",
+                    output::server(ip),
+                    pass.name,
+                    frame,
+                    error
+                );
+                continue;
+            }
+        };
+
+        create_dir_all(&pass_dir)?;
+        write_atomic(&pass_dir.join(format!("{}.{}", stem, pass.extension)), data)?;
+    }
+
+    Ok(())
+}
+
+fn next_in_flight(in_flight: &mut VecDeque<(usize, Instant)>, batched: bool) {
+    in_flight.pop_front();
+
+    if batched && let Some((_, start)) = in_flight.front_mut() {
+        *start = Instant::now();
+    }
+}
+
+/// Reads the next header of a render session, answering the server's heartbeats on the way.
+fn read_session_header(ip: &str, server: &mut TcpStream) -> Result<Vec<u8>, std::io::Error> {
+    loop {
+        let header = read_header(server).map_err(|error| match error.kind() {
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => {
+                std::io::Error::new(ErrorKind::TimedOut, "no heartbeat from server")
+            }
+            _ => error,
+        })?;
+
+        match serde_json::from_slice(&header) {
+            Ok(Heartbeat::Ping) => {
+                server.write_all(&to_header(serde_json::to_vec(&Heartbeat::Pong).unwrap()))?;
+            }
+            Ok(Heartbeat::Shutdown) => {
+                return Err(std::io::Error::new(
+                    ErrorKind::ConnectionAborted,
+                    SERVER_SHUTDOWN,
+                ));
+            }
+            Ok(Heartbeat::Queued { position }) => {
+                log!(
+                    "{}: Waiting for a render slot, position {} in queue",
+                    output::server(ip),
+                    position
+                );
+            }
+            _ => {
+                return Ok(header);
+            }
+        }
+    }
+}
+
+fn render_request(job: &RenderState) -> Vec<u8> {
+    to_header(
+        serde_json::to_vec(&Request::Render {
+            id: Some(String::from(job.id)),
+            prefetch: job.settings.prefetch,
+            addons: job.settings.addons.clone(),
+            heartbeat: true,
+            blender: job.settings.blender_version.clone(),
+            compression: compression(),
+            priority: Some(job.settings.priority),
+            user: user(),
+        })
+        .unwrap(),
+    )
+}
+
+fn reconnect(ip: &str) -> Option<TcpStream> {
+    let start = Instant::now();
+
+    while start.elapsed() < RECONNECT_TIMEOUT {
+        thread::sleep(RECONNECT_INTERVAL);
+
+        if let Ok(stream) = try_connect(ip, Some(RECONNECT_INTERVAL)) {
+            return Some(stream);
+        }
+    }
+
+    None
+}
+
+pub fn bake_textures(
+    ip: &str,
+    output_dir: &Path,
+    id: String,
+    objects: Vec<String>,
+    maps: Vec<BakeMap>,
+    resolution: u32,
+) -> Result<(), std::io::Error> {
+    let mut server = pool::try_checkout(ip, None)?;
+
+    let request = to_header(
+        serde_json::to_vec(&Request::BakeTextures {
+            id,
+            objects,
+            maps,
+            resolution,
+            user: user(),
+        })
+        .unwrap(),
+    );
+    server.write_all(&request)?;
+
+    let header = read_header(&mut server)?;
+    let header: BakeResponse = serde_json::from_slice(&header)?;
+
+    match header {
+        BakeResponse::Okay { images } => {
+            for image in images {
+                let data = transfer::read_exact(&mut server, image.size)?;
+
+                if image.extension.is_empty()
+                    || !image.extension.chars().all(|c| c.is_ascii_alphanumeric())
+                {
+                    error!(
+                        "{}: Skipped baked map with invalid extension \"{}\"",
+                        output::server(ip),
+                        image.extension
+                    );
+                    continue;
+                }
+
+                let image_name = format!(
+                    "{}_{}.{}",
+                    file_stem(&image.object),
+                    image.map,
+                    image.extension
+                );
+                write(output_dir.join(&image_name), &*data)?;
+                log!("{}: Saved baked map as {}", output::server(ip), image_name);
+            }
+        }
+        BakeResponse::Fail { message } => {
+            error!(
+                "{}: {}\nReason: {}",
+                output::server(ip),
+                paint("Baking failed", Color::Red),
+                message
+            );
+        }
+    }
+
+    pool::checkin(ip, server);
+    Ok(())
+}
+
+/// Turns a name sent by a server into a file name that stays inside the output directory.
+fn file_stem(name: &str) -> String {
+    let stem: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ' ') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+
+    stem.trim_start_matches('.').to_string()
+}
+
+pub fn thumbnail(
+    ip: &str,
+    output_dir: &Path,
+    id: &str,
+    frame: usize,
+) -> Result<(), std::io::Error> {
+    let mut server = pool::try_checkout(ip, None)?;
+
+    let request = to_header(
+        serde_json::to_vec(&Request::Thumbnail {
+            id: String::from(id),
+            frame,
+            user: user(),
+        })
+        .unwrap(),
+    );
+    server.write_all(&request)?;
+
+    let header = read_header(&mut server)?;
+    let header = serde_json::from_slice(&header)?;
+
+    match header {
+        ThumbnailResponse::Okay { size } => {
+            let image = transfer::read_exact(&mut server, size)?;
+
+            let image_name = format!("{:04}.jpg", frame);
+            write(output_dir.join(&image_name), &*image)?;
+            log!(
+                "{}: Saved thumbnail of frame {} as {}",
+                output::server(ip),
+                frame,
+                image_name
+            );
+        }
+        ThumbnailResponse::Fail { message } => {
+            error!(
+                "{}: {}\nReason: {}",
+                output::server(ip),
+                paint(format!("No thumbnail for frame {}", frame), Color::Red),
+                message
+            );
+        }
+    }
+
+    pool::checkin(ip, server);
+    Ok(())
+}
+
+pub fn fetch_frame(
+    ip: &str,
+    output_dir: &Path,
+    id: &str,
+    frame: usize,
+) -> Result<(), std::io::Error> {
+    let mut server = pool::try_checkout(ip, None)?;
+
+    let request = to_header(
+        serde_json::to_vec(&Request::FetchFrame {
+            id: String::from(id),
+            frame,
+            compressed: true,
+            user: user(),
+        })
+        .unwrap(),
+    );
+    server.write_all(&request)?;
+
+    let header = read_header(&mut server)?;
+    let header = serde_json::from_slice(&header)?;
+
+    match header {
+        RenderResponse::Okay {
+            size,
+            extension,
+            checksum,
+            compressed,
+            ..
+        } => {
+            let image = transfer::read_exact(&mut server, size)?;
+            let image = if compressed {
+                zstd::decode_all(&image[..])?
+            } else {
+                image.to_vec()
+            };
+
+            if checksum.is_some_and(|checksum| checksum != hash(&image)) {
+                error!(
+                    "{}: {}",
+                    output::server(ip),
+                    paint(format!("Checksum mismatch for frame {}", frame), Color::Red)
+                );
+            } else {
+                let image_name = format!("{:04}.{}", frame, extension);
+                write_atomic(&output_dir.join(&image_name), &image)?;
+                log!(
+                    "{}: Saved retained frame {} as {}",
+                    output::server(ip),
+                    frame,
+                    image_name
+                );
+            }
+        }
+        RenderResponse::Fail { .. } | RenderResponse::Progress(_) => {
+            error!(
+                "{}: {}",
+                output::server(ip),
+                paint(format!("Frame {} is not retained", frame), Color::Red)
+            );
+        }
+    }
+
+    pool::checkin(ip, server);
+    Ok(())
+}
+
+pub fn cancel_frame(ip: &str, id: &str, frame: Option<usize>) -> Result<(), String> {
+    let mut server =
+        try_connect(ip, Some(Duration::from_secs(5))).map_err(|error| error.to_string())?;
+
+    let request = to_header(
+        serde_json::to_vec(&Request::CancelFrame {
+            id: String::from(id),
+            frame,
+            user: user(),
+        })
+        .unwrap(),
+    );
+    server
+        .write_all(&request)
+        .map_err(|error| error.to_string())?;
+
+    let header = read_header(&mut server).map_err(|error| error.to_string())?;
+
+    match serde_json::from_slice(&header).unwrap() {
+        Response::Okay => Ok(()),
+        Response::Fail { message } => Err(message),
+    }
+}
+
+/// Cancels every frame of `id` on the given servers, including renders already in progress.
+pub fn cancel_job(ips: &str, id: &str) -> bool {
+    let mut success = true;
+
+    for ip in ips.split_terminator(',') {
+        let result = try_connect(ip, Some(Duration::from_secs(5)))
+            .and_then(|mut server| {
+                let request = to_header(
+                    serde_json::to_vec(&Request::Cancel {
+                        id: String::from(id),
+                        user: user(),
+                    })
+                    .unwrap(),
+                );
+                server.write_all(&request)?;
+
+                read_header(&mut server)
+            })
+            .map_err(|error| error.to_string())
+            .and_then(|header| match serde_json::from_slice(&header).unwrap() {
+                Response::Okay => Ok(()),
+                Response::Fail { message } => Err(message),
+            });
+
+        match result {
+            Ok(()) => log!("{}: Cancelled \"{}\"", output::server(ip), id),
+            Err(message) => {
+                success = false;
+                error!(
+                    "{}: {}\nReason: {}",
+                    output::server(ip),
+                    paint("Cancelling failed", Color::Red),
+                    message
+                );
+            }
+        }
+    }
+
+    success
+}
+
+/// Deletes the stored job `id` from every server. Returns whether all of them deleted it.
+pub fn delete_job(ips: &str, id: &str) -> bool {
+    let mut success = true;
+
+    for ip in ips.split_terminator(',') {
+        match delete(ip, id) {
+            Ok(()) => log!("{}: Deleted \"{}\"", output::server(ip), id),
+            Err(message) => {
+                success = false;
+                error!(
+                    "{}: {}\nReason: {}",
+                    output::server(ip),
+                    paint("Deleting failed", Color::Red),
+                    message
+                );
+            }
+        }
+    }
+
+    success
+}
+
+fn delete(ip: &str, id: &str) -> Result<(), String> {
+    let mut server =
+        try_connect(ip, Some(Duration::from_secs(5))).map_err(|error| error.to_string())?;
+    let request = to_header(
+        serde_json::to_vec(&Request::Delete {
+            id: String::from(id),
+            user: user(),
+        })
+        .unwrap(),
+    );
+    server
+        .write_all(&request)
+        .map_err(|error| error.to_string())?;
+
+    let header = read_header(&mut server).map_err(|error| error.to_string())?;
+    match serde_json::from_slice(&header).map_err(|error| error.to_string())? {
+        Response::Okay => Ok(()),
+        Response::Fail { message } => Err(message),
+    }
+}
+
+fn upload(
+    ip: &str,
+    id: &str,
+    ttl: Option<u64>,
+    blender: Option<&BlenderRequirement>,
+    size: usize,
+    hash: &str,
+    blend: impl Read,
+) -> bool {
+    emit(Event::UploadStarted {
+        server: ip,
+        id,
+        bytes: size,
+    });
+
+    let mut server = match pool::try_checkout(ip, None) {
+        Ok(server) => server,
+        Err(error) => {
+            error!(
+                "{}: {}\nReason: {}",
+                output::server(ip),
+                paint("Could not connect", Color::Red),
+                error
+            );
+            emit(Event::UploadFailed {
+                server: ip,
+                id,
+                reason: &error.to_string(),
+            });
+            return false;
+        }
+    };
+
+    let request = to_header(
+        serde_json::to_vec(&Request::Upload {
+            id: String::from(id),
+            size,
+            ttl,
+            hash: Some(String::from(hash)),
+            blender: blender.cloned(),
+            compressed: compression().is_some(),
+            user: user(),
+        })
+        .unwrap(),
+    );
+
+    let start = Instant::now();
+    let mut last_report = start;
+
+    let present = server
+        .write_all(&request)
+        .and_then(|()| read_header(&mut server))
+        .and_then(|header| {
+            serde_json::from_slice(&header)
+                .map_err(|error| std::io::Error::new(ErrorKind::InvalidData, error))
+        })
+        .and_then(|response| match response {
+            UploadResponse::Present => Ok(None),
+            UploadResponse::Send { compressed } => Ok(Some(compressed)),
+            UploadResponse::Reject { message } => Err(std::io::Error::other(message)),
+        });
+
+    let read = Cell::new(0);
+    let sent = present.and_then(|compressed| {
+        let compressed = match compressed {
+            Some(compressed) => compressed,
+            None => {
+                return Ok(None);
+            }
+        };
+
+        let mut report = |sent| {
+            if last_report.elapsed() >= Duration::from_secs(1) {
+                last_report = Instant::now();
+                log!(
+                    "{}: {:.1}% uploaded ({})",
+                    output::server(ip),
+                    read.get() as f64 / size.max(1) as f64 * 100.0,
+                    format_speed(sent, start.elapsed())
+                );
+            }
+        };
+
+        let blend = transfer::Counted {
+            inner: blend,
+            count: &read,
+        };
+        match compression().filter(|_| compressed) {
+            Some(level) => transfer::send_chunked(
+                zstd::stream::read::Encoder::new(blend, level)?,
+                &server,
+                &mut report,
+            ),
+            None => transfer::send_chunked(blend, &server, &mut report),
+        }
+        .map(Some)
+    });
+
+    let sent = match sent {
+        Ok(sent) => sent,
+        Err(error) => {
+            error!(
+                "{}: {}\nReason: {}",
+                output::server(ip),
+                paint("File upload failed", Color::Red),
+                error
+            );
+            emit(Event::UploadFailed {
+                server: ip,
+                id,
+                reason: &error.to_string(),
+            });
+            return false;
+        }
+    };
+
+    let duration = start.elapsed();
+
+    let header = read_header(&mut server).and_then(|header| {
+        serde_json::from_slice(&header)
+            .map_err(|error| std::io::Error::new(ErrorKind::InvalidData, error))
+    });
+    let header = header.unwrap_or_else(|error| Response::Fail {
+        message: error.to_string(),
+    });
+
+    let uploaded = match header {
+        Response::Okay => {
+            match sent {
+                Some(sent) => log!(
+                    "{}: File uploaded successfully in {:.1}s ({})",
+                    output::server(ip),
+                    duration.as_secs_f64(),
+                    format_speed(sent, duration)
+                ),
+                None => log!(
+                    "{}: File already present, upload skipped",
+                    output::server(ip)
+                ),
+            }
+            emit(Event::UploadCompleted {
+                server: ip,
+                id,
+                bytes: sent.unwrap_or(0),
+                duration: duration.as_secs_f64(),
+            });
+            true
+        }
+        Response::Fail { message } => {
+            error!(
+                "{}: {}\nReason: {}",
+                output::server(ip),
+                paint("File upload failed", Color::Red),
+                message
+            );
+            emit(Event::UploadFailed {
+                server: ip,
+                id,
+                reason: &message,
+            });
+            false
+        }
+    };
+
+    pool::checkin(ip, server);
+
+    uploaded
+}
+
+fn upload_file(
+    ip: &str,
+    id: &str,
+    ttl: Option<u64>,
+    blender: Option<&BlenderRequirement>,
+    size: usize,
+    hash: &str,
+    blend: &Path,
+) -> bool {
+    match File::open(blend) {
+        Ok(file) => upload(ip, id, ttl, blender, size, hash, file),
+        Err(error) => {
+            error!(
+                "{}: {}\nReason: {}",
+                output::server(ip),
+                paint(format!("Could not open {}", blend.display()), Color::Red),
+                error
+            );
+            emit(Event::UploadFailed {
+                server: ip,
+                id,
+                reason: &error.to_string(),
+            });
+            false
+        }
+    }
+}
+
+fn upload_input(ip: &str, id: &str, pass: &str, image: &Path) -> Result<(), String> {
+    let file = File::open(image).map_err(|error| error.to_string())?;
+    let size = file.metadata().map_err(|error| error.to_string())?.len() as usize;
+    let name = image.file_name().unwrap().to_str().unwrap();
+
+    let request = to_header(
+        serde_json::to_vec(&Request::UploadInput {
+            id: String::from(id),
+            pass: String::from(pass),
+            name: String::from(name),
+            size,
+            user: user(),
+        })
+        .unwrap(),
+    );
+
+    let mut server = pool::try_checkout(ip, None).map_err(|error| error.to_string())?;
+    server
+        .write_all(&request)
+        .and_then(|()| transfer::send_chunked(file, &server, |_| {}))
+        .map_err(|error| error.to_string())?;
+
+    let header = read_header(&mut server).map_err(|error| error.to_string())?;
+    let header: Response = serde_json::from_slice(&header).unwrap();
+    pool::checkin(ip, server);
+
+    match header {
+        Response::Okay => Ok(()),
+        Response::Fail { message } => Err(message),
+    }
+}
+
+fn format_progress(progress: &RenderProgress) -> String {
+    let mut details = Vec::new();
+    if let Some(sample) = progress.sample {
+        details.push(format!("sample {}", sample));
+    }
+    if let Some(memory) = progress.memory {
+        details.push(format_size(memory));
+    }
+
+    let mut line = format!("Frame {}", progress.frame);
+    if let Some(percent) = progress.percent {
+        line += &format!(" at {:.0}%", percent);
+    }
+    if !details.is_empty() {
+        line += &format!(" [{}]", details.join(", "));
+    }
+
+    line
+}
+
+fn format_size(bytes: u64) -> String {
+    format!("{:.1} GiB", bytes as f64 / (1 << 30) as f64)
+}
+
+fn format_speed(bytes: usize, duration: Duration) -> String {
+    format!(
+        "{:.1} MiB/s",
+        bytes as f64 / (1 << 20) as f64 / duration.as_secs_f64().max(f64::EPSILON)
+    )
+}
+
+/// Reads one message: a little-endian `u16` length followed by that many bytes of JSON.
+pub fn read_header(stream: &mut impl Read) -> Result<Vec<u8>, std::io::Error> {
+    let mut len = [0; 2];
+    stream.read_exact(&mut len)?;
+
+    let mut header = vec![0; u16::from_le_bytes(len) as usize];
+    stream.read_exact(&mut header)?;
+
+    Ok(header)
+}
+
+/// Checksum compared across machines, so it must not depend on the toolchain that built brsp.
+fn hash(data: &[u8]) -> String {
+    blake3::hash(data).to_hex().to_string()
+}
+
+fn write_atomic(path: &Path, data: &[u8]) -> Result<(), std::io::Error> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".part");
+
+    let mut file = File::create(&temp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+
+    if file.metadata()?.len() != data.len() as u64 {
+        let _ = remove_file(&temp);
+        return Err(std::io::Error::other("Written frame is incomplete"));
+    }
+
+    rename(temp, path)
+}
+
+/// Frames `content` for the wire by prefixing its length, the inverse of `read_header`.
+pub fn to_header(mut content: Vec<u8>) -> Vec<u8> {
+    let mut header = u16::try_from(content.len()).unwrap().to_le_bytes().to_vec();
+    header.append(&mut content);
+
+    header
+}
+
+/// Settings a `Client` applies to every connection and request it makes.
+#[derive(Clone)]
+pub struct ClientOptions {
+    /// Pre-shared token sent to servers on every new connection.
+    pub auth_token: Option<String>,
+    /// Namespace jobs are stored under on servers, instead of the shared anonymous one.
+    pub user: Option<String>,
+    /// zstd level for uploads and rendered frames on servers that support it, 0 to disable.
+    pub compression: i32,
+    pub connect_attempts: u32,
+    pub connect_timeout: Duration,
+}
+
+impl Default for ClientOptions {
+    fn default() -> ClientOptions {
+        ClientOptions {
+            auth_token: None,
+            user: None,
+            compression: 0,
+            connect_attempts: CONNECT_ATTEMPTS,
+            connect_timeout: Duration::from_secs(CONNECT_TIMEOUT),
+        }
+    }
+}
+
+thread_local! {
+    static OPTIONS: RefCell<Arc<ClientOptions>> = RefCell::new(Arc::default());
+}
+
+/// Options of the client running on this thread.
+fn options() -> Arc<ClientOptions> {
+    OPTIONS.with(|options| options.borrow().clone())
+}
+
+/// Runs `f` with `options` as the client options of this thread, restoring the previous ones
+/// afterwards.
+pub fn with_options<T>(options: Arc<ClientOptions>, f: impl FnOnce() -> T) -> T {
+    struct Restore(Option<Arc<ClientOptions>>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            if let Some(previous) = self.0.take() {
+                OPTIONS.with(|options| *options.borrow_mut() = previous);
+            }
+        }
+    }
+
+    let _restore = Restore(Some(OPTIONS.with(|current| current.replace(options))));
+    f()
+}
+
+/// Wraps `f` so it runs with the client options of the calling thread, for work handed to
+/// other threads.
+fn inherit<T>(f: impl FnOnce() -> T) -> impl FnOnce() -> T {
+    let options = options();
+    move || with_options(options, f)
+}
+
+fn compression() -> Option<i32> {
+    Some(options().compression).filter(|level| *level > 0)
+}
+
+pub fn parse_compression(level: &str) -> Result<i32, String> {
+    if level == "off" {
+        return Ok(0);
+    }
+
+    match level.parse() {
+        Ok(level @ 1..=22) => Ok(level),
+        _ => Err(String::from(
+            "Expected a zstd level from 1 to 22 or \"off\"",
+        )),
+    }
+}
+
+fn user() -> Option<String> {
+    options().user.clone()
+}
+
+fn authenticate(mut stream: TcpStream) -> Result<TcpStream, std::io::Error> {
+    let token = match options().auth_token.clone() {
+        Some(token) => token,
+        None => {
+            return Ok(stream);
+        }
+    };
+
+    stream.write_all(&to_header(
+        serde_json::to_vec(&Request::Auth { token }).unwrap(),
+    ))?;
+
+    match serde_json::from_slice(&read_header(&mut stream)?) {
+        Ok(Response::Okay) => Ok(stream),
+        Ok(Response::Fail { message }) => {
+            Err(std::io::Error::new(ErrorKind::PermissionDenied, message))
+        }
+        Err(error) => Err(std::io::Error::new(ErrorKind::InvalidData, error)),
+    }
+}
+
+/// Connects to `ip`, retrying with exponential backoff on errors other than a rejected token.
+fn connect_retrying(ip: &str) -> Result<TcpStream, std::io::Error> {
+    let options = options();
+    let (attempts, timeout) = (options.connect_attempts.max(1), options.connect_timeout);
+    let mut backoff = CONNECT_BACKOFF;
+    let mut attempt = 1;
+
+    loop {
+        match try_connect(ip, Some(timeout)) {
+            Err(error) if attempt < attempts && error.kind() != ErrorKind::PermissionDenied => {
+                warn!(
+                    "{}: Could not connect ({}), retrying in {:.1}s",
+                    output::server(ip),
+                    error,
+                    backoff.as_secs_f64()
+                );
+                thread::sleep(backoff);
+                backoff *= 2;
+                attempt += 1;
+            }
+            result => {
+                return result;
+            }
+        }
+    }
+}
+
+fn try_connect(ip: &str, timeout: Option<Duration>) -> Result<TcpStream, std::io::Error> {
+    let addresses: Vec<SocketAddr> = match ip.to_socket_addrs() {
+        Ok(addresses) => addresses.collect(),
+        Err(error) => match error.kind() {
+            ErrorKind::InvalidInput => {
+                let host = ip
+                    .strip_prefix('[')
+                    .and_then(|host| host.strip_suffix(']'))
+                    .unwrap_or(ip);
+                (host, server::PORT).to_socket_addrs()?.collect()
+            }
+            _ => {
+                return Err(error);
+            }
+        },
+    };
+
+    let mut last_error = None;
+
+    for address in addresses {
+        let stream = match timeout {
+            None => TcpStream::connect(address),
+            Some(timeout) => TcpStream::connect_timeout(&address, timeout),
+        };
+
+        match stream {
+            Ok(stream) => {
+                return authenticate(stream);
+            }
+            Err(error) => {
+                last_error = Some(error);
+            }
+        }
+    }
+
+    Err(last_error
+        .unwrap_or_else(|| std::io::Error::new(ErrorKind::NotFound, "Address resolved to nothing")))
+}
+
+/// Lists the servers advertising themselves via mDNS on the local network.
+pub fn discover_servers(timeout: Duration) -> bool {
+    let servers = match mdns::discover(timeout) {
+        Ok(servers) => servers,
+        Err(message) => {
+            error!("Server discovery failed\nReason: {}", message);
+            return false;
+        }
+    };
+
+    if servers.is_empty() {
+        log!("No servers found");
+        return false;
+    }
+
+    let rows: Vec<Vec<String>> = servers
+        .into_iter()
+        .map(|server| {
+            let property = |key: &str| server.properties.get(key).cloned().unwrap_or_default();
+
+            vec![
+                output::server(&server.address.to_string()),
+                server.name.clone(),
+                property("version"),
+                property("workers"),
+                property("devices"),
+                property("slots"),
+            ]
+        })
+        .collect();
+
+    log!(
+        "{}",
+        output::table(
+            &["SERVER", "NAME", "VERSION", "WORKERS", "DEVICES", "SLOTS"],
+            &rows
+        )
+    );
+
+    true
+}
+
+/// Adds the servers advertising themselves via mDNS to a comma-separated server list.
+pub fn with_discovered(ips: &str) -> String {
+    let mut ips: Vec<String> = ips.split_terminator(',').map(String::from).collect();
+
+    match mdns::discover(DISCOVERY_TIMEOUT) {
+        Ok(servers) => {
+            log!("Discovered {} servers", servers.len());
+
+            for server in servers {
+                let address = server.address.to_string();
+                if !ips.contains(&address) {
+                    ips.push(address);
+                }
+            }
+        }
+        Err(message) => {
+            error!("Server discovery failed\nReason: {}", message);
+        }
+    }
+
+    ips.join(",")
+}
+
+pub fn query_servers(ips: &str, timeout: Duration) -> bool {
+    let results = query_all(ips, timeout);
+
+    logging::document(
+        &results
+            .iter()
+            .map(|(ip, result)| match result {
+                Ok(info) => serde_json::json!({ "server": ip, "info": info }),
+                Err(error) => serde_json::json!({ "server": ip, "error": error.to_string() }),
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    let mut rows = Vec::new();
+    let mut roots = Vec::new();
+    let mut blends = Vec::new();
+    let mut unreachable = Vec::new();
+
+    for (ip, result) in results {
+        match result {
+            Ok(info) => {
+                if let Some(skew) = clock_skew(&info)
+                    && skew.unsigned_abs() > MAX_CLOCK_SKEW
+                {
+                    warn!(
+                        "{}: {}",
+                        output::server(&ip),
+                        paint(
+                            format!("Clock differs from this machine by {}s", skew),
+                            Color::Yellow
+                        )
+                    );
+                }
+
+                for blend in &info.blends {
+                    blends.push(vec![
+                        output::server(&ip),
+                        blend.id.clone(),
+                        blend
+                            .hash
+                            .as_deref()
+                            .map(|hash| String::from(&hash[..hash.len().min(16)]))
+                            .unwrap_or_default(),
+                    ]);
+                }
+
+                for root in &info.roots {
+                    roots.push(vec![
+                        output::server(&ip),
+                        root.role.clone(),
+                        root.path.clone(),
+                        format_size(root.used),
+                        root.available.map(format_size).unwrap_or_default(),
+                    ]);
+                }
+
+                rows.push(vec![
+                    output::server(&ip),
+                    if info.installations.is_empty() {
+                        format!(
+                            "{}.{}.{}",
+                            info.version[0], info.version[1], info.version[2]
+                        )
+                    } else {
+                        info.installations
+                            .iter()
+                            .map(BlenderInstallation::to_string)
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    },
+                    info.compute_device_type,
+                    info.devices.active.join(", "),
+                    info.devices.inactive.join(", "),
+                    info.capabilities.join(", "),
+                ]);
+            }
+            Err(error) => unreachable.push(vec![output::server(&ip), paint(error, Color::Red)]),
+        }
+    }
+
+    if !rows.is_empty() {
+        log!(
+            "{}",
+            output::table(
+                &[
+                    "SERVER",
+                    "BLENDER",
+                    "DEVICE TYPE",
+                    "ACTIVE",
+                    "INACTIVE",
+                    "CAPABILITIES"
+                ],
+                &rows
+            )
+        );
+    }
+
+    if !roots.is_empty() {
+        log!(
+            "{}",
+            output::table(&["SERVER", "ROOT", "PATH", "USED", "FREE"], &roots)
+        );
+    }
+
+    if !blends.is_empty() {
+        log!("{}", output::table(&["SERVER", "ID", "HASH"], &blends));
+    }
+
+    if !unreachable.is_empty() {
+        log!(
+            "Unreachable servers:\n{}",
+            output::table(&["SERVER", "ERROR"], &unreachable)
+        );
+        return false;
+    }
+
+    true
+}
+
+fn clock_skew(info: &QueryResponse) -> Option<i64> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    info.time.map(|time| time as i64 - now as i64)
+}
+
+fn has_blend(ip: &str, id: &str, hash: &str) -> bool {
+    let request = to_header(serde_json::to_vec(&Request::Query { user: user() }).unwrap());
+
+    query(ip, &request, Duration::from_secs(5)).is_ok_and(|info| {
+        info.blends
+            .iter()
+            .any(|blend| blend.id == id && blend.hash.as_deref() == Some(hash))
+    })
+}
+
+/// Keeps the servers whose brpy reports the "viewport" capability, skipping the others.
+fn viewport_servers(ips: &str) -> String {
+    query_all(ips, Duration::from_secs(CONNECT_TIMEOUT))
+        .into_iter()
+        .filter_map(|(ip, result)| match result {
+            Ok(info)
+                if info
+                    .capabilities
+                    .iter()
+                    .any(|capability| capability == "viewport") =>
+            {
+                Some(ip)
+            }
+            Ok(_) => {
+                warn!(
+                    "{}: Skipped, viewport renders are not supported",
+                    output::server(&ip)
+                );
+                None
+            }
+            Err(error) => {
+                warn!(
+                    "{}: Skipped, could not check viewport support: {}",
+                    output::server(&ip),
+                    error
+                );
+                None
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Queries all `ips` in parallel, in the order they were given.
+fn query_all(ips: &str, timeout: Duration) -> Vec<(String, Result<QueryResponse, std::io::Error>)> {
+    let header = to_header(serde_json::to_vec(&Request::Query { user: user() }).unwrap());
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for ip in ips.split_terminator(',') {
+            let (header, results) = (&header, &results);
+            scope.spawn(inherit(move || {
+                let result = query(ip, header, timeout);
+                results.lock().unwrap().push((String::from(ip), result));
+            }));
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(ip, _)| ips.find(ip.as_str()));
+
+    results
+}
+
+fn query(ip: &str, request: &[u8], timeout: Duration) -> Result<QueryResponse, std::io::Error> {
+    let mut server = pool::try_checkout(ip, Some(timeout))?;
+    server.set_read_timeout(Some(timeout))?;
+    server.set_write_timeout(Some(timeout))?;
+
+    server.write_all(request)?;
+    let header = read_header(&mut server)?;
+    let header = serde_json::from_slice(&header)?;
+
+    server.set_read_timeout(None)?;
+    server.set_write_timeout(None)?;
+    pool::checkin(ip, server);
+
+    Ok(header)
+}
+
+pub fn list_jobs(ips: &str) -> bool {
+    let results = list_all(ips);
+
+    logging::document(
+        &results
+            .iter()
+            .map(|(ip, result)| match result {
+                Ok(jobs) => serde_json::json!({ "server": ip, "jobs": jobs }),
+                Err(error) => serde_json::json!({ "server": ip, "error": error }),
+            })
+            .collect::<Vec<_>>(),
+    );
+
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+
+    let mut rows = Vec::new();
+    let mut unreachable = Vec::new();
+
+    for (ip, result) in results {
+        match result {
+            Ok(jobs) => {
+                for job in jobs {
+                    rows.push(vec![
+                        output::server(&ip),
+                        job.id,
+                        format_size(job.bytes),
+                        format!("{}s ago", now.saturating_sub(job.last_used)),
+                        job.expires
+                            .map(|expires| format!("in {}s", expires.saturating_sub(now)))
+                            .unwrap_or_else(|| String::from("-")),
+                    ]);
+                }
+            }
+            Err(error) => unreachable.push(vec![output::server(&ip), paint(error, Color::Red)]),
+        }
+    }
+
+    if !rows.is_empty() {
+        log!(
+            "{}",
+            output::table(&["SERVER", "ID", "SIZE", "LAST USED", "EXPIRES"], &rows)
+        );
+    } else if unreachable.is_empty() {
+        log!(
+            "No jobs stored for {}",
+            user().as_deref().unwrap_or("anonymous")
+        );
+    }
+
+    if !unreachable.is_empty() {
+        log!(
+            "Unreachable servers:\n{}",
+            output::table(&["SERVER", "ERROR"], &unreachable)
+        );
+        return false;
+    }
+
+    true
+}
+
+/// Lists the jobs of all `ips` in parallel, in the order they were given.
+fn list_all(ips: &str) -> Vec<(String, Result<Vec<StoredJob>, String>)> {
+    let request = to_header(serde_json::to_vec(&Request::List { user: user() }).unwrap());
+    let results = Mutex::new(Vec::new());
+
+    thread::scope(|scope| {
+        for ip in ips.split_terminator(',') {
+            let (request, results) = (&request, &results);
+            scope.spawn(inherit(move || {
+                let result = list(ip, request);
+                results.lock().unwrap().push((String::from(ip), result));
+            }));
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by_key(|(ip, _)| ips.find(ip.as_str()));
+
+    results
+}
+
+fn list(ip: &str, request: &[u8]) -> Result<Vec<StoredJob>, String> {
+    let mut server = pool::try_checkout(ip, None).map_err(|error| error.to_string())?;
+    server
+        .write_all(request)
+        .map_err(|error| error.to_string())?;
+    let header = read_header(&mut server).map_err(|error| error.to_string())?;
+    pool::checkin(ip, server);
+
+    match serde_json::from_slice(&header).map_err(|error| error.to_string())? {
+        ListResponse::Okay { jobs } => Ok(jobs),
+        ListResponse::Fail { message } => Err(message),
+    }
+}
+
+pub fn job_status(ips: &str, id: &str, wait: bool, timeout: Option<Duration>) -> bool {
+    let request = to_header(
+        serde_json::to_vec(&Request::Status {
+            id: String::from(id),
+            user: user(),
+        })
+        .unwrap(),
+    );
+    let start = Instant::now();
+
+    loop {
+        let statuses: Vec<(&str, Result<StatusResponse, std::io::Error>)> = ips
+            .split_terminator(',')
+            .map(|ip| (ip, status(ip, &request)))
+            .collect();
+
+        let mut total = StatusResponse::default();
+        for status in statuses
+            .iter()
+            .filter_map(|(_, status)| status.as_ref().ok())
+        {
+            total.pending += status.pending;
+            total.active += status.active;
+            total.completed += status.completed;
+            total.failed += status.failed;
+            total.cache_size += status.cache_size;
+            total.cache_original += status.cache_original;
+            total.started_ago = total.started_ago.max(status.started_ago);
+            total.updated_ago = match (total.updated_ago, status.updated_ago) {
+                (Some(updated), Some(other)) => Some(updated.min(other)),
+                (updated, other) => updated.or(other),
+            };
+        }
+
+        let done = total.started_ago.is_some() && total.pending + total.active == 0;
+        let timed_out = timeout.is_some_and(|timeout| start.elapsed() >= timeout);
+
+        if !wait || done || timed_out {
+            let ago = |seconds: Option<u64>| match seconds {
+                None => String::from("-"),
+                Some(seconds) => format!("{}s ago", seconds),
+            };
+            let cache = |status: &StatusResponse| match status.cache_original {
+                0 => String::from("-"),
+                original => format!(
+                    "{:.1} MiB ({:.0}% saved)",
+                    status.cache_size as f64 / (1024 * 1024) as f64,
+                    100.0 - status.cache_size as f64 / original as f64 * 100.0
+                ),
+            };
+
+            let mut rows: Vec<Vec<String>> = statuses
+                .iter()
+                .map(|(ip, status)| match status {
+                    Ok(status) => vec![
+                        output::server(ip),
+                        status.pending.to_string(),
+                        status.active.to_string(),
+                        status.completed.to_string(),
+                        status.failed.to_string(),
+                        ago(status.started_ago),
+                        ago(status.updated_ago),
+                        cache(status),
+                    ],
+                    Err(error) => vec![
+                        output::server(ip),
+                        paint(error.to_string(), Color::Red),
+                        String::new(),
+                        String::new(),
+                        String::new(),
+                        String::new(),
+                        String::new(),
+                        String::new(),
+                    ],
+                })
+                .collect();
+
+            rows.push(vec![
+                String::from("TOTAL"),
+                total.pending.to_string(),
+                total.active.to_string(),
+                total.completed.to_string(),
+                total.failed.to_string(),
+                ago(total.started_ago),
+                ago(total.updated_ago),
+                cache(&total),
+            ]);
+
+            log!(
+                "{}",
+                output::table(
+                    &[
+                        "SERVER",
+                        "PENDING",
+                        "ACTIVE",
+                        "COMPLETED",
+                        "FAILED",
+                        "STARTED",
+                        "UPDATED",
+                        "CACHE"
+                    ],
+                    &rows
+                )
+            );
+
+            if done {
+                log!("\"{}\" is {}", id, paint("done", Color::Green));
+            } else if timed_out {
+                error!(
+                    "{}",
+                    paint(format!("Timed out waiting for \"{}\"", id), Color::Red)
+                );
+            }
+
+            return done || !wait;
+        }
+
+        thread::sleep(RECONNECT_INTERVAL);
+    }
+}
+
+fn status(ip: &str, request: &[u8]) -> Result<StatusResponse, std::io::Error> {
+    let mut server = pool::try_checkout(ip, Some(Duration::from_secs(5)))?;
+
+    server.write_all(request)?;
+    let header = read_header(&mut server)?;
+    let header = serde_json::from_slice(&header)?;
+    pool::checkin(ip, server);
+
+    Ok(header)
+}
+
+pub fn admin(ip: &str, token: Option<String>, action: AdminAction) {
+    let mut server = match connect_retrying(ip) {
+        Ok(server) => server,
+        Err(error) => {
+            error!(
+                "{}: {}\nReason: {}",
+                output::server(ip),
+                paint("Could not connect", Color::Red),
+                error
+            );
+            return;
+        }
+    };
+
+    let request = match action {
+        AdminAction::Dump => AdminRequest::Dump,
+        AdminAction::Slots => AdminRequest::Slots,
+        AdminAction::Disconnect { slot, ban } => AdminRequest::Disconnect { slot, ban },
+        AdminAction::Unban { address } => AdminRequest::Unban { address },
+    };
+
+    let request = to_header(serde_json::to_vec(&Request::Admin { token, request }).unwrap());
+    server.write_all(&request).unwrap();
+
+    let header = read_header(&mut server).unwrap();
+    let header: AdminResponse = serde_json::from_slice(&header).unwrap();
+
+    match header {
+        AdminResponse::Okay => {
+            log!("{}: Done", ip);
+        }
+        AdminResponse::Slots { slots } => {
+            let rows: Vec<Vec<String>> = slots
+                .into_iter()
+                .map(|slot| {
+                    vec![
+                        slot.slot.to_string(),
+                        slot.address,
+                        slot.state.label().to_string(),
+                        format!("{:.1}s", slot.seconds_in_state),
+                        slot.id.map(|id| format!("\"{}\"", id)).unwrap_or_default(),
+                        slot.frame
+                            .map(|frame| frame.to_string())
+                            .unwrap_or_default(),
+                        slot.bytes_pending.to_string(),
+                        slot.priority.to_string(),
+                    ]
+                })
+                .collect();
+
+            log!(
+                "{}:\n{}",
+                output::server(ip),
+                output::table(
+                    &[
+                        "SLOT", "ADDRESS", "STATE", "FOR", "JOB", "FRAME", "PENDING", "PRIORITY",
+                    ],
+                    &rows
+                )
+            );
+        }
+        AdminResponse::Fail { message } => {
+            error!("{}: Admin request failed\nReason: {}", ip, message);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_concurrent_uploads() {
+        let (free, returned) = (Mutex::new(2), Condvar::new());
+        let (running, most) = (Mutex::new(0), Mutex::new(0));
+
+        thread::scope(|scope| {
+            for _ in 0..6 {
+                let (free, returned, running, most) = (&free, &returned, &running, &most);
+                scope.spawn(move || {
+                    let _permit = UploadPermit::acquire(free, returned);
+
+                    let mut running_now = running.lock().unwrap();
+                    *running_now += 1;
+                    let mut most = most.lock().unwrap();
+                    *most = (*most).max(*running_now);
+                    drop((running_now, most));
+
+                    thread::sleep(Duration::from_millis(20));
+                    *running.lock().unwrap() -= 1;
+                });
+            }
+        });
+
+        assert_eq!(*most.lock().unwrap(), 2);
+        assert_eq!(*free.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn returns_upload_permits_on_panic() {
+        let (free, returned) = (Mutex::new(1), Condvar::new());
+
+        let upload = thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let _permit = UploadPermit::acquire(&free, &returned);
+                    panic!("upload failed");
+                })
+                .join()
+        });
+
+        assert!(upload.is_err());
+        assert_eq!(*free.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn fails_uploads_of_missing_files() {
+        let blend = Path::new("missing/shot.blend");
+
+        let uploaded = upload_blend(
+            "127.0.0.1:1",
+            String::from("shot"),
+            blend,
+            None,
+            None,
+            false,
+            None,
+        );
+        assert!(uploaded.unwrap_err().contains("missing/shot.blend"));
+        assert!(seed_blend("127.0.0.1:1", "shot", blend, None, None).is_err());
+    }
+
+    #[test]
+    fn parses_blender_requirements() {
+        let parse = |requirement: &str| requirement.parse::<BlenderRequirement>();
+
+        assert!(parse("").is_err());
+        assert!(parse("4.x").is_err());
+        assert!(parse(">=").is_err());
+        assert!(
+            parse("4.2")
+                == Ok(BlenderRequirement::Version {
+                    version: vec![4, 2]
+                })
+        );
+        assert!(
+            parse(">=4.1")
+                == Ok(BlenderRequirement::Minimum {
+                    version: vec![4, 1]
+                })
+        );
+        assert!(
+            parse("lts")
+                == Ok(BlenderRequirement::Name {
+                    name: String::from("lts")
+                })
+        );
+
+        for requirement in ["4.2", ">=3.6.5", "lts"] {
+            assert_eq!(parse(requirement).unwrap().to_string(), requirement);
+        }
+    }
+
+    #[test]
+    fn matches_blender_installations() {
+        let installation = BlenderInstallation {
+            name: String::from("lts"),
+            version: [4, 2, 3],
+        };
+        let matches = |requirement: &str| {
+            requirement
+                .parse::<BlenderRequirement>()
+                .unwrap()
+                .matches(&installation)
+        };
+
+        assert!(matches("lts"));
+        assert!(!matches("main"));
+        assert!(matches("4"));
+        assert!(matches("4.2"));
+        assert!(matches("4.2.3"));
+        assert!(!matches("4.1"));
+        assert!(!matches("4.20"));
+        assert!(matches(">=4.1"));
+        assert!(matches(">=4.2.3"));
+        assert!(!matches(">=4.2.4"));
+        assert!(!matches(">=5"));
+    }
+
+    #[test]
+    fn parses_frames_descending() {
+        assert_eq!(parse_frames("1,3..5"), Ok(vec![5, 4, 3, 1]));
+        assert_eq!(parse_frames("1..3,2,3"), Ok(vec![3, 2, 1]));
+        assert_eq!(parse_frames(""), Ok(vec![]));
+    }
+
+    #[test]
+    fn parses_frame_steps() {
+        assert_eq!(parse_frames("1..10/3"), Ok(vec![10, 7, 4, 1]));
+        assert_eq!(parse_frames("0..4/2,3"), Ok(vec![4, 3, 2, 0]));
+        assert_eq!(parse_frames("5..5/10"), Ok(vec![5]));
+    }
+
+    #[test]
+    fn rejects_invalid_frames() {
+        assert!(parse_frames("x").is_err());
+        assert!(parse_frames("1..").is_err());
+        assert!(parse_frames("1..2..3").is_err());
+        assert!(parse_frames("5..1").is_err());
+        assert!(parse_frames("1..10/x").is_err());
+        assert!(parse_frames("1..10/-1").is_err());
+    }
+
+    #[test]
+    fn rejects_zero_steps() {
+        assert_eq!(
+            parse_frames("1..10/0"),
+            Err(String::from("Step of \"1..10/0\" must be at least 1"))
+        );
+    }
+
+    #[test]
+    fn names_frames_by_pattern() {
+        assert_eq!(frame_stem(None, 7), "0007");
+        assert_eq!(frame_stem(Some("shot010_####.{ext}"), 7), "shot010_0007");
+        assert_eq!(frame_stem(Some("shot_##"), 123), "shot_123");
+    }
+
+    #[test]
+    fn parses_name_patterns() {
+        assert!(parse_name_pattern("shot010_####.{ext}").is_ok());
+        assert!(parse_name_pattern("####").is_ok());
+        assert!(parse_name_pattern("shot").is_err());
+        assert!(parse_name_pattern("a#_b#").is_err());
+        assert!(parse_name_pattern("../####").is_err());
+        assert!(parse_name_pattern("shot/####").is_err());
+    }
+}
@@ -0,0 +1,328 @@
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::Write,
+    path::Path,
+    sync::{
+        Mutex, OnceLock,
+        atomic::{AtomicBool, AtomicU8, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+static EVENTS: OnceLock<Mutex<File>> = OnceLock::new();
+static LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+static FORMAT: OnceLock<LogFormat> = OnceLock::new();
+static JSON: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    static CONTEXT: RefCell<Vec<(&'static str, String)>> = const { RefCell::new(Vec::new()) };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log_at {
+    ($level:expr, $($arg:tt)*) => {
+        if $crate::logging::enabled($level) {
+            $crate::logging::write_log($level, format!($($arg)*))
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __log {
+    ($($arg:tt)*) => {
+        $crate::__log_at!($crate::logging::Level::Info, $($arg)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __error {
+    ($($arg:tt)*) => {
+        $crate::__log_at!($crate::logging::Level::Error, $($arg)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __warn {
+    ($($arg:tt)*) => {
+        $crate::__log_at!($crate::logging::Level::Warn, $($arg)*)
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __debug {
+    ($($arg:tt)*) => {
+        $crate::__log_at!($crate::logging::Level::Debug, $($arg)*)
+    };
+}
+
+pub use __debug as debug;
+pub use __error as error;
+pub use __log as log;
+pub use __warn as warn;
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+pub struct Span {
+    depth: usize,
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        CONTEXT.with_borrow_mut(|context| context.truncate(self.depth));
+    }
+}
+
+impl Level {
+    /// Maps the number of `-v` and `-q` flags to a level, starting from `Info`.
+    pub fn from_verbosity(verbose: u8, quiet: u8) -> Level {
+        match (Level::Info as i16 + i16::from(verbose) - i16::from(quiet)).clamp(0, 3) {
+            0 => Level::Error,
+            1 => Level::Warn,
+            2 => Level::Info,
+            _ => Level::Debug,
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+        }
+    }
+}
+
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    UploadStarted {
+        server: &'a str,
+        id: &'a str,
+        bytes: usize,
+    },
+    UploadCompleted {
+        server: &'a str,
+        id: &'a str,
+        bytes: usize,
+        duration: f64,
+    },
+    UploadFailed {
+        server: &'a str,
+        id: &'a str,
+        reason: &'a str,
+    },
+    FrameStarted {
+        server: &'a str,
+        id: &'a str,
+        frame: usize,
+    },
+    FrameCompleted {
+        server: &'a str,
+        id: &'a str,
+        frame: usize,
+        bytes: usize,
+        duration: f64,
+    },
+    FrameFailed {
+        server: &'a str,
+        id: &'a str,
+        frame: usize,
+        reason: &'a str,
+    },
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    time: f64,
+
+    #[serde(flatten)]
+    event: Event<'a>,
+}
+
+/// With `json`, messages go to stderr and stdout only carries events and `document`s, one JSON
+/// value per line.
+pub fn init(
+    log_file: Option<&Path>,
+    events: Option<&Path>,
+    level: Level,
+    format: LogFormat,
+    json: bool,
+) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+    let _ = FORMAT.set(format);
+    JSON.store(json, Ordering::Relaxed);
+
+    let open = |path| {
+        Mutex::new(
+            OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .unwrap(),
+        )
+    };
+
+    if let Some(path) = log_file {
+        let _ = LOG_FILE.set(open(path));
+    }
+
+    if let Some(path) = events {
+        let _ = EVENTS.set(open(path));
+    }
+}
+
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn json() -> bool {
+    JSON.load(Ordering::Relaxed)
+}
+
+/// Prints `value` as a single line of JSON to stdout if JSON output is enabled.
+pub fn document(value: &impl Serialize) {
+    if json() {
+        println!("{}", serde_json::to_string(value).unwrap());
+    }
+}
+
+/// Attaches `key` to every message logged by the current thread until the returned guard is
+/// dropped.
+#[must_use]
+pub fn span(key: &'static str, value: impl Display) -> Span {
+    CONTEXT.with_borrow_mut(|context| {
+        let depth = context.len();
+        context.push((key, value.to_string()));
+
+        Span { depth }
+    })
+}
+
+pub fn write_log(level: Level, message: String) {
+    let context = CONTEXT.with_borrow(|context| {
+        context
+            .iter()
+            .map(|(key, value)| format!("{}={}", key, value))
+            .collect::<Vec<String>>()
+            .join(" ")
+    });
+    let prefix = if context.is_empty() {
+        String::new()
+    } else {
+        format!("[{}] ", context)
+    };
+
+    let line = match FORMAT.get().copied().unwrap_or_default() {
+        LogFormat::Text => format!("{}{}", prefix, message),
+        LogFormat::Json => {
+            let mut record = Map::new();
+            record.insert(String::from("time"), Value::from(unix_time()));
+            record.insert(String::from("level"), serde_json::to_value(level).unwrap());
+            record.insert(
+                String::from("message"),
+                Value::from(crate::output::strip(&message)),
+            );
+            CONTEXT.with_borrow(|context| {
+                for (key, value) in context {
+                    record.insert(String::from(*key), Value::from(value.as_str()));
+                }
+            });
+
+            Value::Object(record).to_string()
+        }
+    };
+
+    if json() {
+        eprintln!("{}", line);
+    } else {
+        println!("{}", line);
+    }
+
+    if let Some(file) = LOG_FILE.get() {
+        let _ = writeln!(
+            file.lock().unwrap(),
+            "[{}] {} {}{}",
+            timestamp(),
+            level.label(),
+            prefix,
+            crate::output::strip(&message)
+        );
+    }
+}
+
+pub fn emit(event: Event) {
+    let record = Record {
+        time: unix_time(),
+        event,
+    };
+    document(&record);
+
+    if let Some(file) = EVENTS.get() {
+        let mut line = serde_json::to_vec(&record).unwrap();
+        line.push(b'\n');
+
+        let _ = file.lock().unwrap().write_all(&line);
+    }
+}
+
+fn unix_time() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs_f64()
+}
+
+fn timestamp() -> String {
+    let seconds = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let (days, time) = (seconds / 86400, seconds % 86400);
+
+    let days = days as i64 + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
@@ -1,616 +1,629 @@
-use clap::{Parser, Subcommand};
-use serde::{Deserialize, Serialize};
+use brsp::{
+    AdminAction, BakeMap, BlenderRequirement, CONNECT_ATTEMPTS, CONNECT_TIMEOUT, Client,
+    ClientOptions, EPHEMERAL_TTL, OverwritePolicy, RenderSettings, UploadOptions, admin,
+    bake_textures, bench, cancel_frame, cancel_job, config, discover_servers, fetch_frame,
+    job_status, list_jobs,
+    logging::{self, Level, LogFormat, error, log},
+    manifest::Manifest,
+    output::{self, Color, ColorChoice, paint},
+    pack_blend, parse_compression, parse_frames, paths, protocol, query_servers, resume, server,
+    spool, still, stress, thumbnail, with_discovered, with_options,
+};
+use clap::{ArgAction, Parser, Subcommand};
+use serde::{Serialize, de::DeserializeOwned};
+use serde_json::Value;
 use std::{
-    env::set_current_dir,
-    fs::{create_dir, read, remove_file, write},
-    hash::{DefaultHasher, Hash, Hasher},
-    io::{ErrorKind, Read, Write},
-    net::{Ipv6Addr, TcpListener, TcpStream},
-    path::PathBuf,
+    fs::{create_dir_all, remove_file},
+    path::{Path, PathBuf},
     process,
-    sync::{Condvar, Mutex},
-    thread,
+    sync::{Arc, Mutex},
+    time::Duration,
 };
 
 #[derive(Parser)]
 struct Cli {
     #[command(subcommand)]
     command: Command,
+
+    #[arg(long, global = true)]
+    log_file: Option<PathBuf>,
+
+    #[arg(long, global = true)]
+    events: Option<PathBuf>,
+
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    verbose: u8,
+
+    #[arg(short, long, global = true, action = ArgAction::Count, conflicts_with = "verbose")]
+    quiet: u8,
+
+    #[arg(long, global = true, value_enum, default_value_t)]
+    log_format: LogFormat,
+
+    #[arg(long, global = true, value_enum, default_value_t)]
+    color: ColorChoice,
+
+    #[arg(long, global = true)]
+    auth_token: Option<String>,
+
+    #[arg(long, global = true, value_parser = protocol::parse_id)]
+    user: Option<String>,
+
+    #[arg(long, global = true, value_parser = parse_compression)]
+    compression: Option<i32>,
+
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[arg(long, global = true, default_value_t = CONNECT_ATTEMPTS)]
+    connect_attempts: u32,
+
+    #[arg(long, global = true, default_value_t = CONNECT_TIMEOUT)]
+    connect_timeout: u64,
 }
 
 #[derive(Subcommand)]
 enum Command {
     Upload {
+        #[arg(value_parser = config::parse_ips)]
         ips: String,
+
+        #[arg(value_parser = protocol::parse_id)]
         id: String,
+
         blend: PathBuf,
-    },
-    Render {
-        ips: String,
-        output_dir: PathBuf,
-        id: String,
-        frames: String,
-    },
-    Delete,
-    Serve {
-        brpy: PathBuf,
-        work_dir: PathBuf,
 
-        #[arg(short, long)]
-        blender: Option<PathBuf>,
-    },
-    Query {
-        ips: String,
-    },
-}
+        #[arg(short, long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+        max_concurrent_uploads: Option<usize>,
 
-#[derive(Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "lowercase")]
-enum Request {
-    Upload { id: String, size: usize },
-    Render,
-    Delete,
-    Query,
-}
+        #[arg(short, long)]
+        ttl: Option<u64>,
 
-#[derive(Serialize, Deserialize)]
-struct FrameRequest {
-    id: String,
-    frame: usize,
-}
+        #[arg(long)]
+        seed: bool,
 
-#[derive(Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "lowercase")]
-enum Response {
-    Okay,
-    Fail { message: String },
-}
+        #[arg(long)]
+        skip_present: bool,
 
-#[derive(Serialize, Deserialize)]
-enum RenderAcceptResponse {
-    Accept,
-    Reject,
-}
+        #[arg(long)]
+        pack: bool,
 
-#[derive(Serialize, Deserialize)]
-enum RenderResponse {
-    Okay { size: usize, extension: String },
-    Fail,
-}
+        #[arg(long, default_value = "blender")]
+        blender: PathBuf,
 
-#[derive(Serialize, Deserialize)]
-struct QueryResponse {
-    version: [u8; 3],
-    compute_device_type: String,
-    devices: ComputeDeviceList,
-}
+        #[arg(long)]
+        blender_version: Option<BlenderRequirement>,
 
-#[derive(Serialize, Deserialize, Clone)]
-struct ComputeDeviceList {
-    active: Vec<String>,
-    inactive: Vec<String>,
-}
+        #[arg(long)]
+        template: Option<String>,
 
-#[derive(Serialize)]
-#[serde(tag = "type", rename_all = "lowercase")]
-enum BrpyRequest {
-    Render {
-        blend: PathBuf,
-        frame: usize,
-        output: PathBuf,
+        #[arg(long)]
+        auto: bool,
     },
-    Query,
-}
+    Render {
+        #[arg(value_parser = config::parse_ips)]
+        ips: String,
 
-#[derive(Deserialize)]
-#[serde(tag = "type", rename_all = "lowercase")]
-enum BrpyRenderResponse {
-    Okay { image: PathBuf },
-    Fail,
-}
+        #[arg(value_parser = config::parse_output_dir)]
+        output_dir: PathBuf,
 
-fn main() {
-    let args = Cli::parse();
+        #[arg(value_parser = protocol::parse_id)]
+        id: String,
 
-    match args.command {
-        Command::Upload { ips, id, blend } => {
-            let ips = ips.split_terminator(',');
-
-            let mut blend = read(blend).unwrap();
-            let mut request = to_header(
-                serde_json::to_vec(&Request::Upload {
-                    id,
-                    size: blend.len(),
-                })
-                .unwrap(),
-            );
-            request.append(&mut blend);
+        frames: String,
 
-            thread::scope(|scope| {
-                for ip in ips {
-                    scope.spawn(|| {
-                        upload(ip, &request);
-                    });
-                }
-            });
-        }
-        Command::Render {
-            ips,
-            output_dir,
-            id,
-            frames,
-        } => {
-            set_current_dir(output_dir).unwrap();
-
-            let frames = {
-                let mut list = Vec::new();
-
-                for range in frames.split_terminator(',') {
-                    let frame = range.parse::<usize>();
-                    match frame {
-                        Ok(frame) => {
-                            list.push(frame);
-                        }
-                        Err(_) => {
-                            let range: Vec<&str> = range.split_terminator("..").collect();
-                            let start: usize = range[0].parse().unwrap();
-                            let end: usize = range[1].parse().unwrap();
-
-                            list.append(&mut (start..=end).collect());
-                        }
-                    }
-                }
+        #[command(flatten)]
+        settings: RenderSettings,
 
-                list.sort();
-                list.dedup();
-                list.reverse();
+        #[arg(long)]
+        no_create: bool,
 
-                Mutex::new(list)
-            };
+        #[arg(long, value_enum, default_value_t)]
+        overwrite: OverwritePolicy,
 
-            thread::scope(|scope| {
-                for ip in ips.split_terminator(',') {
-                    scope.spawn(|| {
-                        render(ip, &id, &frames);
-                    });
-                }
-            })
-        }
-        Command::Delete => {
-            todo!();
-        }
-        Command::Query { ips } => {
-            let header = to_header(serde_json::to_vec(&Request::Query).unwrap());
-
-            thread::scope(|scope| {
-                for ip in ips.split_terminator(',') {
-                    scope.spawn(|| {
-                        query(ip, &header);
-                    });
-                }
-            });
-        }
-        Command::Serve {
-            brpy,
-            work_dir,
-            blender,
-        } => {
-            if !brpy.is_file() {
-                panic!(
-                    "BRPy script {} either does not exist, access is not permitted or it's not a file",
-                    brpy.display()
-                );
-            }
+        #[arg(long, conflicts_with = "overwrite")]
+        skip_existing: bool,
 
-            let blender = match blender {
-                None => PathBuf::from("blender"),
-                Some(blender) => blender.canonicalize().unwrap(),
-            };
+        #[arg(long)]
+        template: Option<String>,
 
-            set_current_dir(work_dir).unwrap();
+        #[arg(long)]
+        auto: bool,
+    },
+    BakeTextures {
+        ip: String,
 
-            if let Err(error) = create_dir("anonymous") {
-                match error.kind() {
-                    ErrorKind::AlreadyExists => {}
-                    _ => {
-                        panic!("{}", error);
-                    }
-                }
-            }
+        #[arg(value_parser = config::parse_output_dir)]
+        output_dir: PathBuf,
 
-            let listener = match TcpListener::bind((Ipv6Addr::UNSPECIFIED, 21816)) {
-                Ok(listener) => listener,
-                Err(_) => TcpListener::bind((Ipv6Addr::UNSPECIFIED, 0)).unwrap(),
-            };
+        #[arg(value_parser = protocol::parse_id)]
+        id: String,
 
-            let mut brpy = {
-                let listener = TcpListener::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
-                let port = listener.local_addr().unwrap().port();
-
-                process::Command::new(blender)
-                    .args([
-                        "--background",
-                        "--python",
-                        brpy.to_str().unwrap(),
-                        "--",
-                        &port.to_string(),
-                    ])
-                    .spawn()
-                    .unwrap();
-
-                listener.accept().unwrap().0
-            };
+        #[arg(short, long, value_delimiter = ',', required = true)]
+        objects: Vec<String>,
 
-            let info: QueryResponse = {
-                let request = to_header(serde_json::to_vec(&BrpyRequest::Query).unwrap());
-                brpy.write_all(&request).unwrap();
+        #[arg(short, long, value_delimiter = ',', default_value = "combined")]
+        maps: Vec<BakeMap>,
 
-                serde_json::from_slice(&read_header(&mut brpy).unwrap()).unwrap()
-            };
+        #[arg(short, long, default_value_t = 1024)]
+        resolution: u32,
+    },
+    Thumbnails {
+        ip: String,
 
-            let render_requesters: Mutex<Vec<Option<TcpStream>>> = Mutex::new(vec![None]);
-            let notifier = Condvar::new();
+        #[arg(value_parser = config::parse_output_dir)]
+        output_dir: PathBuf,
 
-            thread::scope(|scope| {
-                scope.spawn(|| {
-                    worker_brpy(brpy, &render_requesters, &notifier);
-                });
+        #[arg(value_parser = protocol::parse_id)]
+        id: String,
 
-                println!(
-                    "Listening on port {}",
-                    listener.local_addr().unwrap().port()
-                );
+        frames: String,
+    },
+    FetchFrames {
+        ip: String,
 
-                for stream in listener.incoming() {
-                    match stream {
-                        Ok(stream) => {
-                            scope.spawn(|| {
-                                handle_client(stream, &info, &render_requesters, &notifier);
-                            });
-                        }
-                        Err(error) => {
-                            println!("Failed to establish new connection: {}", error);
-                        }
-                    }
-                }
-            })
-        }
-    }
-}
+        #[arg(value_parser = config::parse_output_dir)]
+        output_dir: PathBuf,
 
-fn handle_client(
-    mut client: TcpStream,
-    info: &QueryResponse,
-    render_requesters: &Mutex<Vec<Option<TcpStream>>>,
-    notifier: &Condvar,
-) {
-    loop {
-        let request = serde_json::from_slice(&read_header(&mut client).unwrap()).unwrap();
-
-        match request {
-            Request::Upload { id, size } => {
-                let mut blend = vec![0; size];
-                client.read_exact(&mut blend).unwrap();
-
-                let mut hasher = DefaultHasher::new();
-                id.hash(&mut hasher);
-                let hash = hasher.finish();
-
-                let _ = create_dir(format!("anonymous/{}", hash));
-                let header = match write(format!("anonymous/{0}/{0}.blend", hash), blend) {
-                    Ok(()) => serde_json::to_vec(&Response::Okay).unwrap(),
-                    Err(_) => serde_json::to_vec(&Response::Fail {
-                        message: "Could not save file".to_string(),
-                    })
-                    .unwrap(),
-                };
-
-                let response = to_header(header);
-                client.write_all(&response).unwrap();
-
-                println!("Saved .blend file with ID \"{}\"", id);
-                break;
-            }
-            Request::Render => {
-                let requester = Some(client);
-
-                let mut free_slot = 0;
-                let mut free_slot_found = false;
-
-                {
-                    let mut render_requesters = render_requesters.lock().unwrap();
-                    let len = render_requesters.len();
-
-                    for slot in 0..len {
-                        if render_requesters[slot].is_none() {
-                            free_slot_found = true;
-                            free_slot = slot;
-                            break;
-                        }
-                    }
+        #[arg(value_parser = protocol::parse_id)]
+        id: String,
 
-                    if free_slot_found {
-                        render_requesters[free_slot] = requester;
-                        println!("Put new render requester in slot {}", free_slot);
-                    } else {
-                        render_requesters.push(requester);
-                        println!("Created render slot {} for new render requester", len);
-                    }
-                }
+        frames: String,
+    },
+    CancelFrame {
+        ip: String,
 
-                notifier.notify_all();
+        #[arg(value_parser = protocol::parse_id)]
+        id: String,
 
-                return;
-            }
-            Request::Delete => {
-                todo!();
-            }
-            Request::Query => {
-                let response = to_header(
-                    serde_json::to_vec(&QueryResponse {
-                        version: info.version,
-                        compute_device_type: info.compute_device_type.clone(),
-                        devices: info.devices.clone(),
-                    })
-                    .unwrap(),
-                );
+        frame: Option<usize>,
+    },
+    Cancel {
+        #[arg(value_parser = config::parse_ips)]
+        ips: String,
 
-                client.write_all(&response).unwrap();
-            }
-        }
-    }
-}
+        #[arg(value_parser = protocol::parse_id)]
+        id: String,
+    },
+    Delete {
+        #[arg(value_parser = config::parse_ips)]
+        ips: String,
 
-fn render(ip: &str, id: &str, frames: &Mutex<Vec<usize>>) {
-    let mut server = connect(ip);
+        #[arg(value_parser = protocol::parse_id)]
+        id: String,
+    },
+    Serve(server::Options),
+    Stress(stress::Options),
+    Bench(bench::Options),
+    RenderStill(still::Options),
+    Resume {
+        session: PathBuf,
+    },
+    Nodectl {
+        #[command(subcommand)]
+        action: server::NodeAction,
 
-    let request = to_header(serde_json::to_vec(&Request::Render).unwrap());
-    server.write_all(&request).unwrap();
+        #[arg(long, default_value_t = server::CONTROL_PORT)]
+        port: u16,
+    },
+    Query {
+        #[arg(default_value = "", value_parser = config::parse_ips)]
+        ips: String,
 
-    loop {
-        if frames.lock().unwrap().is_empty() {
-            return;
-        }
+        #[arg(short, long, default_value_t = 5)]
+        timeout: u64,
+
+        #[arg(long)]
+        auto: bool,
+    },
+    Discover {
+        #[arg(short, long, default_value_t = 3)]
+        timeout: u64,
+    },
+    List {
+        #[arg(value_parser = config::parse_ips)]
+        ips: String,
+    },
+    Status {
+        #[arg(value_parser = config::parse_ips)]
+        ips: String,
 
-        let response = read_header(&mut server).unwrap();
-        let response = serde_json::from_slice(&response).unwrap();
+        #[arg(value_parser = protocol::parse_id)]
+        id: String,
 
-        match response {
-            RenderAcceptResponse::Accept => {
-                println!("Render request accepted");
+        #[arg(short, long)]
+        wait: bool,
 
-                let frame = match frames.lock().unwrap().pop() {
-                    None => {
-                        return;
-                    }
-                    Some(frame) => frame,
-                };
-
-                let request = to_header(
-                    serde_json::to_vec(&FrameRequest {
-                        id: String::from(id),
-                        frame,
-                    })
-                    .unwrap(),
-                );
-                server.write_all(&request).unwrap();
+        #[arg(short, long)]
+        timeout: Option<u64>,
+    },
+    Admin {
+        ip: String,
 
-                let header = read_header(&mut server).unwrap();
-                let header = serde_json::from_slice(&header).unwrap();
+        #[arg(short, long)]
+        token: Option<String>,
 
-                match header {
-                    RenderResponse::Okay { size, extension } => {
-                        let mut image = vec![0; size];
-                        server.read_exact(&mut image).unwrap();
+        #[command(subcommand)]
+        action: AdminAction,
+    },
+    Submit {
+        manifest: PathBuf,
+    },
+    Spool {
+        spool_dir: PathBuf,
 
-                        let image_name = format!("{:04}.{}", frame, extension);
-                        write(&image_name, image).unwrap();
-                        println!("Saved frame {} as {}", frame, image_name);
-                    }
-                    RenderResponse::Fail => {
-                        todo!();
-                    }
-                }
-            }
-            RenderAcceptResponse::Reject => {
-                todo!();
-            }
-        }
-    }
-}
+        #[arg(short, long, default_value_t = 1)]
+        parallel: usize,
+
+        #[arg(long, default_value_t = spool::PORT)]
+        port: u16,
+    },
+    Queue {
+        manifest: PathBuf,
 
-fn upload(ip: &str, request: &[u8]) {
-    let mut server = connect(ip);
-    server.write_all(request).unwrap();
+        #[arg(long, default_value_t = spool::PORT)]
+        port: u16,
+    },
+    Jobs {
+        #[arg(short, long)]
+        cancel: Option<u64>,
 
-    let header = read_header(&mut server).unwrap();
-    let header: Response = serde_json::from_slice(&header).unwrap();
+        #[arg(long, default_value_t = spool::PORT)]
+        port: u16,
+    },
+}
 
-    match header {
-        Response::Okay => {
-            println!("File uploaded successfully");
-        }
-        Response::Fail { message } => {
-            println!("File upload failed\nReason: {}", message);
+fn resolve_output_dir(path: &Path) -> PathBuf {
+    match paths::output_dir(path) {
+        Ok(path) => path,
+        Err(message) => {
+            error!("{}", message);
+            process::exit(1);
         }
     }
 }
 
-fn read_header(stream: &mut TcpStream) -> Result<Vec<u8>, std::io::Error> {
-    let mut len = [0; 2];
-    stream.read_exact(&mut len)?;
-
-    let mut header = vec![0; u16::from_le_bytes(len) as usize];
-    stream.read_exact(&mut header)?;
-
-    Ok(header)
+fn frame_list(frames: &str) -> Vec<usize> {
+    match parse_frames(frames) {
+        Ok(frames) => frames,
+        Err(message) => {
+            error!("{}", message);
+            process::exit(1);
+        }
+    }
 }
 
-fn to_header(mut content: Vec<u8>) -> Vec<u8> {
-    let mut header = u16::try_from(content.len()).unwrap().to_le_bytes().to_vec();
-    header.append(&mut content);
-
-    header
+fn load_template(name: Option<&str>) -> Value {
+    match name.map(config::template) {
+        None => Value::Null,
+        Some(Ok(template)) => template,
+        Some(Err(message)) => {
+            error!("Loading template failed\nReason: {}", message);
+            process::exit(1);
+        }
+    }
 }
 
-fn connect(ip: &str) -> TcpStream {
-    match TcpStream::connect(ip) {
-        Ok(stream) => stream,
-        Err(error) => match error.kind() {
-            ErrorKind::InvalidInput => TcpStream::connect((ip, 21816)).unwrap(),
-            _ => {
-                panic!("{:?}", error);
-            }
-        },
+fn from_template<T: Serialize + DeserializeOwned + Default>(
+    template: &Value,
+    key: &str,
+    value: T,
+) -> T {
+    match config::apply(template, key, value) {
+        Ok(value) => value,
+        Err(message) => {
+            error!("{}", message);
+            process::exit(1);
+        }
     }
 }
 
-fn query(ip: &str, request: &[u8]) {
-    let mut server = connect(ip);
-    server.write_all(request).unwrap();
-
-    let header = read_header(&mut server).unwrap();
-    let header: QueryResponse = serde_json::from_slice(&header).unwrap();
-
-    let mut output = format!(
-        "{}:\n    Blender version: {}.{}.{}\n    Compute device type: {}",
-        ip, header.version[0], header.version[1], header.version[2], header.compute_device_type
+fn main() {
+    let args = Cli::parse();
+    logging::init(
+        args.log_file.as_deref(),
+        args.events.as_deref(),
+        Level::from_verbosity(args.verbose, args.quiet),
+        args.log_format,
+        args.json,
     );
+    output::init(args.color);
 
-    let active_not_empty = !header.devices.active.is_empty();
-    let inactive_not_empty = !header.devices.inactive.is_empty();
-
-    if active_not_empty || inactive_not_empty {
-        output += "\n    Devices:";
-
-        if active_not_empty {
-            output += "\n        Active:";
-            for device in header.devices.active {
-                output += &format!("\n            {}", device);
-            }
-        }
-
-        if inactive_not_empty {
-            output += "\n        Inactive:";
-            for device in header.devices.inactive {
-                output += &format!("\n            {}", device);
-            }
+    let config = match config::load() {
+        Ok(config) => config,
+        Err(message) => {
+            error!("{}", message);
+            process::exit(1);
         }
+    };
+
+    let user = args.user.or(config.user);
+    if let Some(user) = &user
+        && let Err(message) = protocol::parse_id(user)
+    {
+        error!("Invalid user \"{}\": {}", user, message);
+        process::exit(1);
     }
 
-    println!("{}", output);
+    let options = ClientOptions {
+        auth_token: args.auth_token.or(config.auth_token),
+        user,
+        compression: args.compression.unwrap_or(0),
+        connect_attempts: args.connect_attempts,
+        connect_timeout: Duration::from_secs(args.connect_timeout.max(1)),
+    };
+
+    with_options(Arc::new(options.clone()), || run(args.command, &options));
 }
 
-fn worker_brpy(
-    mut brpy: TcpStream,
-    requesters: &Mutex<Vec<Option<TcpStream>>>,
-    notifier: &Condvar,
-) {
-    let mut slot = 0;
-
-    'outer: loop {
-        let frame_request: FrameRequest = {
-            let old_slot = slot;
-            let mut requesters = requesters.lock().unwrap();
-
-            let frame_request = {
-                let len = requesters.len();
-                let client = loop {
-                    slot = (slot + 1) % len;
-                    match &mut requesters[slot] {
-                        None => {
-                            if slot == old_slot {
-                                println!("Awaiting further render requests");
-                                let _requesters = notifier.wait(requesters).unwrap();
-                                continue 'outer;
-                            }
-                        }
-                        Some(requester) => {
-                            break requester;
-                        }
+fn run(command: Command, options: &ClientOptions) {
+    match command {
+        Command::Upload {
+            ips,
+            id,
+            blend,
+            max_concurrent_uploads,
+            ttl,
+            seed,
+            skip_present,
+            pack,
+            blender,
+            blender_version,
+            template,
+            auto,
+        } => {
+            let ips = if auto { with_discovered(&ips) } else { ips };
+            let template = load_template(template.as_deref());
+            let max_concurrent_uploads =
+                from_template(&template, "max_concurrent_uploads", max_concurrent_uploads);
+            let ttl = from_template(&template, "ttl", ttl);
+
+            let packed = if pack {
+                match pack_blend(&blend, &blender) {
+                    Ok(packed) => Some(packed),
+                    Err(message) => {
+                        error!("Packing failed\nReason: {}", message);
+                        process::exit(1);
                     }
-                };
+                }
+            } else {
+                None
+            };
+            let blend = packed.clone().unwrap_or(blend);
 
-                let request = to_header(serde_json::to_vec(&RenderAcceptResponse::Accept).unwrap());
-                let _ = client.write_all(&request);
+            let ttl = if blend == Path::new("-") {
+                ttl.or(Some(EPHEMERAL_TTL))
+            } else {
+                ttl
+            };
 
-                read_header(client)
+            let upload = UploadOptions {
+                ttl,
+                max_concurrent_uploads,
+                skip_present,
+                seed,
+                blender: blender_version,
             };
+            let uploaded = Client::with_options(&ips, options.clone()).upload(&id, &blend, &upload);
 
-            match frame_request {
-                Err(_) => {
-                    requesters[slot] = None;
-                    continue;
-                }
-                Ok(frame_request) => serde_json::from_slice(&frame_request).unwrap(),
+            if let Some(packed) = packed {
+                let _ = remove_file(packed);
             }
-        };
-
-        println!("Rendering slot {}", slot);
-
-        let mut hasher = DefaultHasher::new();
-        frame_request.id.hash(&mut hasher);
-        let hash = hasher.finish();
 
-        let blend = PathBuf::from(format!("anonymous/{0}/{0}.blend", hash));
-        if !blend.is_file() {
-            println!("No .blend file found for ID \"{}\"", frame_request.id);
-
-            let response = to_header(serde_json::to_vec(&RenderResponse::Fail).unwrap());
+            if let Err(message) = uploaded {
+                error!("{}", message);
+                process::exit(1);
+            }
+        }
+        Command::Render {
+            ips,
+            output_dir,
+            id,
+            frames,
+            settings,
+            no_create,
+            overwrite,
+            skip_existing,
+            template,
+            auto,
+        } => {
+            let ips = if auto { with_discovered(&ips) } else { ips };
+            let template = load_template(template.as_deref());
+            let session = settings.session.clone();
+            let settings = RenderSettings {
+                session,
+                ..from_template(&template, "settings", settings)
+            };
+            let overwrite = if skip_existing {
+                OverwritePolicy::Skip
+            } else {
+                from_template(&template, "overwrite", overwrite)
+            };
 
-            let requesters = &mut requesters.lock().unwrap();
-            let client = requesters[slot].as_mut().unwrap();
-            let _ = client.write_all(&response);
+            let output_dir = resolve_output_dir(&output_dir);
+            if !no_create {
+                create_dir_all(&output_dir).unwrap();
+            }
 
-            continue;
+            let rendered = Client::with_options(&ips, options.clone()).render(
+                &id,
+                &output_dir,
+                &frame_list(&frames),
+                &settings,
+                overwrite,
+                &|_, _| {},
+            );
+            if rendered.is_err() {
+                process::exit(1);
+            }
         }
-
-        let request = to_header(
-            serde_json::to_vec(&BrpyRequest::Render {
-                blend,
-                frame: frame_request.frame,
-                output: format!("anonymous/{}/render", hash).into(),
-            })
-            .unwrap(),
-        );
-
-        brpy.write_all(&request).unwrap();
-        let response = serde_json::from_slice(&read_header(&mut brpy).unwrap()).unwrap();
-
-        match response {
-            BrpyRenderResponse::Okay { image } => {
-                let extension = String::from(image.extension().unwrap().to_str().unwrap());
-                let mut image_data = read(&image).unwrap();
-
-                let mut response = to_header(
-                    serde_json::to_vec(&RenderResponse::Okay {
-                        size: image_data.len(),
-                        extension,
-                    })
-                    .unwrap(),
+        Command::BakeTextures {
+            ip,
+            output_dir,
+            id,
+            objects,
+            maps,
+            resolution,
+        } => {
+            let output_dir = resolve_output_dir(&output_dir);
+            if let Err(error) = bake_textures(&ip, &output_dir, id, objects, maps, resolution) {
+                error!(
+                    "{}: {}\nReason: {}",
+                    output::server(&ip),
+                    paint("Baking failed", Color::Red),
+                    error
                 );
-                response.append(&mut image_data);
-
-                {
-                    let mut requesters = requesters.lock().unwrap();
-                    let client = &mut requesters[slot].as_ref().unwrap();
-
-                    if { client.write_all(&response) }.is_err() {
-                        println!("Cannot reach client, discarding frame");
-                        requesters[slot] = None;
-                    } else {
-                        println!(
-                            "Rendered frame {} of \"{}\" sent to client",
-                            frame_request.frame, frame_request.id
-                        );
-                    }
+                process::exit(1);
+            }
+        }
+        Command::Thumbnails {
+            ip,
+            output_dir,
+            id,
+            frames,
+        } => {
+            let output_dir = resolve_output_dir(&output_dir);
+            for frame in frame_list(&frames).into_iter().rev() {
+                if let Err(error) = thumbnail(&ip, &output_dir, &id, frame) {
+                    error!(
+                        "{}: {}\nReason: {}",
+                        output::server(&ip),
+                        paint(format!("No thumbnail for frame {}", frame), Color::Red),
+                        error
+                    );
+                    process::exit(1);
                 }
+            }
+        }
+        Command::FetchFrames {
+            ip,
+            output_dir,
+            id,
+            frames,
+        } => {
+            let output_dir = resolve_output_dir(&output_dir);
+            create_dir_all(&output_dir).unwrap();
+
+            for frame in frame_list(&frames).into_iter().rev() {
+                if let Err(error) = fetch_frame(&ip, &output_dir, &id, frame) {
+                    error!(
+                        "{}: {}\nReason: {}",
+                        output::server(&ip),
+                        paint(format!("Fetching frame {} failed", frame), Color::Red),
+                        error
+                    );
+                    process::exit(1);
+                }
+            }
+        }
+        Command::CancelFrame { ip, id, frame } => match cancel_frame(&ip, &id, frame) {
+            Ok(()) => {
+                log!("{}: Cancelled render of \"{}\"", output::server(&ip), id);
+            }
+            Err(message) => {
+                error!(
+                    "{}: {}\nReason: {}",
+                    output::server(&ip),
+                    paint("Cancelling failed", Color::Red),
+                    message
+                );
+            }
+        },
+        Command::Cancel { ips, id } => {
+            if !cancel_job(&ips, &id) {
+                process::exit(1);
+            }
+        }
+        Command::Delete { ips, id } => {
+            match Client::with_options(&ips, options.clone()).delete(&id) {
+                Ok(()) => log!("Deleted \"{}\"", id),
+                Err(message) => {
+                    error!(
+                        "{}\nReason: {}",
+                        paint("Deleting failed", Color::Red),
+                        message
+                    );
+                    process::exit(1);
+                }
+            }
+        }
+        Command::Query { ips, timeout, auto } => {
+            let ips = if auto { with_discovered(&ips) } else { ips };
+            if !query_servers(&ips, Duration::from_secs(timeout)) {
+                process::exit(1);
+            }
+        }
+        Command::Discover { timeout } => {
+            if !discover_servers(Duration::from_secs(timeout)) {
+                process::exit(1);
+            }
+        }
+        Command::List { ips } => {
+            if !list_jobs(&ips) {
+                process::exit(1);
+            }
+        }
+        Command::Status {
+            ips,
+            id,
+            wait,
+            timeout,
+        } => {
+            if !job_status(&ips, &id, wait, timeout.map(Duration::from_secs)) {
+                process::exit(1);
+            }
+        }
+        Command::Submit { manifest } => {
+            let manifest = Manifest::load(&manifest);
+            let frames = Mutex::new(frame_list(&manifest.frames));
 
-                let _ = remove_file(image);
+            if let Err(message) = manifest.run(&frames, || false) {
+                error!("Job failed\nReason: {}", message);
+                process::exit(1);
             }
-            BrpyRenderResponse::Fail => {
-                todo!();
+        }
+        Command::Spool {
+            spool_dir,
+            parallel,
+            port,
+        } => {
+            spool::serve(&spool_dir, parallel, port);
+        }
+        Command::Queue { manifest, port } => {
+            spool::queue(&manifest, port);
+        }
+        Command::Jobs { cancel, port } => {
+            spool::jobs(cancel, port);
+        }
+        Command::Serve(options) => {
+            server::serve(options);
+        }
+        Command::Stress(options) => {
+            stress::stress(options);
+        }
+        Command::Bench(options) => {
+            if !bench::bench(options) {
+                process::exit(1);
             }
         }
+        Command::Nodectl { action, port } => {
+            if let Err(message) = server::nodectl(action, port) {
+                error!("Node control failed\nReason: {}", message);
+                process::exit(1);
+            }
+        }
+        Command::Resume { session } => {
+            if !resume(&session) {
+                process::exit(1);
+            }
+        }
+        Command::RenderStill(options) => {
+            if !still::render_still(options) {
+                process::exit(1);
+            }
+        }
+        Command::Admin { ip, token, action } => {
+            admin(&ip, token, action);
+        }
     }
 }
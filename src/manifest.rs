@@ -0,0 +1,580 @@
+use crate::{
+    OverwritePolicy, RenderSettings, config, existing_frame, inherit,
+    logging::{error, log, warn},
+    parse_frames, paths, protocol, render_frames, upload_blend, upload_input,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{create_dir_all, read},
+    io::{ErrorKind, Read, Write},
+    net::TcpStream,
+    path::{Path, PathBuf},
+    process,
+    sync::{Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Manifest {
+    pub ips: String,
+    pub id: String,
+    pub blend: Option<PathBuf>,
+    pub output_dir: PathBuf,
+    pub frames: String,
+
+    #[serde(default)]
+    pub max_concurrent_uploads: Option<usize>,
+
+    #[serde(default)]
+    pub settings: RenderSettings,
+
+    #[serde(default)]
+    pub overwrite: OverwritePolicy,
+
+    #[serde(default)]
+    pub steps: Vec<Step>,
+
+    #[serde(default)]
+    pub passes: Vec<Pass>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Pass {
+    pub name: String,
+    pub id: String,
+    pub blend: Option<PathBuf>,
+
+    #[serde(default)]
+    pub settings: RenderSettings,
+
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+#[derive(Default)]
+struct Pipeline {
+    outputs: HashMap<String, HashMap<usize, PathBuf>>,
+    finished: HashSet<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Step {
+    #[serde(flatten)]
+    pub action: Action,
+
+    #[serde(default)]
+    pub on_failure: FailurePolicy,
+
+    #[serde(default)]
+    pub retries: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Action {
+    Encode {
+        #[serde(default = "default_encode_input")]
+        input: String,
+        output: PathBuf,
+
+        #[serde(default = "default_framerate")]
+        framerate: f64,
+
+        #[serde(default)]
+        args: Vec<String>,
+    },
+    Webhook {
+        url: String,
+    },
+    Command {
+        program: String,
+
+        #[serde(default)]
+        args: Vec<String>,
+    },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FailurePolicy {
+    #[default]
+    Abort,
+    Continue,
+}
+
+fn default_encode_input() -> String {
+    String::from("%04d.png")
+}
+
+fn default_framerate() -> f64 {
+    24.0
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Manifest {
+        let mut manifest: Value = serde_json::from_slice(&read(path).unwrap()).unwrap();
+
+        if let Some(name) = manifest
+            .as_object_mut()
+            .and_then(|manifest| manifest.remove("template"))
+        {
+            let template = name
+                .as_str()
+                .ok_or_else(|| String::from("Template name must be a string"))
+                .and_then(config::template);
+
+            match template {
+                Ok(mut template) => {
+                    config::merge(&mut template, manifest);
+                    manifest = template;
+                }
+                Err(message) => {
+                    error!("Loading {} failed\nReason: {}", path.display(), message);
+                    process::exit(1);
+                }
+            }
+        }
+
+        let mut manifest: Manifest = serde_json::from_value(manifest).unwrap();
+
+        let expanded = config::parse_ips(&manifest.ips).and_then(|ips| {
+            config::parse_output_dir(&manifest.output_dir.to_string_lossy())
+                .map(|output_dir| (ips, output_dir))
+        });
+        (manifest.ips, manifest.output_dir) = match expanded {
+            Ok(expanded) => expanded,
+            Err(message) => {
+                error!("Loading {} failed\nReason: {}", path.display(), message);
+                process::exit(1);
+            }
+        };
+
+        let ids = manifest.passes.iter().map(|pass| &pass.id);
+        for id in ids.chain([&manifest.id]) {
+            if let Err(error) = protocol::validate_id(id) {
+                error!("Invalid ID \"{}\" in {}: {}", id, path.display(), error);
+                process::exit(1);
+            }
+        }
+
+        if let Err(message) = parse_frames(&manifest.frames) {
+            error!("Loading {} failed\nReason: {}", path.display(), message);
+            process::exit(1);
+        }
+
+        let base = path.canonicalize().unwrap();
+        let base = base.parent().unwrap();
+
+        manifest.blend = manifest.blend.map(|blend| base.join(blend));
+        manifest.output_dir = paths::output_dir(&base.join(&manifest.output_dir)).unwrap();
+
+        for pass in &mut manifest.passes {
+            pass.blend = pass.blend.take().map(|blend| base.join(blend));
+        }
+
+        manifest
+    }
+
+    pub fn run(
+        &self,
+        frames: &Mutex<Vec<usize>>,
+        cancelled: impl Fn() -> bool + Sync,
+    ) -> Result<(), String> {
+        if let Some(blend) = &self.blend {
+            upload_blend(
+                &self.ips,
+                self.id.clone(),
+                blend,
+                self.max_concurrent_uploads,
+                None,
+                true,
+                self.settings.blender_version.as_ref(),
+            )
+            .map_err(|message| format!("Uploading {} failed: {}", blend.display(), message))?;
+        }
+
+        if self.passes.is_empty() {
+            create_dir_all(&self.output_dir).map_err(|error| error.to_string())?;
+
+            let failed = render_frames(
+                &self.ips,
+                &self.output_dir,
+                &self.id,
+                &self.settings,
+                self.overwrite,
+                frames,
+                &|_, _| {},
+            );
+            if !failed.is_empty() {
+                return Err(format!("{} frame(s) could not be rendered", failed.len()));
+            }
+        } else {
+            self.render_passes(frames, &cancelled)?;
+        }
+
+        for (index, step) in self.steps.iter().enumerate() {
+            let mut attempt = 0;
+
+            let result = loop {
+                if cancelled() {
+                    return Err(String::from("Job was cancelled"));
+                }
+
+                match self.run_action(&step.action) {
+                    Ok(()) => break Ok(()),
+                    Err(message) if attempt < step.retries => {
+                        attempt += 1;
+                        warn!(
+                            "Step {} failed, retrying ({}/{})\nReason: {}",
+                            index, attempt, step.retries, message
+                        );
+                    }
+                    Err(message) => break Err(message),
+                }
+            };
+
+            match (result, step.on_failure) {
+                (Ok(()), _) => {
+                    log!("Step {} done", index);
+                }
+                (Err(message), FailurePolicy::Continue) => {
+                    warn!("Step {} failed, continuing\nReason: {}", index, message);
+                }
+                (Err(message), FailurePolicy::Abort) => {
+                    return Err(format!("Step {} failed: {}", index, message));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render_passes(
+        &self,
+        frames: &Mutex<Vec<usize>>,
+        cancelled: &(impl Fn() -> bool + Sync),
+    ) -> Result<(), String> {
+        for pass in &self.passes {
+            for dependency in &pass.depends_on {
+                if !self.passes.iter().any(|other| &other.name == dependency) {
+                    return Err(format!(
+                        "Pass \"{}\" depends on unknown pass \"{}\"",
+                        pass.name, dependency
+                    ));
+                }
+            }
+        }
+
+        let mut resolved: HashSet<&str> = HashSet::new();
+        while resolved.len() < self.passes.len() {
+            let before = resolved.len();
+
+            for pass in &self.passes {
+                if pass
+                    .depends_on
+                    .iter()
+                    .all(|dependency| resolved.contains(dependency.as_str()))
+                {
+                    resolved.insert(&pass.name);
+                }
+            }
+
+            if resolved.len() == before {
+                return Err(String::from("Pass dependencies form a cycle"));
+            }
+        }
+
+        let frames = frames.lock().unwrap().clone();
+        let pipeline = Mutex::new(Pipeline::default());
+        let changed = Condvar::new();
+
+        let results: Vec<Result<(), String>> = thread::scope(|scope| {
+            let handles: Vec<_> = self
+                .passes
+                .iter()
+                .map(|pass| {
+                    let (frames, pipeline, changed) = (&frames, &pipeline, &changed);
+
+                    scope.spawn(inherit(move || {
+                        let result = self.render_pass(pass, frames, pipeline, changed, cancelled);
+
+                        pipeline.lock().unwrap().finished.insert(pass.name.clone());
+                        changed.notify_all();
+
+                        result
+                    }))
+                })
+                .collect();
+
+            handles
+                .into_iter()
+                .zip(&self.passes)
+                .map(|(handle, pass)| {
+                    handle
+                        .join()
+                        .unwrap_or_else(|_| Err(format!("Pass \"{}\" panicked", pass.name)))
+                })
+                .collect()
+        });
+
+        results.into_iter().collect()
+    }
+
+    fn render_pass(
+        &self,
+        pass: &Pass,
+        frames: &[usize],
+        pipeline: &Mutex<Pipeline>,
+        changed: &Condvar,
+        cancelled: &(impl Fn() -> bool + Sync),
+    ) -> Result<(), String> {
+        let output_dir = self.output_dir.join(&pass.name);
+        create_dir_all(&output_dir).map_err(|error| error.to_string())?;
+
+        if let Some(blend) = &pass.blend {
+            upload_blend(
+                &self.ips,
+                pass.id.clone(),
+                blend,
+                self.max_concurrent_uploads,
+                None,
+                true,
+                pass.settings.blender_version.as_ref(),
+            )
+            .map_err(|message| {
+                format!(
+                    "Pass \"{}\": uploading {} failed: {}",
+                    pass.name,
+                    blend.display(),
+                    message
+                )
+            })?;
+        }
+
+        let mut remaining = frames.to_vec();
+        let mut failed = Vec::new();
+        let queue = Mutex::new(Vec::new());
+
+        if self.overwrite == OverwritePolicy::Skip {
+            remaining.retain(|frame| {
+                match existing_frame(&output_dir, pass.settings.name_pattern.as_deref(), *frame) {
+                    None => true,
+                    Some(image) => {
+                        log!(
+                            "Pass \"{}\": skipping frame {}, output already exists",
+                            pass.name,
+                            frame
+                        );
+
+                        pipeline
+                            .lock()
+                            .unwrap()
+                            .outputs
+                            .entry(pass.name.clone())
+                            .or_default()
+                            .insert(*frame, image);
+                        false
+                    }
+                }
+            });
+            changed.notify_all();
+        }
+
+        while !remaining.is_empty() {
+            let (ready, inputs) = {
+                let mut pipeline = pipeline.lock().unwrap();
+
+                loop {
+                    if cancelled() {
+                        return Err(String::from("Job was cancelled"));
+                    }
+
+                    let is_ready = |frame: &usize| {
+                        pass.depends_on.iter().all(|dependency| {
+                            pipeline
+                                .outputs
+                                .get(dependency)
+                                .is_some_and(|outputs| outputs.contains_key(frame))
+                        })
+                    };
+
+                    let ready: Vec<usize> = remaining.iter().copied().filter(is_ready).collect();
+
+                    if !ready.is_empty() {
+                        let mut inputs = Vec::new();
+                        for frame in &ready {
+                            for dependency in &pass.depends_on {
+                                inputs.push((
+                                    dependency,
+                                    pipeline.outputs[dependency][frame].clone(),
+                                ));
+                            }
+                        }
+
+                        break (ready, inputs);
+                    }
+
+                    if pass
+                        .depends_on
+                        .iter()
+                        .all(|dependency| pipeline.finished.contains(dependency))
+                    {
+                        return Err(format!(
+                            "Pass \"{}\" is missing inputs for {} frames",
+                            pass.name,
+                            remaining.len()
+                        ));
+                    }
+
+                    pipeline = changed
+                        .wait_timeout(pipeline, Duration::from_secs(1))
+                        .unwrap()
+                        .0;
+                }
+            };
+
+            for ip in self.ips.split_terminator(',') {
+                for (dependency, image) in &inputs {
+                    upload_input(ip, &pass.id, dependency, image).map_err(|message| {
+                        format!(
+                            "Could not upload input {} to {}: {}",
+                            image.display(),
+                            ip,
+                            message
+                        )
+                    })?;
+                }
+            }
+
+            log!("Pass \"{}\": dispatching {} frames", pass.name, ready.len());
+
+            remaining.retain(|frame| !ready.contains(frame));
+            *queue.lock().unwrap() = ready;
+
+            failed.extend(render_frames(
+                &self.ips,
+                &output_dir,
+                &pass.id,
+                &pass.settings,
+                self.overwrite,
+                &queue,
+                &|frame, image| {
+                    pipeline
+                        .lock()
+                        .unwrap()
+                        .outputs
+                        .entry(pass.name.clone())
+                        .or_default()
+                        .insert(frame, image.to_path_buf());
+                    changed.notify_all();
+                },
+            ));
+        }
+
+        if !failed.is_empty() {
+            return Err(format!(
+                "Pass \"{}\": {} frame(s) could not be rendered",
+                pass.name,
+                failed.len()
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn run_action(&self, action: &Action) -> Result<(), String> {
+        match action {
+            Action::Encode {
+                input,
+                output,
+                framerate,
+                args,
+            } => {
+                let status = process::Command::new("ffmpeg")
+                    .arg("-y")
+                    .args(["-framerate", &framerate.to_string()])
+                    .arg("-i")
+                    .arg(self.output_dir.join(input))
+                    .args(args)
+                    .arg(self.output_dir.join(output))
+                    .status()
+                    .map_err(|error| format!("Could not run ffmpeg: {}", error))?;
+
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("ffmpeg exited with {}", status))
+                }
+            }
+            Action::Webhook { url } => {
+                let body = serde_json::to_vec(&serde_json::json!({
+                    "id": self.id,
+                    "frames": self.frames,
+                    "output_dir": self.output_dir,
+                }))
+                .unwrap();
+
+                post(url, &body)
+            }
+            Action::Command { program, args } => {
+                let status = process::Command::new(program)
+                    .args(args)
+                    .current_dir(&self.output_dir)
+                    .status()
+                    .map_err(|error| format!("Could not run {}: {}", program, error))?;
+
+                if status.success() {
+                    Ok(())
+                } else {
+                    Err(format!("{} exited with {}", program, status))
+                }
+            }
+        }
+    }
+}
+
+fn post(url: &str, body: &[u8]) -> Result<(), String> {
+    let address = url
+        .strip_prefix("http://")
+        .ok_or_else(|| format!("Unsupported webhook URL {}", url))?;
+
+    let (host, path) = match address.find('/') {
+        Some(index) => address.split_at(index),
+        None => (address, "/"),
+    };
+
+    let mut stream = match TcpStream::connect(host) {
+        Ok(stream) => Ok(stream),
+        Err(error) => match error.kind() {
+            ErrorKind::InvalidInput => TcpStream::connect((host, 80)),
+            _ => Err(error),
+        },
+    }
+    .map_err(|error| format!("Could not reach {}: {}", host, error))?;
+
+    let mut request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        path,
+        host,
+        body.len()
+    )
+    .into_bytes();
+    request.extend_from_slice(body);
+
+    let mut response = String::new();
+    stream
+        .write_all(&request)
+        .and_then(|()| stream.read_to_string(&mut response))
+        .map_err(|error| format!("Webhook request failed: {}", error))?;
+
+    let status = response.split_whitespace().nth(1).unwrap_or_default();
+
+    if status.starts_with('2') {
+        Ok(())
+    } else {
+        Err(format!("Webhook responded with status {}", status))
+    }
+}
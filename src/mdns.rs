@@ -0,0 +1,119 @@
+use crate::logging::log;
+use mdns_sd::{ServiceDaemon, ServiceEvent, ServiceInfo};
+use std::{
+    collections::HashMap,
+    env,
+    fs::read_to_string,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+const SERVICE_TYPE: &str = "_brsp._tcp.local.";
+
+pub struct Advertisement {
+    daemon: ServiceDaemon,
+    fullname: String,
+}
+
+pub struct DiscoveredServer {
+    pub name: String,
+    pub address: SocketAddr,
+    pub properties: HashMap<String, String>,
+}
+
+fn hostname() -> String {
+    env::var("HOSTNAME")
+        .or_else(|_| env::var("COMPUTERNAME"))
+        .ok()
+        .or_else(|| read_to_string("/etc/hostname").ok())
+        .map(|hostname| String::from(hostname.trim()))
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| String::from("brsp"))
+}
+
+impl Advertisement {
+    pub fn new(port: u16, properties: &[(&str, String)]) -> Result<Advertisement, String> {
+        let daemon = ServiceDaemon::new().map_err(|error| error.to_string())?;
+
+        let name = hostname();
+        let info = ServiceInfo::new(
+            SERVICE_TYPE,
+            &name,
+            &format!("{}.local.", name),
+            "",
+            port,
+            properties,
+        )
+        .map_err(|error| error.to_string())?
+        .enable_addr_auto();
+
+        let fullname = String::from(info.get_fullname());
+        daemon.register(info).map_err(|error| error.to_string())?;
+
+        log!("Advertising {} on port {} via mDNS", fullname, port);
+
+        Ok(Advertisement { daemon, fullname })
+    }
+
+    pub fn withdraw(&self) {
+        let _ = self.daemon.unregister(&self.fullname);
+        let _ = self.daemon.shutdown();
+    }
+}
+
+/// Browses the local network for advertised servers until `timeout` has passed.
+pub fn discover(timeout: Duration) -> Result<Vec<DiscoveredServer>, String> {
+    let daemon = ServiceDaemon::new().map_err(|error| error.to_string())?;
+    let events = daemon
+        .browse(SERVICE_TYPE)
+        .map_err(|error| error.to_string())?;
+
+    let deadline = Instant::now() + timeout;
+    let mut servers: Vec<DiscoveredServer> = Vec::new();
+    while let Ok(event) = events.recv_deadline(deadline) {
+        let info = match event {
+            ServiceEvent::ServiceResolved(info) => info,
+            _ => {
+                continue;
+            }
+        };
+
+        let addresses = info.get_addresses();
+        let ip = addresses
+            .iter()
+            .find(|ip| ip.is_ipv4())
+            .or_else(|| addresses.iter().next());
+        let ip: IpAddr = match ip {
+            Some(ip) => *ip,
+            None => {
+                continue;
+            }
+        };
+
+        let name = String::from(
+            info.get_fullname()
+                .trim_end_matches(SERVICE_TYPE)
+                .trim_end_matches('.'),
+        );
+        let properties = ["version", "workers", "devices", "slots"]
+            .into_iter()
+            .filter_map(|key| {
+                info.get_property_val_str(key)
+                    .map(|value| (String::from(key), String::from(value)))
+            })
+            .collect();
+
+        servers.retain(|server| server.name != name);
+        servers.push(DiscoveredServer {
+            name,
+            address: SocketAddr::new(ip, info.get_port()),
+            properties,
+        });
+    }
+
+    let _ = daemon.stop_browse(SERVICE_TYPE);
+    let _ = daemon.shutdown();
+
+    servers.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(servers)
+}
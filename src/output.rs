@@ -0,0 +1,128 @@
+use clap::ValueEnum;
+use std::{
+    env,
+    fmt::Display,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{IsTerminal, stdout},
+    sync::OnceLock,
+};
+
+static COLOR: OnceLock<bool> = OnceLock::new();
+
+#[derive(Clone, Copy, Default, ValueEnum)]
+pub enum ColorChoice {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+#[derive(Clone, Copy)]
+pub enum Color {
+    Red,
+    Green,
+    Yellow,
+    Blue,
+    Magenta,
+    Cyan,
+    Bold,
+}
+
+const SERVER_COLORS: [Color; 5] = [
+    Color::Cyan,
+    Color::Magenta,
+    Color::Yellow,
+    Color::Blue,
+    Color::Green,
+];
+
+pub fn init(choice: ColorChoice) {
+    let color = match choice {
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+        ColorChoice::Auto => {
+            env::var_os("NO_COLOR").is_none_or(|value| value.is_empty()) && stdout().is_terminal()
+        }
+    };
+
+    let _ = COLOR.set(color);
+}
+
+pub fn paint(text: impl Display, color: Color) -> String {
+    if !COLOR.get().copied().unwrap_or(false) {
+        return text.to_string();
+    }
+
+    let code = match color {
+        Color::Red => "31",
+        Color::Green => "32",
+        Color::Yellow => "33",
+        Color::Blue => "34",
+        Color::Magenta => "35",
+        Color::Cyan => "36",
+        Color::Bold => "1",
+    };
+
+    format!("\x1b[{}m{}\x1b[0m", code, text)
+}
+
+pub fn server(ip: &str) -> String {
+    let mut hasher = DefaultHasher::new();
+    ip.hash(&mut hasher);
+
+    paint(
+        ip,
+        SERVER_COLORS[hasher.finish() as usize % SERVER_COLORS.len()],
+    )
+}
+
+pub fn table(header: &[&str], rows: &[Vec<String>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|title| title.len()).collect();
+
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(strip(cell).chars().count());
+        }
+    }
+
+    let mut output = String::new();
+    let header = header
+        .iter()
+        .map(|title| paint(title, Color::Bold))
+        .collect();
+
+    for row in std::iter::once(&header).chain(rows) {
+        if !output.is_empty() {
+            output.push('\n');
+        }
+
+        let mut line = String::new();
+        for (cell, width) in row.iter().zip(&widths) {
+            let padding = width - strip(cell).chars().count();
+            line += &format!("{}{}  ", cell, " ".repeat(padding));
+        }
+
+        output += line.trim_end();
+    }
+
+    output
+}
+
+pub fn strip(text: &str) -> String {
+    let mut output = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(char) = chars.next() {
+        if char == '\x1b' {
+            for char in chars.by_ref() {
+                if char == 'm' {
+                    break;
+                }
+            }
+        } else {
+            output.push(char);
+        }
+    }
+
+    output
+}
@@ -0,0 +1,41 @@
+use std::path::{Path, PathBuf};
+
+#[cfg(windows)]
+pub fn output_dir(path: &Path) -> Result<PathBuf, String> {
+    let path = std::path::absolute(path)
+        .map_err(|error| format!("Invalid output directory {}: {}", path.display(), error))?;
+    let text = match path.to_str() {
+        Some(text) => text.replace('/', "\\"),
+        None => {
+            return Err(format!(
+                "Output directory {} is not valid Unicode",
+                path.display()
+            ));
+        }
+    };
+
+    if text.starts_with(r"\\?\") {
+        return Ok(PathBuf::from(text));
+    }
+
+    match text.strip_prefix(r"\\") {
+        Some(unc) => {
+            let mut components = unc.split('\\');
+            match (components.next(), components.next()) {
+                (Some(server), Some(share)) if !server.is_empty() && !share.is_empty() => {
+                    Ok(PathBuf::from(format!(r"\\?\UNC\{}", unc)))
+                }
+                _ => Err(format!(
+                    r"Output directory {} is not a valid UNC path (expected \\server\share\...)",
+                    text
+                )),
+            }
+        }
+        None => Ok(PathBuf::from(format!(r"\\?\{}", text))),
+    }
+}
+
+#[cfg(not(windows))]
+pub fn output_dir(path: &Path) -> Result<PathBuf, String> {
+    Ok(path.to_path_buf())
+}
@@ -0,0 +1,143 @@
+use crate::{connect_retrying, options, try_connect};
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    net::TcpStream,
+    sync::{LazyLock, Mutex},
+    time::Duration,
+};
+
+/// Address and the token its streams authenticated with.
+type Key = (String, Option<String>);
+
+static IDLE: LazyLock<Mutex<HashMap<Key, Vec<TcpStream>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+pub fn try_checkout(ip: &str, timeout: Option<Duration>) -> Result<TcpStream, std::io::Error> {
+    loop {
+        let stream = match IDLE.lock().unwrap().get_mut(&key(ip)) {
+            None => None,
+            Some(streams) => streams.pop(),
+        };
+
+        match stream {
+            None => {
+                return match timeout {
+                    None => connect_retrying(ip),
+                    Some(timeout) => try_connect(ip, Some(timeout)),
+                };
+            }
+            Some(stream) => {
+                if is_alive(&stream) {
+                    return Ok(stream);
+                }
+            }
+        }
+    }
+}
+
+pub fn checkin(ip: &str, stream: TcpStream) {
+    IDLE.lock()
+        .unwrap()
+        .entry(key(ip))
+        .or_default()
+        .push(stream);
+}
+
+fn key(ip: &str) -> Key {
+    (String::from(ip), options().auth_token.clone())
+}
+
+fn is_alive(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return false;
+    }
+
+    let alive = match stream.peek(&mut [0]) {
+        Ok(_) => false,
+        Err(error) => error.kind() == ErrorKind::WouldBlock,
+    };
+
+    alive && stream.set_nonblocking(false).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClientOptions, Response, read_header, to_header, with_options};
+    use std::{io::Write, net::TcpListener, sync::Arc, thread};
+
+    const TIMEOUT: Option<Duration> = Some(Duration::from_secs(5));
+
+    #[test]
+    fn reuses_idle_streams() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ip = listener.local_addr().unwrap().to_string();
+
+        let stream = try_checkout(&ip, TIMEOUT).unwrap();
+        let (_accepted, _) = listener.accept().unwrap();
+        let local = stream.local_addr().unwrap();
+        checkin(&ip, stream);
+
+        assert_eq!(
+            try_checkout(&ip, TIMEOUT).unwrap().local_addr().unwrap(),
+            local
+        );
+    }
+
+    #[test]
+    fn drops_closed_streams() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ip = listener.local_addr().unwrap().to_string();
+
+        let stream = try_checkout(&ip, TIMEOUT).unwrap();
+        drop(listener.accept().unwrap());
+        let local = stream.local_addr().unwrap();
+        checkin(&ip, stream);
+
+        let stream = try_checkout(&ip, TIMEOUT).unwrap();
+        assert_ne!(stream.local_addr().unwrap(), local);
+    }
+
+    #[test]
+    fn keeps_tokens_apart() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let ip = listener.local_addr().unwrap().to_string();
+        let token = |token: &str| {
+            Arc::new(ClientOptions {
+                auth_token: Some(String::from(token)),
+                ..ClientOptions::default()
+            })
+        };
+
+        let server = thread::spawn(move || {
+            let mut clients = Vec::new();
+            for _ in 0..2 {
+                let (mut client, _) = listener.accept().unwrap();
+                read_header(&mut client).unwrap();
+                client
+                    .write_all(&to_header(serde_json::to_vec(&Response::Okay).unwrap()))
+                    .unwrap();
+                clients.push(client);
+            }
+            clients
+        });
+
+        let local = with_options(token("a"), || {
+            let stream = try_checkout(&ip, TIMEOUT).unwrap();
+            let local = stream.local_addr().unwrap();
+            checkin(&ip, stream);
+            local
+        });
+        with_options(token("b"), || {
+            let stream = try_checkout(&ip, TIMEOUT).unwrap();
+            assert_ne!(stream.local_addr().unwrap(), local);
+        });
+        with_options(token("a"), || {
+            let stream = try_checkout(&ip, TIMEOUT).unwrap();
+            assert_eq!(stream.local_addr().unwrap(), local);
+        });
+
+        server.join().unwrap();
+    }
+}
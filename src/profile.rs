@@ -0,0 +1,108 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    env,
+    fs::{create_dir_all, read, write},
+    path::PathBuf,
+};
+
+const WEIGHT: f64 = 0.3;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct Profile {
+    pub jobs: usize,
+    pub seconds_per_frame: f64,
+    pub bytes_per_second: f64,
+
+    #[serde(default)]
+    pub benchmark: Option<f64>,
+}
+
+fn file() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+
+    Some(config_dir.join("brsp").join("profiles.json"))
+}
+
+pub fn load() -> HashMap<String, Profile> {
+    file()
+        .and_then(|file| read(file).ok())
+        .and_then(|profiles| serde_json::from_slice(&profiles).ok())
+        .unwrap_or_default()
+}
+
+pub fn save(profiles: &HashMap<String, Profile>) {
+    if let Some(file) = file() {
+        let _ = create_dir_all(file.parent().unwrap());
+        let _ = write(file, serde_json::to_vec_pretty(profiles).unwrap());
+    }
+}
+
+pub fn record(
+    profiles: &mut HashMap<String, Profile>,
+    server: &str,
+    seconds_per_frame: f64,
+    bytes_per_second: f64,
+) {
+    let average = |old: f64, new: f64| old * (1.0 - WEIGHT) + new * WEIGHT;
+
+    profiles
+        .entry(String::from(server))
+        .and_modify(|profile| {
+            if profile.jobs == 0 {
+                profile.seconds_per_frame = seconds_per_frame;
+                profile.bytes_per_second = bytes_per_second;
+            } else {
+                profile.seconds_per_frame = average(profile.seconds_per_frame, seconds_per_frame);
+                profile.bytes_per_second = average(profile.bytes_per_second, bytes_per_second);
+            }
+            profile.jobs += 1;
+        })
+        .or_insert(Profile {
+            jobs: 1,
+            seconds_per_frame,
+            bytes_per_second,
+            benchmark: None,
+        });
+}
+
+pub fn benchmark(profiles: &mut HashMap<String, Profile>, server: &str, seconds: f64) {
+    profiles
+        .entry(String::from(server))
+        .and_modify(|profile| profile.benchmark = Some(seconds))
+        .or_insert(Profile {
+            jobs: 0,
+            seconds_per_frame: seconds,
+            bytes_per_second: 0.0,
+            benchmark: Some(seconds),
+        });
+}
+
+/// The expected render time of a frame on `server`. Benchmarks are preferred over job averages,
+/// since every server rendered the same frame for them.
+pub fn seconds_per_frame(profiles: &HashMap<String, Profile>, server: &str) -> Option<f64> {
+    profiles
+        .get(server)
+        .map(|profile| profile.benchmark.unwrap_or(profile.seconds_per_frame))
+}
+
+pub fn estimate(
+    profiles: &HashMap<String, Profile>,
+    servers: &[&str],
+    frames: usize,
+) -> Option<f64> {
+    let frames_per_second: f64 = servers
+        .iter()
+        .filter_map(|server| seconds_per_frame(profiles, server))
+        .map(|seconds| 1.0 / seconds.max(f64::EPSILON))
+        .sum();
+
+    if frames_per_second > 0.0 {
+        Some(frames as f64 / frames_per_second)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,391 @@
+use crate::{FrameRangeRequest, FrameRequest, FrameRequests, Request, read_header};
+use serde::de::DeserializeOwned;
+use std::{
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+const MAX_JSON_DEPTH: usize = 16;
+const MAX_ID_LENGTH: usize = 128;
+const MAX_FILE_SIZE: u64 = 1 << 40;
+const MAX_BAKE_RESOLUTION: u32 = 1 << 16;
+const MAX_BATCH_FRAMES: usize = 1024;
+
+/// Decodes a header received from an untrusted peer, rejecting deeply nested JSON before it is
+/// handed to serde.
+pub(crate) fn decode<T: DeserializeOwned>(header: &[u8]) -> Result<T, String> {
+    let mut depth: usize = 0;
+    let mut string = false;
+    let mut escaped = false;
+
+    for &byte in header {
+        if string {
+            if escaped {
+                escaped = false;
+            } else if byte == b'\\' {
+                escaped = true;
+            } else if byte == b'"' {
+                string = false;
+            }
+            continue;
+        }
+
+        match byte {
+            b'"' => string = true,
+            b'{' | b'[' => {
+                depth += 1;
+                if depth > MAX_JSON_DEPTH {
+                    return Err(String::from("Request is nested too deeply"));
+                }
+            }
+            b'}' | b']' => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+    }
+
+    serde_json::from_slice(header).map_err(|error| format!("Malformed request: {}", error))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum IdError {
+    Empty,
+    TooLong { length: usize },
+    InvalidCharacter { character: char },
+    Reserved,
+    OutsideNamespace,
+}
+
+impl fmt::Display for IdError {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IdError::Empty => write!(formatter, "ID is empty"),
+            IdError::TooLong { length } => write!(
+                formatter,
+                "ID is {} bytes long, at most {} are allowed",
+                length, MAX_ID_LENGTH
+            ),
+            IdError::InvalidCharacter { character } => write!(
+                formatter,
+                "ID contains {:?}, only ASCII letters, digits, '-', '_' and '.' are allowed",
+                character
+            ),
+            IdError::Reserved => write!(formatter, "ID must not start with '.'"),
+            IdError::OutsideNamespace => write!(formatter, "ID resolves outside its namespace"),
+        }
+    }
+}
+
+/// Checks that an ID only uses ASCII letters, digits, `-`, `_` and `.`, does not start with `.`
+/// and is at most 128 bytes long, so it is always safe to use as a single path component.
+pub fn validate_id(id: &str) -> Result<(), IdError> {
+    if id.is_empty() {
+        return Err(IdError::Empty);
+    }
+
+    if id.len() > MAX_ID_LENGTH {
+        return Err(IdError::TooLong { length: id.len() });
+    }
+
+    if let Some(character) = id
+        .chars()
+        .find(|character| !character.is_ascii_alphanumeric() && !"-_.".contains(*character))
+    {
+        return Err(IdError::InvalidCharacter { character });
+    }
+
+    if id.starts_with('.') {
+        return Err(IdError::Reserved);
+    }
+
+    Ok(())
+}
+
+/// Value parser for ID arguments, so invalid IDs are rejected before contacting any server.
+pub fn parse_id(id: &str) -> Result<String, String> {
+    validate_id(id)
+        .map(|()| String::from(id))
+        .map_err(|error| error.to_string())
+}
+
+/// Joins a validated ID onto `namespace`, making sure the result is a direct child of it.
+pub(crate) fn namespace_path(namespace: &Path, id: &str) -> Result<PathBuf, IdError> {
+    validate_id(id)?;
+
+    let path = namespace.join(id);
+    let confined = path.parent() == Some(namespace)
+        && path
+            .components()
+            .skip(namespace.components().count())
+            .all(|component| matches!(component, Component::Normal(_)));
+
+    if confined {
+        Ok(path)
+    } else {
+        Err(IdError::OutsideNamespace)
+    }
+}
+
+/// Key a job is addressed by on the server: the bare ID for anonymous jobs, `user/id` otherwise.
+pub(crate) fn job_key(user: Option<&str>, id: &str) -> String {
+    match user {
+        None => String::from(id),
+        Some(user) => format!("{}/{}", user, id),
+    }
+}
+
+/// Splits a key built by `job_key` back into its user and ID.
+pub(crate) fn split_key(key: &str) -> (Option<&str>, &str) {
+    match key.split_once('/') {
+        None => (None, key),
+        Some((user, id)) => (Some(user), id),
+    }
+}
+
+/// Compares tokens in constant time: `blake3::Hash` equality does not short-circuit, and hashing
+/// first hides the length of the expected token.
+pub(crate) fn tokens_match(given: &str, expected: &str) -> bool {
+    blake3::hash(given.as_bytes()) == blake3::hash(expected.as_bytes())
+}
+
+fn validate_size(size: usize) -> Result<(), String> {
+    if size as u64 > MAX_FILE_SIZE {
+        return Err(format!(
+            "Size {} exceeds the limit of {}",
+            size, MAX_FILE_SIZE
+        ));
+    }
+
+    Ok(())
+}
+
+impl Request {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if let Some(user) = self.user() {
+            validate_id(user).map_err(|error| format!("Invalid user: {}", error))?;
+        }
+
+        match self {
+            Request::Upload { id, size, .. }
+            | Request::Seed { id, size, .. }
+            | Request::UploadInput { id, size, .. } => {
+                validate_id(id).map_err(|error| error.to_string())?;
+                validate_size(*size)
+            }
+            Request::BakeTextures { id, resolution, .. } => {
+                validate_id(id).map_err(|error| error.to_string())?;
+                if *resolution == 0 || *resolution > MAX_BAKE_RESOLUTION {
+                    return Err(format!("Invalid bake resolution {}", resolution));
+                }
+                Ok(())
+            }
+            Request::Render { id: Some(id), .. }
+            | Request::Fetch { id, .. }
+            | Request::Thumbnail { id, .. }
+            | Request::FetchFrame { id, .. }
+            | Request::Status { id, .. }
+            | Request::Fingerprint { id, .. }
+            | Request::FrameRate { id, .. }
+            | Request::Cancel { id, .. }
+            | Request::Delete { id, .. }
+            | Request::CancelFrame { id, .. } => validate_id(id).map_err(|error| error.to_string()),
+            Request::Reconnect { session } => {
+                validate_id(session).map_err(|error| error.to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn id(&self) -> Option<&str> {
+        match self {
+            Request::Upload { id, .. }
+            | Request::Seed { id, .. }
+            | Request::UploadInput { id, .. }
+            | Request::BakeTextures { id, .. }
+            | Request::Render { id: Some(id), .. }
+            | Request::Fetch { id, .. }
+            | Request::Thumbnail { id, .. }
+            | Request::FetchFrame { id, .. }
+            | Request::Status { id, .. }
+            | Request::Fingerprint { id, .. }
+            | Request::FrameRate { id, .. }
+            | Request::Cancel { id, .. }
+            | Request::Delete { id, .. }
+            | Request::CancelFrame { id, .. } => Some(id),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn user(&self) -> Option<&str> {
+        match self {
+            Request::Upload { user, .. }
+            | Request::Seed { user, .. }
+            | Request::UploadInput { user, .. }
+            | Request::BakeTextures { user, .. }
+            | Request::Render { user, .. }
+            | Request::Fetch { user, .. }
+            | Request::Thumbnail { user, .. }
+            | Request::FetchFrame { user, .. }
+            | Request::Status { user, .. }
+            | Request::Fingerprint { user, .. }
+            | Request::FrameRate { user, .. }
+            | Request::Cancel { user, .. }
+            | Request::CancelFrame { user, .. }
+            | Request::Delete { user, .. }
+            | Request::List { user }
+            | Request::Query { user } => user.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Replaces the job ID with its `job_key`, so the server addresses jobs per user.
+    pub(crate) fn qualify(&mut self) {
+        match self {
+            Request::Upload { id, user, .. }
+            | Request::Seed { id, user, .. }
+            | Request::UploadInput { id, user, .. }
+            | Request::BakeTextures { id, user, .. }
+            | Request::Render {
+                id: Some(id), user, ..
+            }
+            | Request::Fetch { id, user }
+            | Request::Thumbnail { id, user, .. }
+            | Request::FetchFrame { id, user, .. }
+            | Request::Status { id, user }
+            | Request::Fingerprint { id, user }
+            | Request::FrameRate { id, user }
+            | Request::Cancel { id, user }
+            | Request::Delete { id, user }
+            | Request::CancelFrame { id, user, .. } => *id = job_key(user.as_deref(), id),
+            _ => {}
+        }
+    }
+}
+
+impl FrameRequest {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        validate_id(&self.id).map_err(|error| error.to_string())
+    }
+}
+
+impl FrameRangeRequest {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        validate_id(&self.id).map_err(|error| error.to_string())?;
+
+        if self.end < self.start || self.end - self.start >= MAX_BATCH_FRAMES {
+            return Err(format!(
+                "Invalid frame range {}-{}, at most {} frames per batch",
+                self.start, self.end, MAX_BATCH_FRAMES
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+impl FrameRequests {
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match self {
+            FrameRequests::Single(request) => request.validate(),
+            FrameRequests::Range(request) => request.validate(),
+        }
+    }
+}
+
+/// Feeds arbitrary bytes through the same header framing, decoding and validation the server
+/// applies to client requests. Entry point for the fuzz targets in `fuzz/`.
+#[doc(hidden)]
+pub fn fuzz_request(mut data: &[u8]) {
+    while let Ok(header) = read_header(&mut data) {
+        if let Ok(request) = decode::<Request>(&header) {
+            let _ = request.validate();
+        }
+
+        if let Ok(request) = decode::<FrameRequests>(&header) {
+            let _ = request.validate();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_plain_ids() {
+        assert_eq!(validate_id("shot-010_v2.final"), Ok(()));
+        assert_eq!(validate_id(&"a".repeat(MAX_ID_LENGTH)), Ok(()));
+    }
+
+    #[test]
+    fn rejects_unsafe_ids() {
+        assert_eq!(validate_id(""), Err(IdError::Empty));
+        assert_eq!(validate_id(".."), Err(IdError::Reserved));
+        assert_eq!(validate_id(".hidden"), Err(IdError::Reserved));
+        assert_eq!(
+            validate_id("a/b"),
+            Err(IdError::InvalidCharacter { character: '/' })
+        );
+        assert_eq!(
+            validate_id("a\\b"),
+            Err(IdError::InvalidCharacter { character: '\\' })
+        );
+        assert_eq!(
+            validate_id(&"a".repeat(MAX_ID_LENGTH + 1)),
+            Err(IdError::TooLong {
+                length: MAX_ID_LENGTH + 1
+            })
+        );
+    }
+
+    #[test]
+    fn confines_ids_to_namespace() {
+        let namespace = Path::new("users");
+
+        assert_eq!(
+            namespace_path(namespace, "shot"),
+            Ok(PathBuf::from("users/shot"))
+        );
+        assert!(namespace_path(namespace, "..").is_err());
+        assert!(namespace_path(namespace, "../shot").is_err());
+    }
+
+    #[test]
+    fn splits_job_keys() {
+        assert_eq!(job_key(None, "shot"), "shot");
+        assert_eq!(split_key(&job_key(None, "shot")), (None, "shot"));
+
+        assert_eq!(job_key(Some("alice"), "shot"), "alice/shot");
+        assert_eq!(
+            split_key(&job_key(Some("alice"), "shot")),
+            (Some("alice"), "shot")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_users() {
+        let request = Request::Status {
+            id: String::from("shot"),
+            user: Some(String::from("../alice")),
+        };
+
+        assert!(request.validate().is_err());
+    }
+
+    #[test]
+    fn limits_nesting_depth() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+
+        assert!(decode::<serde_json::Value>(nested(MAX_JSON_DEPTH).as_bytes()).is_ok());
+        assert_eq!(
+            decode::<serde_json::Value>(nested(MAX_JSON_DEPTH + 1).as_bytes()).unwrap_err(),
+            "Request is nested too deeply"
+        );
+    }
+
+    #[test]
+    fn ignores_brackets_in_strings() {
+        let header = format!("{{\"id\": \"{}\"}}", "[".repeat(MAX_JSON_DEPTH * 2));
+
+        assert!(decode::<serde_json::Value>(header.as_bytes()).is_ok());
+    }
+}
@@ -0,0 +1,218 @@
+use crate::RenderStats;
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+const OUTLIER_FACTOR: f64 = 10.0;
+const CHART_HEIGHT: f64 = 200.0;
+const BAR_WIDTH: f64 = 8.0;
+
+#[derive(ValueEnum, Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    Html,
+}
+
+pub struct FrameTime {
+    pub frame: usize,
+    pub server: String,
+    pub seconds: f64,
+    pub path: PathBuf,
+    pub bytes: usize,
+    pub stats: Option<RenderStats>,
+}
+
+#[derive(Serialize)]
+pub struct ServerReport<'a> {
+    pub server: &'a str,
+    pub frames: usize,
+    pub seconds: f64,
+    pub render_seconds: f64,
+    pub peak_memory: Option<u64>,
+    pub devices: Vec<&'a str>,
+}
+
+#[derive(Serialize)]
+struct FrameReport<'a> {
+    frame: usize,
+    server: &'a str,
+    seconds: f64,
+    bytes: usize,
+    render_seconds: Option<f64>,
+    peak_memory: Option<u64>,
+    device: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct RenderReport<'a> {
+    id: &'a str,
+    servers: Vec<ServerReport<'a>>,
+    frames: Vec<FrameReport<'a>>,
+}
+
+/// Aggregates the frame times per server, in the order the servers first appear in `times`.
+pub fn servers(times: &[FrameTime]) -> Vec<ServerReport<'_>> {
+    let mut servers: Vec<ServerReport> = Vec::new();
+
+    for time in times {
+        let index = match servers
+            .iter()
+            .position(|server| server.server == time.server)
+        {
+            Some(index) => index,
+            None => {
+                servers.push(ServerReport {
+                    server: &time.server,
+                    frames: 0,
+                    seconds: 0.0,
+                    render_seconds: 0.0,
+                    peak_memory: None,
+                    devices: Vec::new(),
+                });
+                servers.len() - 1
+            }
+        };
+
+        let server = &mut servers[index];
+        server.frames += 1;
+        server.seconds += time.seconds;
+
+        if let Some(stats) = &time.stats {
+            server.render_seconds += stats.seconds;
+            server.peak_memory = server.peak_memory.max(stats.peak_memory);
+
+            if let Some(device) = &stats.device
+                && !server.devices.contains(&device.as_str())
+            {
+                server.devices.push(device);
+            }
+        }
+    }
+
+    servers
+}
+
+pub fn json(id: &str, times: &[FrameTime]) -> serde_json::Value {
+    let report = RenderReport {
+        id,
+        servers: servers(times),
+        frames: times
+            .iter()
+            .map(|time| FrameReport {
+                frame: time.frame,
+                server: &time.server,
+                seconds: time.seconds,
+                bytes: time.bytes,
+                render_seconds: time.stats.as_ref().map(|stats| stats.seconds),
+                peak_memory: time.stats.as_ref().and_then(|stats| stats.peak_memory),
+                device: time
+                    .stats
+                    .as_ref()
+                    .and_then(|stats| stats.device.as_deref()),
+            })
+            .collect(),
+    };
+
+    serde_json::to_value(&report).unwrap()
+}
+
+pub fn median(times: &[FrameTime]) -> f64 {
+    let mut seconds: Vec<f64> = times.iter().map(|time| time.seconds).collect();
+    seconds.sort_by(f64::total_cmp);
+
+    match seconds.len() {
+        0 => 0.0,
+        len if len.is_multiple_of(2) => (seconds[len / 2 - 1] + seconds[len / 2]) / 2.0,
+        len => seconds[len / 2],
+    }
+}
+
+pub fn outliers(times: &[FrameTime]) -> Vec<&FrameTime> {
+    let median = median(times);
+
+    times
+        .iter()
+        .filter(|time| median > 0.0 && time.seconds >= median * OUTLIER_FACTOR)
+        .collect()
+}
+
+pub fn html(id: &str, times: &[FrameTime]) -> String {
+    let median = median(times);
+    let longest = times.iter().map(|time| time.seconds).fold(0.0, f64::max);
+
+    let mut bars = String::new();
+    for (index, time) in times.iter().enumerate() {
+        let height = if longest > 0.0 {
+            time.seconds / longest * CHART_HEIGHT
+        } else {
+            0.0
+        };
+        let ratio = if median > 0.0 {
+            time.seconds / median
+        } else {
+            1.0
+        };
+        let hue = 120.0 - ((ratio - 1.0) / (OUTLIER_FACTOR - 1.0)).clamp(0.0, 1.0) * 120.0;
+
+        bars += &format!(
+            "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"hsl({:.0},70%,45%)\"><title>Frame {}: {:.2}s on {}</title></rect>\n",
+            index as f64 * BAR_WIDTH,
+            CHART_HEIGHT - height,
+            BAR_WIDTH - 1.0,
+            height,
+            hue,
+            time.frame,
+            time.seconds,
+            escape(&time.server)
+        );
+    }
+
+    let mut rows = String::new();
+    for time in outliers(times) {
+        rows += &format!(
+            "<tr><td>{}</td><td>{}</td><td>{:.2}s</td><td>{:.1}x</td></tr>\n",
+            time.frame,
+            escape(&time.server),
+            time.seconds,
+            time.seconds / median
+        );
+    }
+
+    if rows.is_empty() {
+        rows = String::from("<tr><td colspan=\"4\">None</td></tr>\n");
+    }
+
+    format!(
+        "<!DOCTYPE html>
+<html>
+<head>
+<meta charset=\"utf-8\">
+<title>Frame times for {id}</title>
+<style>body {{ font-family: sans-serif; }} td, th {{ padding: 2px 12px; text-align: left; }}</style>
+</head>
+<body>
+<h1>Frame times for {id}</h1>
+<p>{frames} frames, median {median:.2}s, longest {longest:.2}s</p>
+<svg width=\"{width:.0}\" height=\"{height:.0}\">
+{bars}</svg>
+<h2>Frames taking {factor}x the median or longer</h2>
+<table>
+<tr><th>FRAME</th><th>SERVER</th><th>TIME</th><th>MEDIAN</th></tr>
+{rows}</table>
+</body>
+</html>
+",
+        id = escape(id),
+        frames = times.len(),
+        width = times.len() as f64 * BAR_WIDTH,
+        height = CHART_HEIGHT,
+        factor = OUTLIER_FACTOR,
+    )
+}
+
+pub fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,4285 @@
+use crate::{
+    AdminRequest, AdminResponse, BakeMap, BakeResponse, BakedImage, BlenderInstallation,
+    BlenderRequirement, BrpyAssetReport, BrpyBakeResponse, BrpyBakedImage, BrpyEnvironment,
+    BrpyFrameRate, BrpyRenderResponse, BrpyRequest, ClientOptions, Fingerprint,
+    FingerprintResponse, FrameRateResponse, FrameRequest, FrameRequests, Heartbeat, ListResponse,
+    PassOutput, QueryResponse, RejectReason, RenderAcceptResponse, RenderProgress, RenderResponse,
+    RenderStats, Request, Response, RootUsage, SlotInfo, SlotState, StatusResponse, StoredBlend,
+    StoredJob, ThumbnailResponse, UploadResponse,
+    dashboard::{self, Dashboard, JobUsage},
+    disk, format_size, hash, idle,
+    logging::{self, debug, error, log, warn},
+    mdns::Advertisement,
+    protocol, read_header,
+    storage::{self, Storage},
+    to_header, transfer, try_connect,
+    upnp::Mapping,
+    with_options,
+};
+use clap::{Args, Subcommand, ValueEnum};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::{
+    borrow::Cow,
+    cmp::Reverse,
+    collections::{HashMap, HashSet, VecDeque},
+    env::set_current_dir,
+    fs::{
+        File, create_dir, create_dir_all, hard_link, read, read_dir, read_to_string,
+        remove_dir_all, remove_file, rename, write,
+    },
+    io::{self, BufRead, BufReader, ErrorKind, Read, Write},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    process::{self, Stdio},
+    sync::{
+        Arc, Condvar, Mutex, TryLockError,
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+const NULL_RENDER_LIMIT: usize = 1 << 28;
+const BRPY_OUTPUT_LINES: usize = 40;
+const SESSION_TTL: u64 = 24 * 60 * 60;
+const SEND_TIMEOUT: Duration = Duration::from_secs(30);
+const BRPY_STOP_TIMEOUT: Duration = Duration::from_secs(5);
+const FRAME_COMPRESSION_LEVEL: i32 = 3;
+const DEFAULT_INSTALLATION: &str = "default";
+const MAX_PRIORITY: u8 = 9;
+const OUTBOX_MEMORY: usize = 256 << 20;
+const MAX_LISTED_JOBS: usize = 100;
+pub const PORT: u16 = 21816;
+pub const CONTROL_PORT: u16 = 21818;
+
+#[derive(Args)]
+pub struct Options {
+    pub brpy: PathBuf,
+    pub work_dir: PathBuf,
+
+    #[arg(short, long, value_parser = parse_installation)]
+    pub blender: Vec<(String, PathBuf)>,
+
+    #[arg(long)]
+    pub admin_token: Option<String>,
+
+    #[arg(long)]
+    pub auth_token: Option<String>,
+
+    #[arg(long)]
+    pub upnp: bool,
+
+    #[arg(long)]
+    pub idle_load: Option<f64>,
+
+    #[arg(long)]
+    pub idle_input: Option<u64>,
+
+    #[arg(long)]
+    pub suspend_blender: bool,
+
+    #[arg(long, default_value_t = 256)]
+    pub max_slots: usize,
+
+    #[arg(long, value_enum, default_value_t)]
+    pub backpressure: Backpressure,
+
+    #[arg(long, default_value_t = 60)]
+    pub brpy_timeout: u64,
+
+    #[arg(long, default_value_t = 2)]
+    pub brpy_retries: u32,
+
+    #[arg(long)]
+    pub scratch_dir: Option<PathBuf>,
+
+    #[arg(long, default_value_t = 24 * 60 * 60)]
+    pub frame_retention: u64,
+
+    #[arg(long, default_value_t = 1)]
+    pub workers: usize,
+
+    #[arg(long, value_delimiter = ',')]
+    pub worker_devices: Vec<String>,
+
+    #[arg(long)]
+    pub storage: Option<String>,
+
+    #[arg(long, default_value_t = CONTROL_PORT)]
+    pub control_port: u16,
+
+    #[arg(long)]
+    pub no_mdns: bool,
+
+    #[arg(long, value_parser = parse_size)]
+    pub max_disk: Option<u64>,
+
+    #[arg(long, value_parser = parse_duration)]
+    pub job_ttl: Option<u64>,
+
+    #[arg(long, default_value_t = 10)]
+    pub heartbeat: u64,
+
+    #[arg(long, default_value_t = 60)]
+    pub heartbeat_timeout: u64,
+
+    #[arg(long)]
+    pub http_port: Option<u16>,
+
+    #[arg(long, value_delimiter = ',')]
+    pub bind: Vec<IpAddr>,
+
+    #[arg(long, default_value_t = PORT)]
+    pub port: u16,
+
+    #[arg(long)]
+    pub random_port: bool,
+
+    #[arg(long, default_value_t = 60)]
+    pub shutdown_timeout: u64,
+
+    #[arg(long, value_parser = parse_size)]
+    pub max_upload: Option<u64>,
+
+    #[arg(long, default_value_t = 32)]
+    pub max_client_connections: usize,
+
+    #[arg(long, default_value_t = 60)]
+    pub client_timeout: u64,
+}
+
+#[derive(Subcommand, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum NodeAction {
+    Pause {
+        #[arg(long)]
+        suspend: bool,
+    },
+    Resume,
+}
+
+#[derive(ValueEnum, Clone, Copy, Default, PartialEq)]
+pub enum Backpressure {
+    #[default]
+    Block,
+    Fail,
+    Disconnect,
+}
+
+struct Server {
+    info: QueryResponse,
+    environment: BrpyEnvironment,
+    admin_token: Option<String>,
+    auth_token: Option<String>,
+    requesters: Mutex<Vec<Option<Requester>>>,
+    notifier: Condvar,
+    workers: Vec<Worker>,
+    sessions: Mutex<HashMap<String, Session>>,
+    job_stats: Mutex<HashMap<String, JobStats>>,
+    connections: Mutex<HashMap<SocketAddr, &'static str>>,
+    bans: Mutex<HashMap<IpAddr, Instant>>,
+    paused: AtomicBool,
+    held: Mutex<Option<bool>>,
+    suspend_blender: bool,
+    max_slots: usize,
+    backpressure: Backpressure,
+    scratch_dir: PathBuf,
+    frame_retention: u64,
+    storage: Box<dyn Storage>,
+    max_disk: Option<u64>,
+    job_ttl: Option<u64>,
+    janitor: Mutex<()>,
+    heartbeat: Duration,
+    heartbeat_timeout: Duration,
+    cancelled_jobs: Mutex<HashSet<String>>,
+    shutting_down: AtomicBool,
+    max_upload: Option<u64>,
+    max_client_connections: usize,
+    client_timeout: Duration,
+}
+
+/// Removes its connection from `Server::connections` when dropped, even if the handler panics.
+struct Connection<'a> {
+    server: &'a Server,
+    address: SocketAddr,
+}
+
+impl Drop for Connection<'_> {
+    fn drop(&mut self) {
+        if let Ok(mut connections) = self.server.connections.lock() {
+            connections.remove(&self.address);
+        }
+    }
+}
+
+struct Worker {
+    index: usize,
+    installation: usize,
+    blender: PathBuf,
+    script: PathBuf,
+    timeout: Duration,
+    device: Option<String>,
+    brpy: Mutex<TcpStream>,
+    control: Mutex<TcpStream>,
+    process: Mutex<process::Child>,
+    output: Mutex<BrpyOutput>,
+    stopped: AtomicBool,
+}
+
+type BrpyOutput = Arc<Mutex<VecDeque<String>>>;
+
+struct Requester {
+    stream: TcpStream,
+    writer: Arc<Mutex<TcpStream>>,
+    requests: Arc<Mutex<Receiver<Vec<u8>>>>,
+    address: SocketAddr,
+    state: SlotState,
+    since: Instant,
+    id: Option<String>,
+    frame: Option<usize>,
+    outbox: Arc<Outbox>,
+    assets_checked: bool,
+    prefetch: bool,
+    addons: Vec<String>,
+    session: String,
+    accepted: bool,
+    worker: Option<usize>,
+    batch: Option<(FrameRequest, VecDeque<usize>)>,
+    heartbeat: bool,
+    last_seen: Instant,
+    installation: usize,
+    compression: Option<i32>,
+    priority: u8,
+    position: Option<usize>,
+    user: Option<String>,
+}
+
+#[derive(Default)]
+struct JobStats {
+    completed: usize,
+    failed: usize,
+    started: Option<Instant>,
+    updated: Option<Instant>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Session {
+    id: Option<String>,
+    prefetch: bool,
+
+    #[serde(default)]
+    addons: Vec<String>,
+
+    #[serde(default)]
+    heartbeat: bool,
+
+    #[serde(default)]
+    blender: Option<BlenderRequirement>,
+
+    #[serde(default)]
+    compression: Option<i32>,
+
+    #[serde(default)]
+    priority: Option<u8>,
+
+    #[serde(default)]
+    user: Option<String>,
+
+    created: u64,
+}
+
+impl Requester {
+    fn set_state(&mut self, state: SlotState) {
+        self.state = state;
+        self.since = Instant::now();
+    }
+}
+
+/// Messages waiting to be written to a render requester by its sender thread, so a worker can
+/// start the next frame while a slow client is still downloading the last one. Messages beyond
+/// `memory` bytes are spilled to disk until the client catches up.
+struct Outbox {
+    queue: Mutex<OutboxQueue>,
+    changed: Condvar,
+    spill: PathBuf,
+    memory: usize,
+}
+
+#[derive(Default)]
+struct OutboxQueue {
+    messages: VecDeque<(Message, Option<String>)>,
+    memory: usize,
+    pending: usize,
+    spilled: usize,
+    closed: bool,
+}
+
+enum Message {
+    Memory(Vec<u8>),
+    Spilled(PathBuf, usize),
+}
+
+impl Message {
+    fn len(&self) -> usize {
+        match self {
+            Message::Memory(data) => data.len(),
+            Message::Spilled(_, len) => *len,
+        }
+    }
+}
+
+impl Outbox {
+    fn new(spill: PathBuf, memory: usize) -> Outbox {
+        Outbox {
+            queue: Mutex::new(OutboxQueue::default()),
+            changed: Condvar::new(),
+            spill,
+            memory,
+        }
+    }
+
+    /// Queues `data` for sending, logging `label` once it was written to the client.
+    fn push(&self, data: Vec<u8>, label: Option<String>) {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.closed {
+            return;
+        }
+
+        let len = data.len();
+        let path = PathBuf::from(format!("{}-{}", self.spill.display(), queue.spilled));
+        let spilled = (queue.memory + len > self.memory).then(|| {
+            self.spill
+                .parent()
+                .map_or(Ok(()), create_dir_all)
+                .and_then(|()| write(&path, &data))
+        });
+
+        let message = match spilled {
+            Some(Ok(())) => {
+                debug!("Client is draining slowly, spilled {} bytes to disk", len);
+                queue.spilled += 1;
+                Message::Spilled(path, len)
+            }
+            Some(Err(error)) => {
+                warn!("Could not spill outgoing data to disk: {}", error);
+                queue.memory += len;
+                Message::Memory(data)
+            }
+            None => {
+                queue.memory += len;
+                Message::Memory(data)
+            }
+        };
+        queue.pending += len;
+        queue.messages.push_back((message, label));
+        self.changed.notify_all();
+    }
+
+    /// Bytes queued or still being written.
+    fn pending(&self) -> usize {
+        self.queue.lock().unwrap().pending
+    }
+
+    fn next(&self) -> Option<(Message, Option<String>)> {
+        let mut queue = self
+            .changed
+            .wait_while(self.queue.lock().unwrap(), |queue| {
+                queue.messages.is_empty() && !queue.closed
+            })
+            .unwrap();
+
+        if queue.closed {
+            return None;
+        }
+
+        queue.messages.pop_front()
+    }
+
+    fn sent(&self, message: &Message) -> bool {
+        let mut queue = self.queue.lock().unwrap();
+        if let Message::Memory(data) = message {
+            queue.memory -= data.len();
+        }
+        queue.pending -= message.len();
+
+        queue.pending == 0
+    }
+
+    /// Drops everything not sent yet and ends the sender thread.
+    fn close(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.closed = true;
+
+        for (message, _) in queue.messages.drain(..) {
+            if let Message::Spilled(path, _) = message {
+                let _ = remove_file(path);
+            }
+        }
+        self.changed.notify_all();
+    }
+}
+
+fn exchange<T: DeserializeOwned>(brpy: &mut TcpStream, request: &[u8]) -> io::Result<T> {
+    brpy.write_all(request)?;
+
+    Ok(serde_json::from_slice(&read_header(brpy)?)?)
+}
+
+impl Worker {
+    fn request<T: DeserializeOwned>(&self, request: &[u8]) -> io::Result<T> {
+        self.exchange(&mut self.brpy.lock().unwrap(), request)
+    }
+
+    /// Exchanges `request` with brpy, restarting a crashed Blender and retrying once.
+    fn exchange<T: DeserializeOwned>(&self, brpy: &mut TcpStream, request: &[u8]) -> io::Result<T> {
+        match exchange(brpy, request) {
+            Ok(response) => Ok(response),
+            Err(error) => {
+                self.recover(brpy, &error);
+                exchange(brpy, request)
+            }
+        }
+    }
+
+    fn render(
+        &self,
+        request: &[u8],
+        mut on_progress: impl FnMut(RenderProgress),
+    ) -> BrpyRenderResponse {
+        let mut brpy = self.brpy.lock().unwrap();
+
+        let mut render = || -> io::Result<BrpyRenderResponse> {
+            brpy.write_all(request)?;
+
+            loop {
+                match serde_json::from_slice(&read_header(&mut *brpy)?)? {
+                    BrpyRenderResponse::Progress(progress) => on_progress(progress),
+                    response => {
+                        return Ok(response);
+                    }
+                }
+            }
+        };
+
+        match render() {
+            Ok(response) => response,
+            Err(error) => {
+                self.recover(&mut brpy, &error);
+                BrpyRenderResponse::Fail
+            }
+        }
+    }
+
+    /// Replaces a crashed Blender process with a fresh one, connected and queried the same way
+    /// as on startup.
+    fn recover(&self, brpy: &mut TcpStream, error: &io::Error) {
+        if self.stopped.load(Ordering::SeqCst) {
+            return;
+        }
+
+        let status = {
+            let mut process = self.process.lock().unwrap();
+            let _ = process.kill();
+            process
+                .wait()
+                .map(|status| status.to_string())
+                .unwrap_or_else(|error| error.to_string())
+        };
+
+        let output = self.output.lock().unwrap().lock().unwrap().clone();
+        error!(
+            "brpy worker {} crashed ({}), Blender exited with {}\nLast output:\n    {}\nRestarting",
+            self.index,
+            error,
+            status,
+            Vec::from(output).join("\n    ")
+        );
+
+        let (stream, process, output) = match launch_brpy(
+            &self.blender,
+            &self.script,
+            self.timeout,
+            self.device.as_deref(),
+        ) {
+            Ok(launched) => launched,
+            Err(message) => {
+                error!(
+                    "Restarting brpy worker {} failed\nReason: {}",
+                    self.index, message
+                );
+                return;
+            }
+        };
+
+        *self.control.lock().unwrap() = stream.try_clone().unwrap();
+        *self.process.lock().unwrap() = process;
+        *self.output.lock().unwrap() = output;
+        *brpy = stream;
+
+        let query = to_header(serde_json::to_vec(&BrpyRequest::Query).unwrap());
+        match exchange::<QueryResponse>(brpy, &query) {
+            Ok(_) => log!("Restarted brpy worker {}", self.index),
+            Err(error) => error!(
+                "Restarted brpy worker {} is not responding: {}",
+                self.index, error
+            ),
+        }
+    }
+
+    fn cancel(&self) {
+        let request = to_header(serde_json::to_vec(&BrpyRequest::Cancel).unwrap());
+        let _ = self.control.lock().unwrap().write_all(&request);
+    }
+
+    /// Asks Blender to terminate and kills it if it is still running after a few seconds.
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        let mut process = self.process.lock().unwrap();
+
+        #[cfg(unix)]
+        {
+            idle::signal_process(process.id(), "TERM");
+
+            let start = Instant::now();
+            while start.elapsed() < BRPY_STOP_TIMEOUT && matches!(process.try_wait(), Ok(None)) {
+                thread::sleep(Duration::from_millis(100));
+            }
+        }
+
+        let _ = process.kill();
+        let _ = process.wait();
+    }
+}
+
+impl Server {
+    /// Sends `request` to the first idle worker, waiting for the first worker if all are busy.
+    /// Sends `request` to an idle worker running `installation`.
+    fn brpy_request<T: DeserializeOwned>(
+        &self,
+        installation: usize,
+        request: &[u8],
+    ) -> io::Result<T> {
+        for worker in &self.workers {
+            if worker.installation == installation
+                && let Ok(mut brpy) = worker.brpy.try_lock()
+            {
+                return worker.exchange(&mut brpy, request);
+            }
+        }
+
+        Err(io::Error::new(
+            ErrorKind::WouldBlock,
+            "No worker for this Blender installation is idle",
+        ))
+    }
+
+    /// The installation matching the Blender requirement `id` was uploaded with.
+    fn job_installation(&self, id: &str) -> Result<usize, String> {
+        let required: Option<BlenderRequirement> = read_to_string(job_dir(id).join("blender"))
+            .ok()
+            .and_then(|required| required.parse().ok());
+
+        self.installation(required.as_ref())
+    }
+
+    /// Picks the Blender installation for `required`, the first one registered if nothing is
+    /// required.
+    fn installation(&self, required: Option<&BlenderRequirement>) -> Result<usize, String> {
+        let required = match required {
+            None => {
+                return Ok(0);
+            }
+            Some(required) => required,
+        };
+
+        let position = if self.info.installations.is_empty() {
+            let default = BlenderInstallation {
+                name: String::from(DEFAULT_INSTALLATION),
+                version: self.info.version,
+            };
+            required.matches(&default).then_some(0)
+        } else {
+            self.info
+                .installations
+                .iter()
+                .position(|installation| required.matches(installation))
+        };
+
+        position.ok_or_else(|| format!("No Blender matching {}", required))
+    }
+
+    fn update_requester(
+        &self,
+        slot: usize,
+        address: SocketAddr,
+        update: impl FnOnce(&mut Requester),
+    ) {
+        if let Some(requester) = &mut self.requesters.lock().unwrap()[slot]
+            && requester.address == address
+        {
+            update(requester);
+        }
+    }
+
+    fn remove_requester(&self, slot: usize, address: SocketAddr) {
+        let mut requesters = self.requesters.lock().unwrap();
+
+        if requesters[slot]
+            .as_ref()
+            .is_some_and(|requester| requester.address == address)
+        {
+            let requester = requesters[slot].take().unwrap();
+            self.notifier.notify_all();
+            drop(requesters);
+
+            self.end_session(&requester.session);
+        }
+    }
+
+    /// Whether the requester in `slot` has kept a worker waiting for a frame request longer than
+    /// `client_timeout` with nothing left to download, or no longer holds the slot.
+    fn is_stalled(&self, slot: usize, address: SocketAddr, outbox: &Outbox) -> bool {
+        let requesters = self.requesters.lock().unwrap();
+
+        match &requesters[slot] {
+            Some(requester) if requester.address == address => {
+                matches!(requester.state, SlotState::AwaitingFrameRequest)
+                    && requester.since.elapsed() >= self.client_timeout
+                    && outbox.pending() == 0
+            }
+            _ => true,
+        }
+    }
+
+    /// Frees the slot of a requester that stopped answering heartbeats, keeping its session so
+    /// the client can reconnect.
+    fn drop_requester(&self, slot: usize, address: SocketAddr) {
+        let requester = {
+            let mut requesters = self.requesters.lock().unwrap();
+            if requesters[slot]
+                .as_ref()
+                .is_some_and(|requester| requester.address == address)
+            {
+                requesters[slot].take()
+            } else {
+                None
+            }
+        };
+
+        if let Some(requester) = requester {
+            self.notifier.notify_all();
+            if let (SlotState::Rendering, Some(worker)) = (requester.state, requester.worker) {
+                self.workers[worker].cancel();
+            }
+
+            let _ = requester.stream.shutdown(Shutdown::Both);
+        }
+    }
+
+    fn save_sessions(&self, sessions: &HashMap<String, Session>) {
+        let _ = write(
+            "sessions.json",
+            serde_json::to_vec_pretty(sessions).unwrap(),
+        );
+    }
+
+    fn end_session(&self, token: &str) {
+        let mut sessions = self.sessions.lock().unwrap();
+        if sessions.remove(token).is_some() {
+            self.save_sessions(&sessions);
+        }
+    }
+
+    fn is_banned(&self, ip: IpAddr) -> bool {
+        let mut bans = self.bans.lock().unwrap();
+        bans.retain(|_, until| *until > Instant::now());
+
+        bans.contains_key(&ip)
+    }
+
+    fn disconnect(&self, slot: usize, ban: Option<u64>) -> AdminResponse {
+        let requester = match self.requesters.lock().unwrap().get_mut(slot) {
+            None => None,
+            Some(requester) => requester.take(),
+        };
+
+        match requester {
+            None => AdminResponse::Fail {
+                message: format!("Slot {} is not occupied", slot),
+            },
+            Some(requester) => {
+                self.notifier.notify_all();
+                if let (SlotState::Rendering, Some(worker)) = (requester.state, requester.worker) {
+                    self.workers[worker].cancel();
+                }
+
+                let _ = requester.stream.shutdown(Shutdown::Both);
+                self.end_session(&requester.session);
+                log!(
+                    "Disconnected {} from slot {} by admin request",
+                    requester.address,
+                    slot
+                );
+
+                if let Some(seconds) = ban {
+                    self.bans.lock().unwrap().insert(
+                        requester.address.ip(),
+                        Instant::now() + Duration::from_secs(seconds),
+                    );
+                    warn!("Banned {} for {}s", requester.address.ip(), seconds);
+                }
+
+                AdminResponse::Okay
+            }
+        }
+    }
+
+    fn job_status(&self, id: &str) -> StatusResponse {
+        let mut status = match self.job_stats.lock().unwrap().get(id) {
+            Some(stats) => StatusResponse {
+                completed: stats.completed,
+                failed: stats.failed,
+                started_ago: stats.started.map(|started| started.elapsed().as_secs()),
+                updated_ago: stats.updated.map(|updated| updated.elapsed().as_secs()),
+                ..StatusResponse::default()
+            },
+            None => StatusResponse::default(),
+        };
+        (status.cache_size, status.cache_original) = frame_cache_size(&job_dir(id));
+
+        let requesters = self.requesters.lock().unwrap();
+        for requester in requesters.iter().flatten() {
+            if requester.id.as_deref() != Some(id) {
+                continue;
+            }
+
+            match requester.state {
+                SlotState::Rendering | SlotState::Sending => status.active += 1,
+                SlotState::Queued | SlotState::AwaitingFrameRequest => status.pending += 1,
+            }
+        }
+
+        status
+    }
+
+    fn restore_job(&self, job_dir: &Path) -> bool {
+        let blend = blend_file(job_dir);
+        if blend.is_file() {
+            touch_job(job_dir);
+            return true;
+        }
+
+        let _ = create_dir_all(job_dir);
+        let restored = self
+            .storage
+            .restore(&blend)
+            .and_then(|()| self.storage.restore_dir(&job_dir.join("inputs")));
+
+        match restored {
+            Ok(()) => {
+                log!("Restored {} from storage", job_dir.display());
+                touch_job(job_dir);
+                true
+            }
+            Err(error) => {
+                if error.kind() != ErrorKind::NotFound {
+                    error!("Restoring {} failed: {}", job_dir.display(), error);
+                }
+                false
+            }
+        }
+    }
+
+    fn active_job_dirs(&self) -> HashSet<PathBuf> {
+        self.requesters
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .filter_map(|requester| requester.id.as_deref())
+            .map(job_dir)
+            .collect()
+    }
+
+    fn reserve_disk(&self, size: u64) -> Result<(), String> {
+        let max_disk = match self.max_disk {
+            Some(max_disk) => max_disk,
+            None => {
+                return Ok(());
+            }
+        };
+
+        let _janitor = self.janitor.lock().unwrap();
+        let used = || {
+            disk::used(Path::new("anonymous"))
+                + disk::used(Path::new("users"))
+                + disk::used(Path::new("blobs"))
+        };
+        if used() + size <= max_disk {
+            return Ok(());
+        }
+
+        let mut jobs = job_dirs().unwrap_or_default();
+
+        for job_dir in &jobs {
+            let frames_dir = job_dir.join("frames");
+            if frames_dir.is_dir() && remove_dir_all(&frames_dir).is_ok() {
+                log!(
+                    "Removed delivered frames of {} to free disk space",
+                    job_dir.display()
+                );
+            }
+        }
+
+        let active = self.active_job_dirs();
+        jobs.retain(|job_dir| !active.contains(job_dir));
+        jobs.sort_by_key(|job_dir| last_used(job_dir));
+
+        for job_dir in jobs {
+            if used() + size <= max_disk {
+                break;
+            }
+
+            if remove_dir_all(&job_dir).is_ok() {
+                log!("Evicted least recently used job {}", job_dir.display());
+                remove_unused_blobs();
+            }
+        }
+
+        let used = used();
+        if used + size <= max_disk {
+            Ok(())
+        } else {
+            Err(format!(
+                "Disk quota exceeded: {} bytes needed, {} of {} bytes in use by active jobs",
+                size, used, max_disk
+            ))
+        }
+    }
+
+    fn store(&self, path: &Path) {
+        if let Err(error) = self.storage.store(path) {
+            error!("Storing {} failed: {}", path.display(), error);
+        }
+    }
+
+    fn update_job_stats(&self, id: &str, update: impl FnOnce(&mut JobStats)) {
+        let mut job_stats = self.job_stats.lock().unwrap();
+        let status = job_stats.entry(String::from(id)).or_default();
+
+        let now = Instant::now();
+        status.started.get_or_insert(now);
+        status.updated = Some(now);
+        update(status);
+    }
+
+    fn cancel_frame(&self, id: &str, frame: Option<usize>) -> Response {
+        let requesters = self.requesters.lock().unwrap();
+        let workers: Vec<usize> = requesters
+            .iter()
+            .flatten()
+            .filter(|requester| {
+                matches!(requester.state, SlotState::Rendering)
+                    && requester.id.as_deref() == Some(id)
+                    && frame.is_none_or(|frame| requester.frame == Some(frame))
+            })
+            .filter_map(|requester| requester.worker)
+            .collect();
+        drop(requesters);
+
+        if !workers.is_empty() {
+            for worker in workers {
+                self.workers[worker].cancel();
+            }
+            log!("Cancelling in-progress render of \"{}\"", id);
+
+            Response::Okay
+        } else {
+            Response::Fail {
+                message: format!("No matching frame of \"{}\" is rendering", id),
+            }
+        }
+    }
+
+    /// Cancels all renders of `id` and answers its further frame requests as cancelled, until a
+    /// new render of `id` is requested.
+    fn cancel_job(&self, id: &str) -> Response {
+        self.cancelled_jobs.lock().unwrap().insert(String::from(id));
+
+        let mut requesters = self.requesters.lock().unwrap();
+        let mut workers = Vec::new();
+        for requester in requesters.iter_mut().flatten() {
+            if requester.id.as_deref() == Some(id) {
+                requester.batch = None;
+
+                if let (SlotState::Rendering, Some(worker)) = (requester.state, requester.worker) {
+                    workers.push(worker);
+                }
+            }
+        }
+        drop(requesters);
+
+        for worker in workers {
+            self.workers[worker].cancel();
+        }
+        log!("Cancelled job \"{}\"", id);
+
+        Response::Okay
+    }
+
+    /// Removes the stored job `id` and its frames, unless it is being rendered.
+    fn delete_job(&self, id: &str) -> Response {
+        let _janitor = self.janitor.lock().unwrap();
+        let job_dir = job_dir(id);
+
+        let message = if !job_dir.is_dir() {
+            format!("\"{}\" is not stored here", id)
+        } else if self.active_job_dirs().contains(&job_dir) {
+            format!("\"{}\" is being rendered", id)
+        } else if let Err(error) = remove_dir_all(&job_dir) {
+            format!("Deleting \"{}\" failed: {}", id, error)
+        } else {
+            if let Err(error) = self.storage.remove_dir(&job_dir) {
+                error!(
+                    "Removing {} from storage failed: {}",
+                    job_dir.display(),
+                    error
+                );
+            }
+            self.job_stats.lock().unwrap().remove(id);
+            remove_unused_blobs();
+            log!("Deleted job \"{}\"", id);
+
+            return Response::Okay;
+        };
+
+        Response::Fail { message }
+    }
+
+    fn is_cancelled(&self, id: &str) -> bool {
+        self.cancelled_jobs.lock().unwrap().contains(id)
+    }
+
+    fn set_connection_state(&self, address: SocketAddr, state: &'static str) {
+        self.connections.lock().unwrap().insert(address, state);
+    }
+
+    /// Registers a new connection, refusing it once its host already holds
+    /// `max_client_connections`.
+    fn open_connection(&self, address: SocketAddr) -> Result<Connection<'_>, String> {
+        let mut connections = self.connections.lock().unwrap();
+
+        let open = connections
+            .keys()
+            .filter(|other| other.ip() == address.ip())
+            .count();
+        if open >= self.max_client_connections {
+            return Err(format!(
+                "Too many connections from {}, at most {} are allowed",
+                address.ip(),
+                self.max_client_connections
+            ));
+        }
+
+        connections.insert(address, "awaiting request");
+        Ok(Connection {
+            server: self,
+            address,
+        })
+    }
+
+    fn check_upload_size(&self, size: usize) -> Result<(), String> {
+        match self.max_upload {
+            Some(max_upload) if size as u64 > max_upload => Err(format!(
+                "Size {} exceeds this server's upload limit of {}",
+                format_size(size as u64),
+                format_size(max_upload)
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn roots(&self) -> Vec<RootUsage> {
+        let mut roots = vec![(String::from("storage"), PathBuf::from("."))];
+        if !self.scratch_dir.as_os_str().is_empty() {
+            roots.push((String::from("scratch"), self.scratch_dir.clone()));
+        }
+
+        roots
+            .into_iter()
+            .map(|(role, path)| {
+                let path = path.canonicalize().unwrap_or(path);
+
+                RootUsage {
+                    role,
+                    used: disk::used(&path.join("anonymous")) + disk::used(&path.join("users")),
+                    available: disk::available(&path),
+                    path: path.display().to_string(),
+                }
+            })
+            .collect()
+    }
+
+    fn dashboard(&self) -> Dashboard {
+        let slots = self.slots();
+        let queued = slots
+            .iter()
+            .filter(|slot| matches!(slot.state, SlotState::Queued))
+            .count();
+
+        let job_stats = self.job_stats.lock().unwrap();
+        let mut jobs: Vec<JobUsage> = match job_dirs() {
+            Ok(job_dirs) => job_dirs
+                .into_iter()
+                .filter_map(|job_dir| {
+                    let id = read_to_string(job_dir.join("id")).ok()?;
+                    let stats = job_stats.get(&id);
+
+                    Some(JobUsage {
+                        bytes: disk::used(&job_dir),
+                        completed: stats.map(|stats| stats.completed).unwrap_or_default(),
+                        failed: stats.map(|stats| stats.failed).unwrap_or_default(),
+                        id,
+                    })
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        jobs.sort_by(|a, b| a.id.cmp(&b.id));
+
+        let [major, minor, patch] = self.info.version;
+        Dashboard {
+            version: format!("{}.{}.{}", major, minor, patch),
+            compute_device_type: self.info.compute_device_type.clone(),
+            devices: self.info.devices.active.clone(),
+            slots,
+            queued,
+            jobs,
+        }
+    }
+
+    fn slots(&self) -> Vec<SlotInfo> {
+        let requesters = self.requesters.lock().unwrap();
+
+        requesters
+            .iter()
+            .enumerate()
+            .filter_map(|(slot, requester)| {
+                requester.as_ref().map(|requester| SlotInfo {
+                    slot,
+                    address: requester.address.to_string(),
+                    id: requester.id.clone(),
+                    frame: requester.frame,
+                    state: requester.state,
+                    seconds_in_state: requester.since.elapsed().as_secs_f64(),
+                    bytes_pending: requester.outbox.pending(),
+                    priority: requester.priority,
+                })
+            })
+            .collect()
+    }
+}
+
+/// Runs a render server with the given options until the process exits.
+pub fn serve(options: Options) {
+    let Options {
+        brpy,
+        work_dir,
+        blender,
+        admin_token,
+        auth_token,
+        upnp,
+        idle_load,
+        idle_input,
+        suspend_blender,
+        max_slots,
+        backpressure,
+        brpy_timeout,
+        brpy_retries,
+        scratch_dir,
+        frame_retention,
+        workers,
+        worker_devices,
+        storage,
+        control_port,
+        no_mdns,
+        max_disk,
+        job_ttl,
+        heartbeat,
+        heartbeat_timeout,
+        http_port,
+        bind: addresses,
+        port,
+        random_port,
+        shutdown_timeout,
+        max_upload,
+        max_client_connections,
+        client_timeout,
+    } = options;
+
+    if auth_token.is_some() || admin_token.is_some() {
+        warn!("Tokens are sent unencrypted, only rely on them on trusted networks");
+    }
+
+    let storage = match storage::open(storage.as_deref()) {
+        Ok(storage) => storage,
+        Err(message) => {
+            error!("{}", message);
+            process::exit(1);
+        }
+    };
+
+    if !brpy.is_file() {
+        panic!(
+            "BRPy script {} either does not exist, access is not permitted or it's not a file",
+            brpy.display()
+        );
+    }
+
+    let installations: Vec<(String, PathBuf)> = if blender.is_empty() {
+        vec![(String::from(DEFAULT_INSTALLATION), PathBuf::from("blender"))]
+    } else {
+        blender
+            .into_iter()
+            .map(|(name, path)| (name, path.canonicalize().unwrap()))
+            .collect()
+    };
+
+    let scratch_dir = match scratch_dir {
+        None => PathBuf::new(),
+        Some(scratch_dir) => {
+            create_dir_all(&scratch_dir).unwrap();
+            scratch_dir.canonicalize().unwrap()
+        }
+    };
+
+    set_current_dir(work_dir).unwrap();
+
+    for dir in ["anonymous", "users", "blobs"] {
+        if let Err(error) = create_dir(dir) {
+            match error.kind() {
+                ErrorKind::AlreadyExists => {}
+                _ => {
+                    panic!("{}", error);
+                }
+            }
+        }
+    }
+
+    let sessions = read("sessions.json")
+        .map(|sessions| load_sessions(&sessions, unix_time()))
+        .unwrap_or_default();
+
+    let listeners = match bind(&addresses, port, random_port) {
+        Ok(listeners) => listeners,
+        Err(message) => {
+            error!("Could not listen for clients\nReason: {}", message);
+            process::exit(1);
+        }
+    };
+    let port = listeners[0].local_addr().unwrap().port();
+
+    let mapping = if upnp {
+        match Mapping::new(port) {
+            Ok(mapping) => Some(mapping),
+            Err(message) => {
+                warn!("UPnP port mapping unavailable\nReason: {}", message);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let advertisement = if no_mdns {
+        None
+    } else {
+        let properties = [
+            ("version", String::from(env!("CARGO_PKG_VERSION"))),
+            ("workers", workers.max(1).to_string()),
+            ("devices", worker_devices.join(",")),
+            ("slots", max_slots.to_string()),
+        ];
+
+        match Advertisement::new(port, &properties) {
+            Ok(advertisement) => Some(advertisement),
+            Err(message) => {
+                warn!("mDNS advertisement unavailable\nReason: {}", message);
+                None
+            }
+        }
+    };
+
+    let per_installation = workers.max(1);
+    let workers: Vec<Worker> = (0..installations.len() * per_installation)
+        .map(|index| {
+            let installation = index / per_installation;
+            let blender = &installations[installation].1;
+            let device = worker_devices
+                .get(index % per_installation)
+                .map(String::as_str);
+            let mut attempt = 0;
+
+            let (stream, process, output) = loop {
+                attempt += 1;
+
+                match launch_brpy(blender, &brpy, Duration::from_secs(brpy_timeout), device) {
+                    Ok(brpy) => break brpy,
+                    Err(message) => {
+                        error!(
+                            "Starting brpy worker {} failed (attempt {} of {})\nReason: {}",
+                            index,
+                            attempt,
+                            brpy_retries + 1,
+                            message
+                        );
+
+                        if attempt > brpy_retries {
+                            error!("Giving up, check the Blender path and the brpy script");
+                            process::exit(1);
+                        }
+                    }
+                }
+            };
+
+            if let Some(device) = device {
+                log!("Started brpy worker {} on {}", index, device);
+            }
+
+            Worker {
+                index,
+                installation,
+                blender: blender.clone(),
+                script: brpy.clone(),
+                timeout: Duration::from_secs(brpy_timeout),
+                device: device.map(String::from),
+                control: Mutex::new(stream.try_clone().unwrap()),
+                brpy: Mutex::new(stream),
+                process: Mutex::new(process),
+                output: Mutex::new(output),
+                stopped: AtomicBool::new(false),
+            }
+        })
+        .collect();
+
+    let query = to_header(serde_json::to_vec(&BrpyRequest::Query).unwrap());
+    let mut info: QueryResponse = startup_request(&workers[0], &query);
+    info.capabilities.push(String::from("zstd"));
+
+    if installations.len() > 1 {
+        info.installations = installations
+            .iter()
+            .enumerate()
+            .map(|(index, (name, _))| {
+                let version =
+                    startup_request::<QueryResponse>(&workers[index * per_installation], &query)
+                        .version;
+                log!(
+                    "Registered Blender \"{}\" ({}.{}.{})",
+                    name,
+                    version[0],
+                    version[1],
+                    version[2]
+                );
+
+                BlenderInstallation {
+                    name: name.clone(),
+                    version,
+                }
+            })
+            .collect();
+    }
+
+    let environment: BrpyEnvironment = startup_request(
+        &workers[0],
+        &to_header(serde_json::to_vec(&BrpyRequest::Environment).unwrap()),
+    );
+
+    let server = Server {
+        info,
+        environment,
+        admin_token,
+        auth_token,
+        requesters: Mutex::new(vec![None]),
+        notifier: Condvar::new(),
+        workers,
+        sessions: Mutex::new(sessions),
+        job_stats: Mutex::new(HashMap::new()),
+        connections: Mutex::new(HashMap::new()),
+        bans: Mutex::new(HashMap::new()),
+        paused: AtomicBool::new(false),
+        held: Mutex::new(None),
+        suspend_blender,
+        max_slots: max_slots.max(1),
+        backpressure,
+        scratch_dir,
+        frame_retention,
+        storage,
+        max_disk,
+        job_ttl,
+        janitor: Mutex::new(()),
+        heartbeat: Duration::from_secs(heartbeat.max(1)),
+        heartbeat_timeout: Duration::from_secs(heartbeat_timeout.max(heartbeat.max(1) * 2)),
+        cancelled_jobs: Mutex::new(HashSet::new()),
+        shutting_down: AtomicBool::new(false),
+        max_upload,
+        max_client_connections: max_client_connections.max(1),
+        client_timeout: Duration::from_secs(client_timeout.max(1)),
+    };
+
+    thread::scope(|scope| {
+        for index in 0..server.workers.len() {
+            let server = &server;
+            scope.spawn(move || {
+                worker_brpy(server, index);
+            });
+        }
+
+        scope.spawn(|| {
+            remove_expired(&server);
+        });
+
+        scope.spawn(|| {
+            send_heartbeats(&server);
+        });
+
+        if let Some(http_port) = http_port {
+            match bind(&addresses, http_port, false) {
+                Ok(listeners) => {
+                    log!("Serving status page on port {}", http_port);
+
+                    for http in listeners {
+                        let server = &server;
+                        scope.spawn(move || {
+                            dashboard::serve(http, server.auth_token.as_deref(), || {
+                                server.dashboard()
+                            });
+                        });
+                    }
+                }
+                Err(error) => {
+                    warn!("HTTP port unavailable: {}", error);
+                }
+            }
+        }
+
+        if idle_load.is_some() || idle_input.is_some() {
+            scope.spawn(|| {
+                monitor_local_use(&server, idle_load, idle_input);
+            });
+        }
+
+        match TcpListener::bind((Ipv6Addr::LOCALHOST, control_port)) {
+            Ok(control) => {
+                let server = &server;
+                scope.spawn(move || {
+                    for stream in control.incoming().flatten() {
+                        let _ = stream.set_read_timeout(Some(server.client_timeout));
+                        let _ = stream.set_write_timeout(Some(server.client_timeout));
+                        handle_node_control(stream, server);
+                    }
+                });
+            }
+            Err(error) => {
+                warn!("Local control port {} unavailable: {}", control_port, error);
+            }
+        }
+
+        #[cfg(unix)]
+        scope.spawn(|| {
+            use signal_hook::{consts::SIGUSR1, iterator::Signals};
+
+            let mut signals = Signals::new([SIGUSR1]).unwrap();
+            for _ in signals.forever() {
+                dump_state(&server);
+            }
+        });
+
+        if let Some(mapping) = &mapping {
+            scope.spawn(|| {
+                mapping.refresh();
+            });
+        }
+
+        #[cfg(unix)]
+        scope.spawn(|| {
+            use signal_hook::{
+                consts::{SIGINT, SIGTERM},
+                iterator::Signals,
+            };
+
+            let mut signals = Signals::new([SIGINT, SIGTERM]).unwrap();
+            for (count, _) in signals.forever().enumerate() {
+                if count > 0 {
+                    warn!("Interrupted again, exiting immediately");
+                    for worker in &server.workers {
+                        let _ = worker.process.lock().unwrap().kill();
+                    }
+                    process::exit(1);
+                }
+
+                let (server, mapping, advertisement) = (&server, &mapping, &advertisement);
+                scope.spawn(move || {
+                    shutdown(server, Duration::from_secs(shutdown_timeout));
+
+                    if let Some(mapping) = mapping {
+                        mapping.release();
+                    }
+                    if let Some(advertisement) = advertisement {
+                        advertisement.withdraw();
+                    }
+                    process::exit(0);
+                });
+            }
+        });
+
+        let server = &server;
+        let accept = move |listener: TcpListener| {
+            log!("Listening on {}", listener.local_addr().unwrap());
+
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(mut stream) => {
+                        let address = match stream.peer_addr() {
+                            Ok(address) => address,
+                            Err(_) => {
+                                continue;
+                            }
+                        };
+
+                        if server.shutting_down.load(Ordering::SeqCst) {
+                            continue;
+                        }
+
+                        if server.is_banned(address.ip()) {
+                            warn!("Refused connection from banned address {}", address);
+                            continue;
+                        }
+
+                        let _ = stream.set_read_timeout(Some(server.client_timeout));
+                        let _ = stream.set_write_timeout(Some(server.client_timeout));
+
+                        let connection = match server.open_connection(address) {
+                            Ok(connection) => connection,
+                            Err(message) => {
+                                warn!("Refused connection from {}: {}", address, message);
+
+                                let response = Response::Fail { message };
+                                let _ = stream
+                                    .write_all(&to_header(serde_json::to_vec(&response).unwrap()));
+                                continue;
+                            }
+                        };
+
+                        scope.spawn(move || {
+                            let _connection = connection;
+
+                            if authenticate(&mut stream, address, server) {
+                                handle_client(stream, address, server);
+                            }
+                        });
+                    }
+                    Err(error) => {
+                        error!("Failed to establish new connection: {}", error);
+                    }
+                }
+            }
+        };
+
+        let mut listeners = listeners.into_iter();
+        let first = listeners.next().unwrap();
+        for listener in listeners {
+            scope.spawn(move || accept(listener));
+        }
+
+        accept(first);
+    })
+}
+
+/// Sends a request brpy must answer for the server to start, exiting if it cannot.
+fn startup_request<T: DeserializeOwned>(worker: &Worker, request: &[u8]) -> T {
+    match worker.request(request) {
+        Ok(response) => response,
+        Err(error) => {
+            error!(
+                "brpy worker {} is not responding\nReason: {}",
+                worker.index, error
+            );
+            process::exit(1);
+        }
+    }
+}
+
+fn parse_installation(installation: &str) -> Result<(String, PathBuf), String> {
+    match installation.split_once('=') {
+        Some((name, path)) if !name.is_empty() && !path.is_empty() => {
+            Ok((String::from(name), PathBuf::from(path)))
+        }
+        Some(_) => Err(String::from("Expected name=path")),
+        None => Ok((
+            String::from(DEFAULT_INSTALLATION),
+            PathBuf::from(installation),
+        )),
+    }
+}
+
+/// Stops taking on work, waits up to `timeout` for frames that are being rendered or sent, tells
+/// render requesters to requeue their frames elsewhere and stops the brpy workers.
+fn shutdown(server: &Server, timeout: Duration) {
+    log!("Shutting down, waiting for frames in progress");
+    server.shutting_down.store(true, Ordering::SeqCst);
+    server.notifier.notify_all();
+
+    let busy = || {
+        server
+            .requesters
+            .lock()
+            .unwrap()
+            .iter()
+            .flatten()
+            .any(|requester| {
+                matches!(requester.state, SlotState::Rendering | SlotState::Sending)
+                    || requester.outbox.pending() > 0
+            })
+    };
+
+    let start = Instant::now();
+    while busy() {
+        if start.elapsed() >= timeout {
+            warn!(
+                "Frames still in progress after {}s, cancelling them",
+                timeout.as_secs()
+            );
+            for worker in &server.workers {
+                worker.cancel();
+            }
+            break;
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    let message = to_header(serde_json::to_vec(&Heartbeat::Shutdown).unwrap());
+    for requester in server.requesters.lock().unwrap().iter().flatten() {
+        if requester.heartbeat
+            && let Ok(mut writer) = requester.writer.try_lock()
+        {
+            let _ = writer.write_all(&message);
+        }
+
+        let _ = requester.stream.shutdown(Shutdown::Both);
+    }
+
+    for worker in &server.workers {
+        worker.stop();
+    }
+
+    log!("Shut down");
+}
+
+/// Binds a listener on `port` for every address. Without addresses the IPv6 wildcard is tried
+/// first and the IPv4 one second, for hosts with IPv6 disabled.
+fn bind(addresses: &[IpAddr], port: u16, random_port: bool) -> Result<Vec<TcpListener>, String> {
+    if addresses.is_empty() {
+        let listener = match bind_address(IpAddr::V6(Ipv6Addr::UNSPECIFIED), port, random_port) {
+            Err(error) if error.kind() != ErrorKind::AddrInUse => {
+                debug!("Binding the IPv6 wildcard failed: {}", error);
+                bind_address(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port, random_port)
+            }
+            result => result,
+        };
+
+        return listener
+            .map(|listener| vec![listener])
+            .map_err(|error| format!("Port {}: {}", port, error));
+    }
+
+    let mut listeners: Vec<TcpListener> = Vec::new();
+    for address in addresses {
+        let listener = match listeners.first() {
+            None => bind_address(*address, port, random_port),
+            Some(first) => TcpListener::bind((*address, first.local_addr().unwrap().port())),
+        };
+
+        listeners.push(listener.map_err(|error| format!("{} port {}: {}", address, port, error))?);
+    }
+
+    Ok(listeners)
+}
+
+fn bind_address(address: IpAddr, port: u16, random_port: bool) -> io::Result<TcpListener> {
+    match TcpListener::bind((address, port)) {
+        Err(error) if random_port && error.kind() == ErrorKind::AddrInUse => {
+            warn!("Port {} is taken, falling back to a random port", port);
+            TcpListener::bind((address, 0))
+        }
+        result => result,
+    }
+}
+
+fn authenticate(client: &mut TcpStream, address: SocketAddr, server: &Server) -> bool {
+    let auth_token = match &server.auth_token {
+        Some(auth_token) => auth_token,
+        None => {
+            return true;
+        }
+    };
+
+    let request = read_header(client)
+        .ok()
+        .and_then(|request| protocol::decode(&request).ok());
+
+    let response = match request {
+        Some(Request::Auth { token }) if protocol::tokens_match(&token, auth_token) => {
+            Response::Okay
+        }
+        _ => {
+            warn!("Refused unauthenticated connection from {}", address);
+
+            Response::Fail {
+                message: "Authentication failed".to_string(),
+            }
+        }
+    };
+
+    let authenticated = matches!(response, Response::Okay);
+    let _ = client.write_all(&to_header(serde_json::to_vec(&response).unwrap()));
+
+    authenticated
+}
+
+fn handle_client(mut client: TcpStream, address: SocketAddr, server: &Server) {
+    let _client = logging::span("client", address);
+
+    loop {
+        server.set_connection_state(address, "awaiting request");
+        let request = match read_header(&mut client) {
+            Ok(request) => request,
+            Err(_) => {
+                return;
+            }
+        };
+
+        let mut request = match protocol::decode::<Request>(&request)
+            .and_then(|request| request.validate().map(|()| request))
+        {
+            Ok(request) => request,
+            Err(message) => {
+                warn!("Rejected request from {}: {}", address, message);
+
+                let response = Response::Fail { message };
+                let _ = client.write_all(&to_header(serde_json::to_vec(&response).unwrap()));
+                return;
+            }
+        };
+        request.qualify();
+        let _job = request.id().map(|id| logging::span("id", id));
+
+        match request {
+            Request::Upload {
+                id,
+                size,
+                ttl,
+                hash,
+                blender,
+                compressed,
+                ..
+            } => {
+                server.set_connection_state(address, "receiving upload");
+
+                let compressed = compressed && hash.is_some();
+
+                let job_dir = create_job_dir(&id, ttl);
+
+                let blend = blend_file(&job_dir);
+                let present = hash
+                    .as_deref()
+                    .is_some_and(|hash| link_blob(&job_dir, hash).is_ok());
+
+                let reserved = match server.installation(blender.as_ref()) {
+                    Err(reason) => Err(reason),
+                    Ok(_) if present => Ok(()),
+                    Ok(_) => server
+                        .check_upload_size(size)
+                        .and_then(|()| server.reserve_disk(size as u64)),
+                };
+
+                save_requirement(&job_dir, blender.as_ref());
+
+                if hash.is_some() {
+                    let response = match &reserved {
+                        Err(message) => UploadResponse::Reject {
+                            message: message.clone(),
+                        },
+                        Ok(()) if present => UploadResponse::Present,
+                        Ok(()) => UploadResponse::Send { compressed },
+                    };
+                    let response = to_header(serde_json::to_vec(&response).unwrap());
+                    if client.write_all(&response).is_err() {
+                        return;
+                    }
+                }
+
+                if let Err(message) = reserved {
+                    warn!("Rejected upload with ID \"{}\": {}", id, message);
+
+                    if !blend.is_file() {
+                        let _ = remove_dir_all(&job_dir);
+                    }
+                    if hash.is_none() {
+                        let response = Response::Fail { message };
+                        let _ =
+                            client.write_all(&to_header(serde_json::to_vec(&response).unwrap()));
+                    }
+                    return;
+                }
+
+                let received = if present {
+                    Ok(())
+                } else {
+                    receive_file(&client, &blend, size, compressed)
+                        .and_then(|()| verify_blob(&blend, hash.as_deref()))
+                };
+                let received = received.and_then(|()| server.storage.store(&blend));
+
+                if let Err(error) = received {
+                    error!("Receiving .blend file with ID \"{}\" failed: {}", id, error);
+
+                    let response = Response::Fail {
+                        message: format!("Could not save file: {}", error),
+                    };
+                    let _ = client.write_all(&to_header(serde_json::to_vec(&response).unwrap()));
+                    return;
+                }
+
+                if !present && let Some(hash) = &hash {
+                    save_blob(&job_dir, hash);
+                }
+
+                let response = to_header(serde_json::to_vec(&Response::Okay).unwrap());
+                if client.write_all(&response).is_err() {
+                    return;
+                }
+
+                if present {
+                    log!("Reused stored .blend file for ID \"{}\"", id);
+                } else {
+                    log!("Saved .blend file with ID \"{}\"", id);
+                }
+            }
+            Request::Seed {
+                id,
+                source,
+                size,
+                checksum,
+                ttl,
+                blender,
+                ..
+            } => {
+                server.set_connection_state(address, "fetching from peer");
+
+                let job_dir = create_job_dir(&id, ttl);
+                save_requirement(&job_dir, blender.as_ref());
+
+                let blend = blend_file(&job_dir);
+                let fetched = server
+                    .installation(blender.as_ref())
+                    .and_then(|_| match link_blob(&job_dir, &checksum) {
+                        Ok(()) => Ok(()),
+                        Err(_) => server
+                            .check_upload_size(size)
+                            .and_then(|()| server.reserve_disk(size as u64))
+                            .and_then(|()| {
+                                let options = ClientOptions {
+                                    auth_token: server.auth_token.clone(),
+                                    ..ClientOptions::default()
+                                };
+                                with_options(Arc::new(options), || {
+                                    fetch_blend(&source, &id, &blend, size, &checksum)
+                                })
+                            })
+                            .inspect(|()| save_blob(&job_dir, &checksum)),
+                    })
+                    .and_then(|()| {
+                        server
+                            .storage
+                            .store(&blend)
+                            .map_err(|error| error.to_string())
+                    });
+
+                let response = match fetched {
+                    Ok(()) => {
+                        log!("Fetched .blend file with ID \"{}\" from {}", id, source);
+                        Response::Okay
+                    }
+                    Err(message) => {
+                        error!(
+                            "Fetching .blend file with ID \"{}\" from {} failed: {}",
+                            id, source, message
+                        );
+                        Response::Fail { message }
+                    }
+                };
+
+                let response = to_header(serde_json::to_vec(&response).unwrap());
+                if client.write_all(&response).is_err() {
+                    return;
+                }
+            }
+            Request::Fetch { id, .. } => {
+                server.set_connection_state(address, "seeding peer");
+
+                let job_dir = job_dir(&id);
+                server.restore_job(&job_dir);
+
+                let file = match File::open(blend_file(&job_dir)) {
+                    Ok(file) => file,
+                    Err(_) => {
+                        let response = Response::Fail {
+                            message: format!("No .blend file with ID \"{}\"", id),
+                        };
+                        let response = to_header(serde_json::to_vec(&response).unwrap());
+                        if client.write_all(&response).is_err() {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+
+                let response = to_header(serde_json::to_vec(&Response::Okay).unwrap());
+                if client.write_all(&response).is_err() {
+                    return;
+                }
+
+                if let Err(error) = transfer::send_chunked(file, &client, |_| {}) {
+                    error!("Seeding .blend file with ID \"{}\" failed: {}", id, error);
+                    return;
+                }
+            }
+            Request::UploadInput {
+                id,
+                pass,
+                name,
+                size,
+                ..
+            } => {
+                server.set_connection_state(address, "receiving input");
+
+                if let Err(message) = server.check_upload_size(size) {
+                    warn!(
+                        "Rejected input {}/{} for \"{}\": {}",
+                        pass, name, id, message
+                    );
+
+                    let response = Response::Fail { message };
+                    let _ = client.write_all(&to_header(serde_json::to_vec(&response).unwrap()));
+                    return;
+                }
+
+                let received = match input_file(&job_dir(&id), &pass, &name) {
+                    None => transfer::receive_chunked(&client, io::sink(), size).map(|_| {
+                        Response::Fail {
+                            message: format!("Invalid input name {}/{}", pass, name),
+                        }
+                    }),
+                    Some(path) => {
+                        let _ = create_dir_all(path.parent().unwrap());
+                        receive_file(&client, &path, size, false)
+                            .and_then(|()| server.storage.store(&path))
+                            .map(|()| Response::Okay)
+                    }
+                };
+
+                let header = match received {
+                    Ok(header) => header,
+                    Err(error) => {
+                        error!("Receiving input {}/{} failed: {}", pass, name, error);
+
+                        let response = Response::Fail {
+                            message: format!("Could not save file: {}", error),
+                        };
+                        let _ =
+                            client.write_all(&to_header(serde_json::to_vec(&response).unwrap()));
+                        return;
+                    }
+                };
+
+                let response = to_header(serde_json::to_vec(&header).unwrap());
+                if client.write_all(&response).is_err() {
+                    return;
+                }
+
+                log!("Saved input {}/{} for \"{}\"", pass, name, id);
+            }
+            Request::Render {
+                id,
+                prefetch,
+                addons,
+                heartbeat,
+                blender,
+                compression,
+                priority,
+                user,
+            } => {
+                if let Some(id) = &id {
+                    server.cancelled_jobs.lock().unwrap().remove(id);
+                }
+
+                let token = match random_token() {
+                    Ok(token) => token,
+                    Err(error) => {
+                        error!("Could not create a session for {}: {}", address, error);
+
+                        let response = to_header(
+                            serde_json::to_vec(&RenderAcceptResponse::Reject {
+                                reason: RejectReason::Failed {
+                                    message: String::from("Could not create a session"),
+                                },
+                            })
+                            .unwrap(),
+                        );
+                        let _ = client.write_all(&response);
+                        return;
+                    }
+                };
+                let session = Session {
+                    id,
+                    prefetch,
+                    addons,
+                    heartbeat,
+                    blender,
+                    compression: compression.filter(|level| (1..=22).contains(level)),
+                    priority: priority.map(|priority| priority.min(MAX_PRIORITY)),
+                    user,
+                    created: unix_time(),
+                };
+
+                if let Some(requests) =
+                    add_requester(client.try_clone().unwrap(), address, server, token, session)
+                {
+                    read_requests(client, address, server, requests);
+                }
+                return;
+            }
+            Request::Reconnect { session: token } => {
+                let session = server.sessions.lock().unwrap().get(&token).cloned();
+
+                match session {
+                    None => {
+                        warn!("Unknown session from {}, asking for a new one", address);
+
+                        let response = to_header(
+                            serde_json::to_vec(&RenderAcceptResponse::Reject {
+                                reason: RejectReason::UnknownSession,
+                            })
+                            .unwrap(),
+                        );
+                        if client.write_all(&response).is_err() {
+                            return;
+                        }
+                    }
+                    Some(session) => {
+                        log!("Resuming session of {}", address);
+
+                        if let Some(requests) = add_requester(
+                            client.try_clone().unwrap(),
+                            address,
+                            server,
+                            token,
+                            session,
+                        ) {
+                            read_requests(client, address, server, requests);
+                        }
+                        return;
+                    }
+                }
+            }
+            Request::BakeTextures {
+                id,
+                objects,
+                maps,
+                resolution,
+                ..
+            } => {
+                server.set_connection_state(address, "baking textures");
+                bake_textures(&mut client, server, &id, objects, maps, resolution);
+            }
+            Request::Delete { id, .. } => {
+                let response = server.delete_job(&id);
+
+                if client
+                    .write_all(&to_header(serde_json::to_vec(&response).unwrap()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Request::Thumbnail { id, frame, .. } => {
+                let response = match read(thumbnail_file(&job_dir(&id), frame)) {
+                    Ok(mut image) => {
+                        let mut response = to_header(
+                            serde_json::to_vec(&ThumbnailResponse::Okay { size: image.len() })
+                                .unwrap(),
+                        );
+                        response.append(&mut image);
+                        response
+                    }
+                    Err(_) => to_header(
+                        serde_json::to_vec(&ThumbnailResponse::Fail {
+                            message: format!("Frame {} of \"{}\" was not rendered here", frame, id),
+                        })
+                        .unwrap(),
+                    ),
+                };
+
+                if client.write_all(&response).is_err() {
+                    return;
+                }
+            }
+            Request::FetchFrame {
+                id,
+                frame,
+                compressed,
+                ..
+            } => {
+                server.set_connection_state(address, "sending retained frame");
+
+                let image = retained_frame(&job_dir(&id), frame).and_then(|path| {
+                    let data = transfer::read_file(&path).ok()?.to_vec();
+                    let image = if is_compressed(&path) {
+                        zstd::decode_all(&data[..]).ok()?
+                    } else {
+                        data.clone()
+                    };
+
+                    Some((image, data, path))
+                });
+
+                let (image, stored, path) = match image {
+                    Some(image) => image,
+                    None => {
+                        let response = RenderResponse::Fail {
+                            cancelled: false,
+                            job_cancelled: false,
+                        };
+                        if client
+                            .write_all(&to_header(serde_json::to_vec(&response).unwrap()))
+                            .is_err()
+                        {
+                            return;
+                        }
+                        continue;
+                    }
+                };
+
+                let compressed = compressed && is_compressed(&path);
+                let data = if compressed { &stored } else { &image };
+
+                let header = to_header(
+                    serde_json::to_vec(&RenderResponse::Okay {
+                        size: data.len(),
+                        extension: retained_extension(&path),
+                        format_override: None,
+                        checksum: Some(hash(&image)),
+                        compressed,
+                        stats: None,
+                        passes: Vec::new(),
+                    })
+                    .unwrap(),
+                );
+
+                if transfer::write_all(&client, &header)
+                    .and_then(|()| transfer::write_all(&client, data))
+                    .is_err()
+                {
+                    return;
+                }
+
+                log!("Sent retained frame {} of \"{}\"", frame, id);
+            }
+            Request::Fingerprint { id, .. } => {
+                let job_dir = job_dir(&id);
+                server.restore_job(&job_dir);
+
+                let response = match read(blend_file(&job_dir)) {
+                    Ok(blend) => FingerprintResponse::Okay {
+                        fingerprint: Fingerprint {
+                            brsp_version: String::from(env!("CARGO_PKG_VERSION")),
+                            blender_version: server.info.version,
+                            blender_build_hash: server.environment.build_hash.clone(),
+                            addons: server.environment.addons.clone(),
+                            gpu_driver: server.environment.gpu_driver.clone(),
+                            blend_hash: hash(&blend),
+                        },
+                    },
+                    Err(_) => FingerprintResponse::Fail {
+                        message: format!("No .blend file found for ID \"{}\"", id),
+                    },
+                };
+
+                if client
+                    .write_all(&to_header(serde_json::to_vec(&response).unwrap()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Request::FrameRate { id, .. } => {
+                let job_dir = job_dir(&id);
+
+                let response = if server.restore_job(&job_dir) {
+                    let request = to_header(
+                        serde_json::to_vec(&BrpyRequest::FrameRate {
+                            blend: blend_file(&job_dir),
+                        })
+                        .unwrap(),
+                    );
+
+                    let response = server.job_installation(&id).and_then(|installation| {
+                        server
+                            .brpy_request(installation, &request)
+                            .map_err(|error| error.to_string())
+                    });
+
+                    match response {
+                        Ok(BrpyFrameRate { fps, fps_base }) => FrameRateResponse::Okay {
+                            framerate: fps as f64 / fps_base,
+                        },
+                        Err(message) => FrameRateResponse::Fail {
+                            message: format!("Reading the frame rate failed: {}", message),
+                        },
+                    }
+                } else {
+                    FrameRateResponse::Fail {
+                        message: format!("No .blend file found for ID \"{}\"", id),
+                    }
+                };
+
+                if client
+                    .write_all(&to_header(serde_json::to_vec(&response).unwrap()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Request::Cancel { id, .. } => {
+                let response = server.cancel_job(&id);
+
+                if client
+                    .write_all(&to_header(serde_json::to_vec(&response).unwrap()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Request::CancelFrame { id, frame, .. } => {
+                let response = server.cancel_frame(&id, frame);
+
+                if client
+                    .write_all(&to_header(serde_json::to_vec(&response).unwrap()))
+                    .is_err()
+                {
+                    return;
+                }
+            }
+            Request::NullRender { size } => {
+                if server.auth_token.is_none() || size > NULL_RENDER_LIMIT {
+                    if server.auth_token.is_none() {
+                        warn!("Refused null render from {}: no auth token is set", address);
+                    }
+
+                    let response = to_header(
+                        serde_json::to_vec(&RenderResponse::Fail {
+                            cancelled: false,
+                            job_cancelled: false,
+                        })
+                        .unwrap(),
+                    );
+                    if client.write_all(&response).is_err() {
+                        return;
+                    }
+                    continue;
+                }
+
+                let header = to_header(
+                    serde_json::to_vec(&RenderResponse::Okay {
+                        size,
+                        extension: String::from("null"),
+                        format_override: None,
+                        checksum: None,
+                        compressed: false,
+                        stats: None,
+                        passes: Vec::new(),
+                    })
+                    .unwrap(),
+                );
+                if send_zeros(&mut client, &header, size).is_err() {
+                    return;
+                }
+            }
+            Request::Status { id, .. } => {
+                let response = to_header(serde_json::to_vec(&server.job_status(&id)).unwrap());
+                if client.write_all(&response).is_err() {
+                    return;
+                }
+            }
+            Request::List { user } => {
+                let response = ListResponse::Okay {
+                    jobs: stored_jobs(user.as_deref()),
+                };
+                let response = to_header(serde_json::to_vec(&response).unwrap());
+
+                if client.write_all(&response).is_err() {
+                    return;
+                }
+            }
+            Request::Query { user } => {
+                let mut info = server.info.clone();
+                info.roots = server.roots();
+                info.blends = stored_blends(user.as_deref());
+                info.time = Some(unix_time());
+
+                let response = to_header(serde_json::to_vec(&info).unwrap());
+
+                if client.write_all(&response).is_err() {
+                    return;
+                }
+            }
+            Request::Auth { .. } => {
+                let response = to_header(serde_json::to_vec(&Response::Okay).unwrap());
+                if client.write_all(&response).is_err() {
+                    return;
+                }
+            }
+            Request::Admin { token, request } => {
+                let response = match &server.admin_token {
+                    None => AdminResponse::Fail {
+                        message: "Admin requests are disabled on this server".to_string(),
+                    },
+                    Some(admin_token)
+                        if !token
+                            .is_some_and(|token| protocol::tokens_match(&token, admin_token)) =>
+                    {
+                        warn!("Rejected admin request from {}", address);
+
+                        AdminResponse::Fail {
+                            message: "Invalid admin token".to_string(),
+                        }
+                    }
+                    Some(_) => match request {
+                        AdminRequest::Dump => {
+                            dump_state(server);
+                            AdminResponse::Okay
+                        }
+                        AdminRequest::Slots => AdminResponse::Slots {
+                            slots: server.slots(),
+                        },
+                        AdminRequest::Disconnect { slot, ban } => server.disconnect(slot, ban),
+                        AdminRequest::Unban { address } => {
+                            match server.bans.lock().unwrap().remove(&address) {
+                                Some(_) => {
+                                    log!("Unbanned {}", address);
+                                    AdminResponse::Okay
+                                }
+                                None => AdminResponse::Fail {
+                                    message: format!("{} is not banned", address),
+                                },
+                            }
+                        }
+                    },
+                };
+
+                let response = to_header(serde_json::to_vec(&response).unwrap());
+                if client.write_all(&response).is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+fn dump_state(server: &Server) {
+    let mut output = String::from("State dump:\n    Render slots:");
+
+    match server.requesters.try_lock() {
+        Ok(requesters) => {
+            for (slot, requester) in requesters.iter().enumerate() {
+                match requester {
+                    None => {
+                        output += &format!("\n        {}: free", slot);
+                    }
+                    Some(requester) => {
+                        output += &format!(
+                            "\n        {}: {} {:?} for {:.1}s",
+                            slot,
+                            requester.address,
+                            requester.state,
+                            requester.since.elapsed().as_secs_f64()
+                        );
+
+                        if let (Some(id), Some(frame)) = (&requester.id, requester.frame) {
+                            output += &format!(", frame {} of \"{}\"", frame, id);
+                        }
+
+                        let pending = requester.outbox.pending();
+                        if pending > 0 {
+                            output += &format!(", {} bytes pending", pending);
+                        }
+                    }
+                }
+            }
+        }
+        Err(TryLockError::WouldBlock) => {
+            output += "\n        render slots locked";
+        }
+        Err(TryLockError::Poisoned(_)) => {
+            output += "\n        poisoned";
+        }
+    }
+
+    output += "\n    BRPy workers:";
+    for (index, worker) in server.workers.iter().enumerate() {
+        let mut process = worker.process.lock().unwrap();
+        match process.try_wait() {
+            Ok(None) => {
+                output += &format!(
+                    "\n        {}: running (PID {}, {})",
+                    index,
+                    process.id(),
+                    worker.blender.display()
+                );
+            }
+            Ok(Some(status)) => {
+                output += &format!("\n        {}: exited ({})", index, status);
+            }
+            Err(error) => {
+                output += &format!("\n        {}: unknown ({})", index, error);
+            }
+        }
+    }
+
+    output += "\n    Connection threads:";
+    for (address, state) in server.connections.lock().unwrap().iter() {
+        output += &format!("\n        {}: {}", address, state);
+    }
+
+    log!("{}", output);
+}
+
+fn job_dir(key: &str) -> PathBuf {
+    match protocol::split_key(key) {
+        (None, id) => protocol::namespace_path(
+            Path::new("anonymous"),
+            &blake3::hash(id.as_bytes()).to_hex(),
+        )
+        .unwrap(),
+        (Some(user), id) => protocol::namespace_path(&user_dir(user), id).unwrap(),
+    }
+}
+
+fn user_dir(user: &str) -> PathBuf {
+    protocol::namespace_path(Path::new("users"), user).unwrap()
+}
+
+/// Job directories of all namespaces. Fails only if the anonymous namespace can't be read.
+fn job_dirs() -> Result<Vec<PathBuf>, io::Error> {
+    let mut job_dirs: Vec<PathBuf> = read_dir("anonymous")?
+        .flatten()
+        .map(|entry| entry.path())
+        .collect();
+
+    if let Ok(users) = read_dir("users") {
+        for user in users.flatten() {
+            if let Ok(entries) = read_dir(user.path()) {
+                job_dirs.extend(entries.flatten().map(|entry| entry.path()));
+            }
+        }
+    }
+
+    Ok(job_dirs)
+}
+
+/// Sessions saved by a previous run that are younger than `SESSION_TTL`, so their clients can
+/// reconnect.
+fn load_sessions(data: &[u8], now: u64) -> HashMap<String, Session> {
+    let mut sessions: HashMap<String, Session> = serde_json::from_slice(data).unwrap_or_default();
+    sessions.retain(|_, session| session.created + SESSION_TTL > now);
+
+    sessions
+}
+
+/// 32 bytes from the OS random source, hex-encoded, so session tokens can't be guessed.
+fn random_token() -> io::Result<String> {
+    let mut bytes = [0; 32];
+    getrandom::fill(&mut bytes)?;
+
+    Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+/// Sends `header` followed by `size` zero bytes, without holding them all in memory.
+fn send_zeros(client: &mut TcpStream, header: &[u8], mut size: usize) -> io::Result<()> {
+    static ZEROS: [u8; 1 << 16] = [0; 1 << 16];
+
+    client.write_all(header)?;
+    while size > 0 {
+        let len = size.min(ZEROS.len());
+        client.write_all(&ZEROS[..len])?;
+        size -= len;
+    }
+
+    Ok(())
+}
+
+fn blob_file(hash: &str) -> Result<PathBuf, io::Error> {
+    if hash.len() != 64 || !hash.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "Invalid blob hash"));
+    }
+
+    Ok(PathBuf::from(format!("blobs/{}.blend", hash)))
+}
+
+fn link_blob(job_dir: &Path, hash: &str) -> Result<(), io::Error> {
+    let blob = blob_file(hash)?;
+    if !blob.is_file() {
+        return Err(io::Error::new(ErrorKind::NotFound, "Unknown blob"));
+    }
+
+    let linked = job_dir.join("blend.link");
+    let _ = remove_file(&linked);
+    hard_link(blob, &linked)?;
+
+    if let Err(error) = rename(&linked, blend_file(job_dir)) {
+        let _ = remove_file(&linked);
+        return Err(error);
+    }
+
+    write(job_dir.join("blob"), hash)
+}
+
+fn save_blob(job_dir: &Path, hash: &str) {
+    if let Ok(blob) = blob_file(hash) {
+        let _ = write(job_dir.join("blob"), hash);
+        let _ = hard_link(blend_file(job_dir), blob);
+    }
+}
+
+fn verify_blob(blend: &Path, hash: Option<&str>) -> Result<(), io::Error> {
+    let hash = match hash {
+        Some(hash) => hash,
+        None => {
+            return Ok(());
+        }
+    };
+
+    let received = transfer::checksum(File::open(blend)?)?;
+    if received != hash {
+        let _ = remove_file(blend);
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!("Checksum mismatch (expected {}, got {})", hash, received),
+        ));
+    }
+
+    Ok(())
+}
+
+/// Job directories with a .blend file in the namespace of `user`, limited to the
+/// `MAX_LISTED_JOBS` most recently used so listings fit into a single header.
+fn namespace_jobs(user: Option<&str>) -> Vec<PathBuf> {
+    let namespace = match user {
+        None => PathBuf::from("anonymous"),
+        Some(user) => user_dir(user),
+    };
+
+    let mut job_dirs: Vec<PathBuf> = match read_dir(namespace) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|job_dir| blend_file(job_dir).is_file())
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    job_dirs.sort_by_key(|job_dir| Reverse(last_used(job_dir)));
+    job_dirs.truncate(MAX_LISTED_JOBS);
+    job_dirs
+}
+
+fn job_id(job_dir: &Path) -> Option<String> {
+    let key = read_to_string(job_dir.join("id")).ok()?;
+    Some(String::from(protocol::split_key(&key).1))
+}
+
+fn stored_blends(user: Option<&str>) -> Vec<StoredBlend> {
+    let mut blends: Vec<StoredBlend> = namespace_jobs(user)
+        .into_iter()
+        .filter_map(|job_dir| {
+            Some(StoredBlend {
+                id: job_id(&job_dir)?,
+                hash: read_to_string(job_dir.join("blob")).ok(),
+            })
+        })
+        .collect();
+
+    blends.sort_by(|a, b| a.id.cmp(&b.id));
+    blends
+}
+
+fn stored_jobs(user: Option<&str>) -> Vec<StoredJob> {
+    let mut jobs: Vec<StoredJob> = namespace_jobs(user)
+        .into_iter()
+        .filter_map(|job_dir| {
+            Some(StoredJob {
+                id: job_id(&job_dir)?,
+                bytes: disk::used(&job_dir),
+                last_used: last_used(&job_dir),
+                expires: read_to_string(job_dir.join("expires"))
+                    .ok()
+                    .and_then(|expires| expires.trim().parse().ok()),
+            })
+        })
+        .collect();
+
+    jobs.sort_by(|a, b| a.id.cmp(&b.id));
+    jobs
+}
+
+fn remove_unused_blobs() {
+    let used: HashSet<String> = match job_dirs() {
+        Ok(job_dirs) => job_dirs
+            .into_iter()
+            .filter_map(|job_dir| read_to_string(job_dir.join("blob")).ok())
+            .collect(),
+        Err(_) => {
+            return;
+        }
+    };
+
+    if let Ok(entries) = read_dir("blobs") {
+        for entry in entries.flatten() {
+            let blob = entry.path();
+            let hash = blob.file_stem().and_then(|stem| stem.to_str());
+
+            if hash.is_some_and(|hash| !used.contains(hash)) && remove_file(&blob).is_ok() {
+                debug!("Removed unused blob {}", blob.display());
+            }
+        }
+    }
+}
+
+fn touch_job(job_dir: &Path) {
+    let _ = write(job_dir.join("used"), unix_time().to_string());
+}
+
+fn last_used(job_dir: &Path) -> u64 {
+    read_to_string(job_dir.join("used"))
+        .ok()
+        .and_then(|used| used.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size
+        .trim()
+        .trim_end_matches(['B', 'b'])
+        .trim_end_matches('i');
+    let (number, unit) = match size.find(|character: char| character.is_ascii_alphabetic()) {
+        Some(index) => size.split_at(index),
+        None => (size, ""),
+    };
+
+    let factor: u64 = match unit.to_ascii_uppercase().as_str() {
+        "" => 1,
+        "K" => 1 << 10,
+        "M" => 1 << 20,
+        "G" => 1 << 30,
+        "T" => 1 << 40,
+        _ => {
+            return Err(format!("Unknown size unit \"{}\"", unit));
+        }
+    };
+
+    match number.trim().parse::<f64>() {
+        Ok(number) if number >= 0.0 => Ok((number * factor as f64) as u64),
+        _ => Err(format!("Invalid size \"{}\"", size)),
+    }
+}
+
+fn parse_duration(duration: &str) -> Result<u64, String> {
+    let duration = duration.trim();
+    let (number, factor) = match duration.chars().last() {
+        Some('s') => (&duration[..duration.len() - 1], 1),
+        Some('m') => (&duration[..duration.len() - 1], 60),
+        Some('h') => (&duration[..duration.len() - 1], 60 * 60),
+        Some('d') => (&duration[..duration.len() - 1], 24 * 60 * 60),
+        Some('w') => (&duration[..duration.len() - 1], 7 * 24 * 60 * 60),
+        _ => (duration, 1),
+    };
+
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(factor))
+        .ok_or_else(|| format!("Invalid duration \"{}\"", duration))
+}
+
+fn blend_file(job_dir: &Path) -> PathBuf {
+    job_dir
+        .join(job_dir.file_name().unwrap())
+        .with_extension("blend")
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+fn remove_expired(server: &Server) {
+    loop {
+        if let Ok(job_dirs) = job_dirs() {
+            let active = server.active_job_dirs();
+
+            for job_dir in job_dirs {
+                remove_expired_frames(&job_dir);
+
+                let expires = read_to_string(job_dir.join("expires"))
+                    .ok()
+                    .map(|expires| expires.trim().parse().unwrap_or(u64::MAX));
+                let unused = server.job_ttl.map(|ttl| last_used(&job_dir) + ttl);
+
+                let expires = match (expires, unused) {
+                    (None, None) => {
+                        continue;
+                    }
+                    (expires, unused) => {
+                        expires.unwrap_or(u64::MAX).min(unused.unwrap_or(u64::MAX))
+                    }
+                };
+
+                if expires <= unix_time()
+                    && !active.contains(&job_dir)
+                    && remove_dir_all(&job_dir).is_ok()
+                {
+                    log!("Removed expired upload {}", job_dir.display());
+
+                    if let Err(error) = server.storage.remove_dir(&job_dir) {
+                        error!(
+                            "Removing {} from storage failed: {}",
+                            job_dir.display(),
+                            error
+                        );
+                    }
+                }
+            }
+        }
+
+        remove_unused_blobs();
+
+        if let Err(message) = server.reserve_disk(0) {
+            warn!("{}", message);
+        }
+
+        thread::sleep(Duration::from_secs(60));
+    }
+}
+
+fn retained_frame(job_dir: &Path, frame: usize) -> Option<PathBuf> {
+    let stem = format!("{:04}", frame);
+
+    read_dir(job_dir.join("frames"))
+        .ok()?
+        .flatten()
+        .map(|entry| entry.path())
+        .find(|path| {
+            let name = path.file_name().unwrap().to_str().unwrap_or_default();
+
+            name.split('.').next() == Some(stem.as_str())
+                && !["expires", "part", "size"]
+                    .iter()
+                    .any(|extension| path.extension() == Some(extension.as_ref()))
+        })
+}
+
+fn is_compressed(path: &Path) -> bool {
+    path.extension() == Some("zst".as_ref())
+}
+
+fn retained_extension(path: &Path) -> String {
+    let path = if is_compressed(path) {
+        Path::new(path.file_stem().unwrap())
+    } else {
+        path
+    };
+
+    String::from(path.extension().unwrap().to_str().unwrap())
+}
+
+fn retain_frame(job_dir: &Path, frame: usize, extension: &str, data: &[u8], retention: u64) {
+    let frames_dir = job_dir.join("frames");
+    let _ = create_dir(&frames_dir);
+
+    if let Some(previous) = retained_frame(job_dir, frame) {
+        let _ = remove_file(previous);
+    }
+
+    let path = frames_dir.join(format!("{:04}.{}.zst", frame, extension));
+
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+
+    let retained = zstd::encode_all(data, FRAME_COMPRESSION_LEVEL).and_then(|compressed| {
+        write(&part, compressed)?;
+        rename(&part, &path)
+    });
+
+    if retained.is_ok() {
+        let expires = unix_time() + retention;
+        let _ = write(
+            frames_dir.join(format!("{:04}.expires", frame)),
+            expires.to_string(),
+        );
+        let _ = write(
+            frames_dir.join(format!("{:04}.size", frame)),
+            data.len().to_string(),
+        );
+    }
+}
+
+fn frame_cache_size(job_dir: &Path) -> (u64, u64) {
+    let entries = match read_dir(job_dir.join("frames")) {
+        Ok(entries) => entries,
+        Err(_) => {
+            return (0, 0);
+        }
+    };
+
+    let (mut size, mut original) = (0, 0);
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension() == Some("size".as_ref()) {
+            original += read_to_string(&path)
+                .ok()
+                .and_then(|size| size.trim().parse().ok())
+                .unwrap_or(0);
+        } else if path.extension() != Some("expires".as_ref())
+            && path.extension() != Some("part".as_ref())
+        {
+            let len = path.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+            size += len;
+            if !is_compressed(&path) {
+                original += len;
+            }
+        }
+    }
+
+    (size, original)
+}
+
+fn remove_expired_frames(job_dir: &Path) {
+    let entries = match read_dir(job_dir.join("frames")) {
+        Ok(entries) => entries,
+        Err(_) => {
+            return;
+        }
+    };
+
+    for path in entries.flatten().map(|entry| entry.path()) {
+        if path.extension() != Some("expires".as_ref()) {
+            continue;
+        }
+
+        let expires = read_to_string(&path)
+            .ok()
+            .and_then(|expires| expires.trim().parse().ok())
+            .unwrap_or(u64::MAX);
+
+        if expires > unix_time() {
+            continue;
+        }
+
+        let frame = path
+            .file_stem()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap_or(usize::MAX);
+        if let Some(image) = retained_frame(job_dir, frame) {
+            let _ = remove_file(image);
+        }
+        let _ = remove_file(job_dir.join("frames").join(format!("{:04}.size", frame)));
+
+        if remove_file(&path).is_ok() {
+            debug!("Removed expired frame {} from {}", frame, job_dir.display());
+        }
+    }
+}
+
+fn thumbnail_file(job_dir: &Path, frame: usize) -> PathBuf {
+    job_dir.join("thumbnails").join(format!("{:04}.jpg", frame))
+}
+
+fn create_job_dir(id: &str, ttl: Option<u64>) -> PathBuf {
+    let job_dir = job_dir(id);
+
+    let _ = create_dir_all(&job_dir);
+    let _ = write(job_dir.join("id"), id);
+    touch_job(&job_dir);
+    match ttl {
+        None => {
+            let _ = remove_file(job_dir.join("expires"));
+        }
+        Some(ttl) => {
+            let expires = unix_time() + ttl;
+            let _ = write(job_dir.join("expires"), expires.to_string());
+            log!("Upload with ID \"{}\" expires in {}s", id, ttl);
+        }
+    }
+
+    job_dir
+}
+
+fn save_requirement(job_dir: &Path, blender: Option<&BlenderRequirement>) {
+    match blender {
+        None => {
+            let _ = remove_file(job_dir.join("blender"));
+        }
+        Some(blender) => {
+            let _ = write(job_dir.join("blender"), blender.to_string());
+        }
+    }
+}
+
+fn fetch_blend(
+    source: &str,
+    id: &str,
+    path: &Path,
+    size: usize,
+    checksum: &str,
+) -> Result<(), String> {
+    let mut peer =
+        try_connect(source, Some(Duration::from_secs(10))).map_err(|error| error.to_string())?;
+
+    let (user, id) = protocol::split_key(id);
+    let request = to_header(
+        serde_json::to_vec(&Request::Fetch {
+            id: String::from(id),
+            user: user.map(String::from),
+        })
+        .unwrap(),
+    );
+    peer.write_all(&request)
+        .map_err(|error| error.to_string())?;
+
+    let header = read_header(&mut peer).map_err(|error| error.to_string())?;
+    match serde_json::from_slice(&header).map_err(|error| error.to_string())? {
+        Response::Okay => {}
+        Response::Fail { message } => {
+            return Err(message);
+        }
+    }
+
+    receive_file(&peer, path, size, false).map_err(|error| error.to_string())?;
+
+    let received = File::open(path)
+        .and_then(transfer::checksum)
+        .map_err(|error| error.to_string())?;
+    if received != checksum {
+        let _ = remove_file(path);
+        return Err(format!(
+            "Checksum mismatch (expected {}, got {})",
+            checksum, received
+        ));
+    }
+
+    Ok(())
+}
+
+/// Receives `size` bytes into `path`. A `compressed` upload is a zstd stream that is decompressed
+/// on the fly and must expand to exactly `size` bytes.
+fn receive_file(
+    client: &TcpStream,
+    path: &Path,
+    size: usize,
+    compressed: bool,
+) -> Result<(), io::Error> {
+    let mut part = path.as_os_str().to_owned();
+    part.push(".part");
+
+    let result = File::create(&part)
+        .and_then(|file| {
+            let mut file = transfer::Limited::new(file, size);
+            if compressed {
+                let mut decoder = zstd::stream::write::Decoder::new(&mut file)?;
+                let limit = size + size / 128 + transfer::CHUNK_SIZE;
+                transfer::receive_chunked(client, &mut decoder, limit)?;
+                decoder.flush()?;
+            } else {
+                transfer::receive_chunked(client, &mut file, size)?;
+            }
+
+            if file.written != size {
+                return Err(io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Upload ended before the announced size",
+                ));
+            }
+
+            file.inner.sync_all()
+        })
+        .and_then(|()| rename(&part, path));
+
+    if result.is_err() {
+        let _ = remove_file(&part);
+    }
+
+    result
+}
+
+fn input_file(job_dir: &Path, pass: &str, name: &str) -> Option<PathBuf> {
+    let is_plain = |component: &str| {
+        !component.contains(['/', '\\', ':'])
+            && Path::new(component).file_name() == Some(component.as_ref())
+            && component != ".."
+    };
+
+    if is_plain(pass) && is_plain(name) {
+        Some(job_dir.join("inputs").join(pass).join(name))
+    } else {
+        None
+    }
+}
+
+fn bake_textures(
+    client: &mut TcpStream,
+    server: &Server,
+    id: &str,
+    objects: Vec<String>,
+    maps: Vec<BakeMap>,
+    resolution: u32,
+) {
+    let job_dir = job_dir(id);
+    let blend = blend_file(&job_dir);
+
+    let response = if !server.restore_job(&job_dir) {
+        Err(format!("No .blend file found for ID \"{}\"", id))
+    } else {
+        log!("Baking {:?} of {:?} in \"{}\"", maps, objects, id);
+
+        let request = to_header(
+            serde_json::to_vec(&BrpyRequest::Bake {
+                blend,
+                objects,
+                maps,
+                resolution,
+                output: job_dir.join("bake"),
+            })
+            .unwrap(),
+        );
+
+        let response = server.job_installation(id).and_then(|installation| {
+            server
+                .brpy_request(installation, &request)
+                .map_err(|error| error.to_string())
+        });
+
+        match response {
+            Ok(BrpyBakeResponse::Okay { images }) => Ok(images),
+            Ok(BrpyBakeResponse::Fail { message }) => Err(message),
+            Err(message) => Err(format!("Baking failed: {}", message)),
+        }
+    };
+
+    match response.and_then(read_baked) {
+        Ok((baked, mut data)) => {
+            let mut response =
+                to_header(serde_json::to_vec(&BakeResponse::Okay { images: baked }).unwrap());
+            response.append(&mut data);
+
+            if client.write_all(&response).is_err() {
+                warn!("Cannot reach client, discarding baked maps");
+            }
+        }
+        Err(message) => {
+            error!("Baking failed: {}", message);
+
+            let response = to_header(serde_json::to_vec(&BakeResponse::Fail { message }).unwrap());
+            let _ = client.write_all(&response);
+        }
+    }
+}
+
+/// Reads and removes the maps brpy baked, failing if one of them can't be read.
+fn read_baked(images: Vec<BrpyBakedImage>) -> Result<(Vec<BakedImage>, Vec<u8>), String> {
+    let mut data = Vec::new();
+    let mut baked = Vec::new();
+
+    for image in images {
+        let image_data = read(&image.image);
+        let _ = remove_file(&image.image);
+
+        let mut image_data = image_data.map_err(|error| {
+            format!(
+                "Could not read baked map {}: {}",
+                image.image.display(),
+                error
+            )
+        })?;
+        let extension = image
+            .image
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .ok_or_else(|| format!("Baked map {} has no file extension", image.image.display()))?;
+
+        baked.push(BakedImage {
+            object: image.object,
+            map: image.map,
+            size: image_data.len(),
+            extension: String::from(extension),
+        });
+        data.append(&mut image_data);
+    }
+
+    Ok((baked, data))
+}
+
+fn check_assets(
+    server: &Server,
+    worker: &Worker,
+    id: &str,
+    addons: Vec<String>,
+) -> Option<RejectReason> {
+    let job_dir = job_dir(id);
+    if !server.restore_job(&job_dir) {
+        return None;
+    }
+
+    let blend = blend_file(&job_dir);
+
+    let request =
+        to_header(serde_json::to_vec(&BrpyRequest::CheckAssets { blend, addons }).unwrap());
+    let report: BrpyAssetReport = match worker.request(&request) {
+        Ok(report) => report,
+        Err(error) => {
+            error!("Checking assets of \"{}\" failed: {}", id, error);
+            return None;
+        }
+    };
+
+    if !report.addons.is_empty() {
+        Some(RejectReason::MissingAddons {
+            addons: report.addons,
+        })
+    } else if report.libraries.is_empty() && report.images.is_empty() {
+        None
+    } else {
+        Some(RejectReason::MissingAssets {
+            libraries: report.libraries,
+            images: report.images,
+        })
+    }
+}
+
+fn add_requester(
+    mut client: TcpStream,
+    address: SocketAddr,
+    server: &Server,
+    token: String,
+    session: Session,
+) -> Option<Sender<Vec<u8>>> {
+    let required = session.blender.clone().or_else(|| {
+        let id = session.id.as_ref()?;
+        read_to_string(job_dir(id).join("blender"))
+            .ok()?
+            .parse()
+            .ok()
+    });
+
+    let installation = match server.installation(required.as_ref()) {
+        Ok(installation) => installation,
+        Err(reason) => {
+            warn!("Rejecting {}: {}", address, reason);
+
+            let response = to_header(
+                serde_json::to_vec(&RenderAcceptResponse::Reject {
+                    reason: RejectReason::MissingBlender {
+                        required: required
+                            .map(|required| required.to_string())
+                            .unwrap_or_default(),
+                        available: server
+                            .info
+                            .installations
+                            .iter()
+                            .map(BlenderInstallation::to_string)
+                            .collect(),
+                    },
+                })
+                .unwrap(),
+            );
+            let _ = client.write_all(&response);
+            return None;
+        }
+    };
+
+    let mut free_slot = 0;
+    let mut free_slot_found = false;
+    let (sender, receiver) = mpsc::channel();
+
+    {
+        let mut render_requesters = server.requesters.lock().unwrap();
+
+        if slots_full(&render_requesters, server.max_slots) {
+            match server.backpressure {
+                Backpressure::Block => {
+                    server.set_connection_state(address, "waiting for a free slot");
+                    render_requesters = server
+                        .notifier
+                        .wait_while(render_requesters, |requesters| {
+                            slots_full(requesters, server.max_slots)
+                        })
+                        .unwrap();
+                }
+                Backpressure::Fail => {
+                    drop(render_requesters);
+                    warn!("All render slots taken, rejecting {}", address);
+
+                    let response = to_header(
+                        serde_json::to_vec(&RenderAcceptResponse::Reject {
+                            reason: RejectReason::Busy,
+                        })
+                        .unwrap(),
+                    );
+                    let _ = client.write_all(&response);
+                    return None;
+                }
+                Backpressure::Disconnect => {
+                    warn!("All render slots taken, disconnecting {}", address);
+                    return None;
+                }
+            }
+        }
+
+        let requester = Some(Requester {
+            writer: Arc::new(Mutex::new(client.try_clone().unwrap())),
+            requests: Arc::new(Mutex::new(receiver)),
+            stream: client,
+            address,
+            state: SlotState::Queued,
+            since: Instant::now(),
+            id: session.id.clone(),
+            frame: None,
+            outbox: Arc::new(Outbox::new(
+                server
+                    .scratch_dir
+                    .join("outbox")
+                    .join(format!("{}-{}", token, address.port())),
+                OUTBOX_MEMORY,
+            )),
+            assets_checked: false,
+            worker: None,
+            prefetch: session.prefetch,
+            addons: session.addons.clone(),
+            session: token.clone(),
+            accepted: false,
+            batch: None,
+            heartbeat: session.heartbeat,
+            last_seen: Instant::now(),
+            installation,
+            compression: session.compression,
+            priority: session.priority.unwrap_or_default(),
+            position: session.priority.map(|_| 0),
+            user: session.user.clone(),
+        });
+
+        let len = render_requesters.len();
+
+        for slot in 0..len {
+            if render_requesters[slot].is_none() {
+                free_slot_found = true;
+                free_slot = slot;
+                break;
+            }
+        }
+
+        if free_slot_found {
+            render_requesters[free_slot] = requester;
+            debug!("Put new render requester in slot {}", free_slot);
+        } else {
+            render_requesters.push(requester);
+            debug!("Created render slot {} for new render requester", len);
+        }
+
+        let mut sessions = server.sessions.lock().unwrap();
+        sessions.insert(token, session);
+        server.save_sessions(&sessions);
+    }
+
+    server.notifier.notify_all();
+    Some(sender)
+}
+
+fn slots_full<T>(requesters: &[Option<T>], max_slots: usize) -> bool {
+    requesters.len() >= max_slots && requesters.iter().all(Option::is_some)
+}
+
+/// Reads everything a render requester sends from then on, noting when it was last heard from
+/// and passing frame requests on to the workers.
+fn read_requests(
+    mut client: TcpStream,
+    address: SocketAddr,
+    server: &Server,
+    requests: Sender<Vec<u8>>,
+) {
+    server.set_connection_state(address, "render requester");
+
+    let requester = server
+        .requesters
+        .lock()
+        .unwrap()
+        .iter()
+        .enumerate()
+        .find_map(|(slot, requester)| {
+            requester
+                .as_ref()
+                .filter(|requester| requester.address == address)
+                .map(|requester| {
+                    (
+                        slot,
+                        requester.outbox.clone(),
+                        requester.writer.clone(),
+                        requester.heartbeat,
+                    )
+                })
+        });
+    let (slot, outbox, writer, heartbeat) = match requester {
+        Some(requester) => requester,
+        None => {
+            return;
+        }
+    };
+
+    // Requesters wait silently while queued or rendering, so without heartbeats a read timeout
+    // only ends the connection once a worker waits for a frame request that does not come.
+    let _ = client.set_read_timeout((!heartbeat).then_some(server.client_timeout));
+    let _ = writer
+        .lock()
+        .unwrap()
+        .set_write_timeout((server.backpressure != Backpressure::Block).then_some(SEND_TIMEOUT));
+
+    thread::scope(|scope| {
+        scope.spawn(|| send_outbox(server, slot, address, &outbox, &writer));
+
+        loop {
+            let header = match read_header(&mut client) {
+                Ok(header) => header,
+                Err(error)
+                    if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    if server.is_stalled(slot, address, &outbox) {
+                        warn!("Closing silent connection of {}", address);
+                        server.drop_requester(slot, address);
+                        break;
+                    }
+                    continue;
+                }
+                Err(_) => {
+                    break;
+                }
+            };
+
+            for requester in server.requesters.lock().unwrap().iter_mut().flatten() {
+                if requester.address == address {
+                    requester.last_seen = Instant::now();
+                }
+            }
+
+            if protocol::decode::<Heartbeat>(&header).is_err() && requests.send(header).is_err() {
+                break;
+            }
+        }
+
+        outbox.close();
+    });
+}
+
+/// Waits for the next frame request of a requester, giving up once it has been silent for
+/// `timeout` without anything left to download.
+fn wait_for_request(
+    requests: &Receiver<Vec<u8>>,
+    outbox: &Outbox,
+    timeout: Duration,
+) -> Result<Vec<u8>, RecvTimeoutError> {
+    loop {
+        match requests.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) if outbox.pending() > 0 => {}
+            result => {
+                return result;
+            }
+        }
+    }
+}
+
+/// Writes what the workers queued for the requester in `slot` until its connection closes.
+fn send_outbox(
+    server: &Server,
+    slot: usize,
+    address: SocketAddr,
+    outbox: &Outbox,
+    writer: &Mutex<TcpStream>,
+) {
+    while let Some((message, label)) = outbox.next() {
+        let data = match &message {
+            Message::Memory(data) => Ok(Cow::Borrowed(data)),
+            Message::Spilled(path, _) => {
+                let data = read(path);
+                let _ = remove_file(path);
+                data.map(Cow::Owned)
+            }
+        };
+
+        let sent = data.and_then(|data| {
+            let client = writer.lock().unwrap();
+            for chunk in data.chunks(1 << 20) {
+                transfer::write_all(&client, chunk)?;
+            }
+
+            Ok(())
+        });
+        let drained = outbox.sent(&message);
+
+        if let Err(error) = sent {
+            warn!("Cannot reach client, discarding queued frames: {}", error);
+            server.remove_requester(slot, address);
+            outbox.close();
+            return;
+        }
+
+        if let Some(label) = label {
+            log!("{} sent to client", label);
+        }
+
+        if drained {
+            server.update_requester(slot, address, |requester| {
+                if let SlotState::Sending = requester.state {
+                    requester.set_state(SlotState::Queued);
+                }
+            });
+            server.notifier.notify_all();
+        }
+    }
+}
+
+fn send_heartbeats(server: &Server) {
+    let ping = to_header(serde_json::to_vec(&Heartbeat::Ping).unwrap());
+
+    loop {
+        thread::sleep(server.heartbeat);
+
+        let mut positions = Vec::new();
+        let mut requesters = server.requesters.lock().unwrap();
+        for (slot, position) in queue_positions(&requesters) {
+            let requester = requesters[slot].as_mut().unwrap();
+            if requester.position.is_some_and(|last| last != position) {
+                requester.position = Some(position);
+                positions.push((requester.writer.clone(), position));
+            }
+        }
+
+        let mut alive = Vec::new();
+        let mut dead = Vec::new();
+        for (slot, requester) in requesters.iter().enumerate() {
+            let requester = match requester {
+                Some(requester) if requester.heartbeat => requester,
+                _ => {
+                    continue;
+                }
+            };
+
+            if requester.last_seen.elapsed() > server.heartbeat_timeout {
+                dead.push((slot, requester.address));
+            } else {
+                alive.push(requester.writer.clone());
+            }
+        }
+        drop(requesters);
+
+        for (writer, position) in positions {
+            let message = to_header(serde_json::to_vec(&Heartbeat::Queued { position }).unwrap());
+            if let Ok(mut writer) = writer.try_lock() {
+                let _ = writer.write_all(&message);
+            }
+        }
+
+        for writer in alive {
+            if let Ok(mut writer) = writer.try_lock() {
+                let _ = writer.write_all(&ping);
+            }
+        }
+
+        for (slot, address) in dead {
+            warn!(
+                "No heartbeat from {} for {}s, freeing slot {}",
+                address,
+                server.heartbeat_timeout.as_secs(),
+                slot
+            );
+            server.drop_requester(slot, address);
+        }
+    }
+}
+
+/// Where each queued requester that has not been accepted yet stands among those waiting for the
+/// same Blender installation, ordered by priority and then by how long they have been waiting.
+fn queue_positions(requesters: &[Option<Requester>]) -> Vec<(usize, usize)> {
+    let mut queued: Vec<_> = requesters
+        .iter()
+        .enumerate()
+        .filter_map(|(slot, requester)| {
+            requester
+                .as_ref()
+                .filter(|requester| !requester.accepted && requester.worker.is_none())
+                .map(|requester| (slot, requester))
+        })
+        .collect();
+    queued.sort_by_key(|(_, requester)| (Reverse(requester.priority), requester.since));
+
+    queued
+        .iter()
+        .map(|(slot, requester)| {
+            let position = queued
+                .iter()
+                .take_while(|(other, _)| other != slot)
+                .filter(|(_, other)| other.installation == requester.installation)
+                .count();
+
+            (*slot, position + 1)
+        })
+        .collect()
+}
+
+fn forward_output(output: impl Read + Send + 'static, captured: BrpyOutput) {
+    thread::spawn(move || {
+        for line in BufReader::new(output).lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => {
+                    return;
+                }
+            };
+
+            debug!("{}", line);
+
+            let mut captured = captured.lock().unwrap();
+            if captured.len() == BRPY_OUTPUT_LINES {
+                captured.pop_front();
+            }
+            captured.push_back(line);
+        }
+    });
+}
+
+fn launch_brpy(
+    blender: &Path,
+    brpy: &Path,
+    timeout: Duration,
+    device: Option<&str>,
+) -> Result<(TcpStream, process::Child, BrpyOutput), String> {
+    let listener = TcpListener::bind((Ipv6Addr::LOCALHOST, 0)).unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let mut process = process::Command::new(blender)
+        .args([
+            "--background",
+            "--python",
+            brpy.to_str().unwrap(),
+            "--",
+            &port.to_string(),
+        ])
+        .args(device)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|error| format!("Could not launch {}: {}", blender.display(), error))?;
+
+    let captured = Arc::new(Mutex::new(VecDeque::new()));
+    forward_output(process.stdout.take().unwrap(), captured.clone());
+    forward_output(process.stderr.take().unwrap(), captured.clone());
+
+    let start = Instant::now();
+    let reason = loop {
+        match listener.accept() {
+            Ok((stream, _)) => {
+                stream.set_nonblocking(false).unwrap();
+                return Ok((stream, process, captured));
+            }
+            Err(error) => {
+                if error.kind() != ErrorKind::WouldBlock {
+                    break error.to_string();
+                }
+            }
+        }
+
+        if let Ok(Some(status)) = process.try_wait() {
+            break format!("Blender exited ({}) before brpy connected", status);
+        }
+
+        if start.elapsed() > timeout {
+            break format!("brpy did not connect within {}s", timeout.as_secs());
+        }
+
+        thread::sleep(Duration::from_millis(100));
+    };
+
+    let _ = process.kill();
+    let _ = process.wait();
+
+    let captured = captured.lock().unwrap();
+    if captured.is_empty() {
+        Err(reason)
+    } else {
+        let output: Vec<&str> = captured.iter().map(String::as_str).collect();
+        Err(format!(
+            "{}\nLast output:\n    {}",
+            reason,
+            output.join("\n    ")
+        ))
+    }
+}
+
+fn monitor_local_use(server: &Server, idle_load: Option<f64>, idle_input: Option<u64>) {
+    loop {
+        let busy_load = idle_load
+            .zip(idle::load_average())
+            .is_some_and(|(threshold, load)| load > threshold);
+        let busy_input = idle_input
+            .zip(idle::input_idle())
+            .is_some_and(|(seconds, idle)| idle < Duration::from_secs(seconds));
+        let busy = busy_load || busy_input;
+
+        if busy != server.paused.load(Ordering::SeqCst) {
+            let requesters = server.requesters.lock().unwrap();
+            let held = server.held.lock().unwrap();
+            server.paused.store(busy, Ordering::SeqCst);
+
+            let signal = if busy {
+                log!("Machine is in local use, pausing farm work");
+                "STOP"
+            } else {
+                log!("Machine is idle, resuming farm work");
+                "CONT"
+            };
+
+            // Blender suspended through nodectl stays suspended until nodectl resumes it.
+            if server.suspend_blender && (busy || *held != Some(true)) {
+                for worker in &server.workers {
+                    idle::signal_process(worker.process.lock().unwrap().id(), signal);
+                }
+            }
+
+            drop(held);
+            drop(requesters);
+            server.notifier.notify_all();
+        }
+
+        thread::sleep(Duration::from_secs(5));
+    }
+}
+
+fn handle_node_control(mut client: TcpStream, server: &Server) {
+    let action: NodeAction = match read_header(&mut client)
+        .ok()
+        .and_then(|request| serde_json::from_slice(&request).ok())
+    {
+        Some(action) => action,
+        None => {
+            return;
+        }
+    };
+
+    let requesters = server.requesters.lock().unwrap();
+    let mut held = server.held.lock().unwrap();
+
+    let signal = match (action, *held) {
+        (NodeAction::Pause { suspend }, None) => {
+            log!("Paused by local request");
+            *held = Some(suspend);
+            suspend.then_some("STOP")
+        }
+        (NodeAction::Pause { suspend: true }, Some(false)) => {
+            log!("Suspending Blender by local request");
+            *held = Some(true);
+            Some("STOP")
+        }
+        (NodeAction::Resume, Some(suspended)) => {
+            log!("Resumed by local request");
+            *held = None;
+            let idle_suspended = server.suspend_blender && server.paused.load(Ordering::SeqCst);
+            (suspended && !idle_suspended).then_some("CONT")
+        }
+        _ => None,
+    };
+
+    if let Some(signal) = signal {
+        for worker in &server.workers {
+            idle::signal_process(worker.process.lock().unwrap().id(), signal);
+        }
+    }
+
+    drop(held);
+    drop(requesters);
+    server.notifier.notify_all();
+
+    let _ = client.write_all(&to_header(serde_json::to_vec(&Response::Okay).unwrap()));
+}
+
+/// Pauses or resumes the render server running on this machine through its local control port.
+pub fn nodectl(action: NodeAction, port: u16) -> Result<(), String> {
+    let mut node =
+        TcpStream::connect((Ipv6Addr::LOCALHOST, port)).map_err(|error| error.to_string())?;
+    node.write_all(&to_header(serde_json::to_vec(&action).unwrap()))
+        .map_err(|error| error.to_string())?;
+
+    let response = read_header(&mut node).map_err(|error| error.to_string())?;
+    match serde_json::from_slice(&response).map_err(|error| error.to_string())? {
+        Response::Okay => Ok(()),
+        Response::Fail { message } => Err(message),
+    }
+}
+
+fn worker_brpy(server: &Server, index: usize) {
+    let worker = &server.workers[index];
+
+    let mut slot = 0;
+
+    loop {
+        let (outbox, requests, address, unchecked_id, accept, batched, compression, user) = {
+            let mut requesters = server
+                .notifier
+                .wait_while(server.requesters.lock().unwrap(), |_| {
+                    server.paused.load(Ordering::SeqCst)
+                        || server.held.lock().unwrap().is_some()
+                        || server.shutting_down.load(Ordering::SeqCst)
+                })
+                .unwrap();
+
+            loop {
+                let next = (1..=requesters.len())
+                    .map(|offset| (slot + offset) % requesters.len())
+                    .filter(|slot| {
+                        requesters[*slot].as_ref().is_some_and(|requester| {
+                            requester.installation == worker.installation
+                                && requester.worker.is_none_or(|other| other == index)
+                        })
+                    })
+                    .min_by_key(|slot| Reverse(requesters[*slot].as_ref().unwrap().priority));
+
+                match next {
+                    Some(next) if !server.shutting_down.load(Ordering::SeqCst) => {
+                        slot = next;
+                        break;
+                    }
+                    _ => {
+                        debug!("Awaiting further render requests");
+                        requesters = server.notifier.wait(requesters).unwrap();
+                    }
+                }
+            }
+
+            let requester = requesters[slot].as_mut().unwrap();
+            requester.set_state(SlotState::AwaitingFrameRequest);
+            requester.worker = Some(index);
+
+            let batched = requester.batch.as_mut().and_then(|(request, frames)| {
+                frames.pop_front().map(|frame| FrameRequest {
+                    frame,
+                    ..request.clone()
+                })
+            });
+
+            let accept = if batched.is_some() || requester.prefetch && requester.accepted {
+                None
+            } else {
+                let response = RenderAcceptResponse::Accept {
+                    session: Some(requester.session.clone()),
+                    batch: true,
+                    heartbeat: requester.heartbeat.then_some(server.heartbeat.as_secs()),
+                };
+                Some(to_header(serde_json::to_vec(&response).unwrap()))
+            };
+            requester.accepted = true;
+            let unchecked_id = if requester.assets_checked {
+                None
+            } else {
+                requester
+                    .id
+                    .clone()
+                    .map(|id| (id, requester.addons.clone()))
+            };
+            requester.assets_checked = true;
+
+            (
+                requester.outbox.clone(),
+                requester.requests.clone(),
+                requester.address,
+                unchecked_id,
+                accept,
+                batched,
+                requester.compression,
+                requester.user.clone(),
+            )
+        };
+
+        if let Some((id, addons)) = unchecked_id
+            && let Some(reason) = check_assets(server, worker, &id, addons)
+        {
+            warn!(
+                "Rejecting render requester in slot {} due to missing assets in \"{}\"",
+                slot, id
+            );
+
+            let response =
+                to_header(serde_json::to_vec(&RenderAcceptResponse::Reject { reason }).unwrap());
+            outbox.push(response, None);
+
+            server.remove_requester(slot, address);
+            continue;
+        }
+
+        let frame_request = match batched {
+            Some(frame_request) => frame_request,
+            None => {
+                if let Some(response) = accept {
+                    outbox.push(response, None);
+                }
+
+                let frame_request =
+                    wait_for_request(&requests.lock().unwrap(), &outbox, server.client_timeout);
+
+                let frame_request = match frame_request {
+                    Err(RecvTimeoutError::Timeout) => {
+                        warn!(
+                            "No frame request from {} for {}s, freeing slot {}",
+                            address,
+                            server.client_timeout.as_secs(),
+                            slot
+                        );
+                        server.drop_requester(slot, address);
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => {
+                        server.remove_requester(slot, address);
+                        continue;
+                    }
+                    Ok(frame_request) => protocol::decode::<FrameRequests>(&frame_request)
+                        .and_then(|frame_request| frame_request.validate().map(|()| frame_request)),
+                };
+
+                match frame_request {
+                    Ok(FrameRequests::Single(frame_request)) => FrameRequest {
+                        id: protocol::job_key(user.as_deref(), &frame_request.id),
+                        ..frame_request
+                    },
+                    Ok(FrameRequests::Range(range)) => {
+                        debug!(
+                            "Received batch of frames {}-{} for slot {}",
+                            range.start, range.end, slot
+                        );
+
+                        let frame_request = FrameRequest {
+                            id: protocol::job_key(user.as_deref(), &range.id),
+                            frame: range.start,
+                            settings: range.settings,
+                        };
+                        let frames = (range.start + 1..=range.end).collect();
+                        server.update_requester(slot, address, |requester| {
+                            requester.batch = Some((frame_request.clone(), frames));
+                        });
+
+                        frame_request
+                    }
+                    Err(message) => {
+                        warn!("Rejected frame request from {}: {}", address, message);
+                        server.remove_requester(slot, address);
+                        continue;
+                    }
+                }
+            }
+        };
+
+        let _client = logging::span("client", address);
+        let _job = logging::span("id", &frame_request.id);
+        let _frame = logging::span("frame", frame_request.frame);
+
+        if server.is_cancelled(&frame_request.id) {
+            debug!("Skipping frame of cancelled job");
+
+            let response = to_header(
+                serde_json::to_vec(&RenderResponse::Fail {
+                    cancelled: true,
+                    job_cancelled: true,
+                })
+                .unwrap(),
+            );
+            outbox.push(response, None);
+
+            server.update_requester(slot, address, |requester| {
+                requester.batch = None;
+                requester.set_state(SlotState::Queued);
+                requester.worker = None;
+            });
+            server.notifier.notify_all();
+            continue;
+        }
+
+        debug!("Rendering slot {}", slot);
+
+        server.update_requester(slot, address, |requester| {
+            requester.id = Some(frame_request.id.clone());
+            requester.frame = Some(frame_request.frame);
+            requester.set_state(SlotState::Rendering);
+        });
+        server.update_job_stats(&frame_request.id, |_| {});
+
+        let job_dir = job_dir(&frame_request.id);
+
+        let blend = blend_file(&job_dir);
+        if !server.restore_job(&job_dir) {
+            warn!("No .blend file found for ID \"{}\"", frame_request.id);
+
+            let response = to_header(
+                serde_json::to_vec(&RenderResponse::Fail {
+                    cancelled: false,
+                    job_cancelled: false,
+                })
+                .unwrap(),
+            );
+            outbox.push(response, None);
+
+            server.update_requester(slot, address, |requester| {
+                requester.set_state(SlotState::Queued);
+                requester.worker = None;
+            });
+            server.notifier.notify_all();
+            continue;
+        }
+
+        let _ = create_dir(job_dir.join("thumbnails"));
+
+        let render_dir = server
+            .scratch_dir
+            .join(&job_dir)
+            .join("render")
+            .join(format!("{:04}-{}", frame_request.frame, slot));
+        let _ = create_dir_all(&render_dir);
+
+        let request = to_header(
+            serde_json::to_vec(&BrpyRequest::Render {
+                blend,
+                frame: frame_request.frame,
+                output: render_dir.clone(),
+                inputs: job_dir.join("inputs"),
+                thumbnail: thumbnail_file(&job_dir, frame_request.frame),
+                settings: Box::new(frame_request.settings.clone()),
+            })
+            .unwrap(),
+        );
+
+        let start = Instant::now();
+        let mut peak_memory = None;
+        let response = worker.render(&request, |progress| {
+            peak_memory = peak_memory.max(progress.memory);
+
+            if outbox.pending() > 0 {
+                return;
+            }
+
+            let progress =
+                to_header(serde_json::to_vec(&RenderResponse::Progress(progress)).unwrap());
+            outbox.push(progress, None);
+        });
+
+        match response {
+            BrpyRenderResponse::Okay {
+                image,
+                format_override,
+                passes,
+            } => {
+                if let Some(format_override) = &format_override {
+                    log!(
+                        "\"{}\" outputs {}, rendered frame {} as {} instead",
+                        frame_request.id,
+                        format_override.from,
+                        frame_request.frame,
+                        format_override.to
+                    );
+                }
+
+                let output = image
+                    .extension()
+                    .and_then(|extension| extension.to_str())
+                    .ok_or_else(|| io::Error::new(ErrorKind::InvalidData, "Missing file extension"))
+                    .and_then(|extension| {
+                        Ok((String::from(extension), transfer::read_file(&image)?))
+                    });
+                let (extension, image_data) = match output {
+                    Ok(output) => output,
+                    Err(error) => {
+                        error!(
+                            "Could not read frame {} of \"{}\" from {}: {}",
+                            frame_request.frame,
+                            frame_request.id,
+                            image.display(),
+                            error
+                        );
+                        server.update_job_stats(&frame_request.id, |status| status.failed += 1);
+
+                        let response = to_header(
+                            serde_json::to_vec(&RenderResponse::Fail {
+                                cancelled: false,
+                                job_cancelled: false,
+                            })
+                            .unwrap(),
+                        );
+                        outbox.push(response, None);
+
+                        server.update_requester(slot, address, |requester| {
+                            requester.set_state(SlotState::Queued);
+                            requester.worker = None;
+                        });
+                        server.notifier.notify_all();
+
+                        let _ = remove_dir_all(&render_dir);
+                        continue;
+                    }
+                };
+                server.update_job_stats(&frame_request.id, |status| status.completed += 1);
+
+                let retention = frame_request
+                    .settings
+                    .retention
+                    .unwrap_or(server.frame_retention);
+                if retention > 0 {
+                    retain_frame(
+                        &job_dir,
+                        frame_request.frame,
+                        &extension,
+                        &image_data,
+                        retention,
+                    );
+
+                    if let Some(retained) = retained_frame(&job_dir, frame_request.frame) {
+                        server.store(&retained);
+                    }
+                }
+
+                let compressed =
+                    compression.and_then(|level| zstd::encode_all(&image_data[..], level).ok());
+                let size = compressed.as_ref().map_or(image_data.len(), Vec::len);
+
+                let (passes, pass_data): (Vec<_>, Vec<_>) = passes
+                    .into_iter()
+                    .filter(|_| frame_request.settings.passes)
+                    .filter_map(|pass| {
+                        let data = protocol::validate_id(&pass.name)
+                            .map_err(|error| error.to_string())
+                            .and_then(|()| read(&pass.image).map_err(|error| error.to_string()));
+                        let data = match data {
+                            Ok(data) => data,
+                            Err(message) => {
+                                warn!(
+                                    "Discarding pass \"{}\" of frame {}: {}",
+                                    pass.name, frame_request.frame, message
+                                );
+                                return None;
+                            }
+                        };
+
+                        let checksum = Some(hash(&data));
+                        let data = match compression.filter(|_| compressed.is_some()) {
+                            Some(level) => zstd::encode_all(&data[..], level).ok()?,
+                            None => data,
+                        };
+                        let extension = pass.image.extension()?.to_str()?;
+
+                        Some((
+                            PassOutput {
+                                name: pass.name,
+                                size: data.len(),
+                                extension: String::from(extension),
+                                checksum,
+                            },
+                            data,
+                        ))
+                    })
+                    .unzip();
+                let size = size + pass_data.iter().map(Vec::len).sum::<usize>();
+
+                let header = to_header(
+                    serde_json::to_vec(&RenderResponse::Okay {
+                        size,
+                        extension,
+                        format_override,
+                        checksum: Some(hash(&image_data)),
+                        compressed: compressed.is_some(),
+                        stats: Some(RenderStats {
+                            seconds: start.elapsed().as_secs_f64(),
+                            peak_memory,
+                            device: Some(
+                                worker
+                                    .device
+                                    .clone()
+                                    .unwrap_or_else(|| server.info.devices.active.join(", ")),
+                            ),
+                        }),
+                        passes,
+                    })
+                    .unwrap(),
+                );
+
+                server.update_requester(slot, address, |requester| {
+                    requester.set_state(SlotState::Sending);
+                    requester.worker = None;
+                });
+                server.notifier.notify_all();
+
+                let mut message = header;
+                message.extend_from_slice(compressed.as_deref().unwrap_or(&image_data[..]));
+                for data in pass_data {
+                    message.extend_from_slice(&data);
+                }
+                outbox.push(
+                    message,
+                    Some(format!(
+                        "Rendered frame {} of \"{}\"",
+                        frame_request.frame, frame_request.id
+                    )),
+                );
+            }
+            response @ (BrpyRenderResponse::Fail | BrpyRenderResponse::Cancelled) => {
+                let cancelled = matches!(response, BrpyRenderResponse::Cancelled);
+                let outcome = if cancelled { "was cancelled" } else { "failed" };
+                if !cancelled {
+                    server.update_job_stats(&frame_request.id, |status| status.failed += 1);
+                }
+
+                log!(
+                    "Rendering frame {} of \"{}\" {}",
+                    frame_request.frame,
+                    frame_request.id,
+                    outcome
+                );
+
+                let response = to_header(
+                    serde_json::to_vec(&RenderResponse::Fail {
+                        cancelled,
+                        job_cancelled: cancelled && server.is_cancelled(&frame_request.id),
+                    })
+                    .unwrap(),
+                );
+                outbox.push(response, None);
+
+                server.update_requester(slot, address, |requester| {
+                    requester.set_state(SlotState::Queued);
+                    requester.worker = None;
+                });
+                server.notifier.notify_all();
+            }
+            BrpyRenderResponse::Progress(_) => unreachable!(),
+        }
+
+        let _ = remove_dir_all(&render_dir);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounds_render_slots() {
+        assert!(!slots_full::<()>(&[], 2));
+        assert!(!slots_full(&[Some(()), None], 2));
+        assert!(slots_full(&[Some(()), Some(())], 2));
+        assert!(!slots_full(&[Some(()), Some(()), None], 2));
+        assert!(slots_full(&[Some(())], 1));
+    }
+
+    #[test]
+    fn spills_outbox_to_disk() {
+        let spill = std::env::temp_dir()
+            .join(format!("brsp-outbox-{}", std::process::id()))
+            .join("client");
+        let outbox = Outbox::new(spill.clone(), 8);
+
+        outbox.push(vec![1; 5], None);
+        outbox.push(vec![2; 5], None);
+        outbox.push(vec![3; 5], None);
+        assert_eq!(outbox.pending(), 15);
+
+        let first = outbox.next().unwrap().0;
+        assert!(matches!(first, Message::Memory(ref data) if *data == [1; 5]));
+        assert!(!outbox.sent(&first));
+
+        let second = outbox.next().unwrap().0;
+        let Message::Spilled(path, 5) = &second else {
+            panic!("Expected the second message on disk");
+        };
+        assert_eq!(read(path).unwrap(), [2; 5]);
+        assert!(!outbox.sent(&second));
+
+        let third = PathBuf::from(format!("{}-1", spill.display()));
+        assert!(third.is_file());
+        outbox.close();
+        assert!(!third.exists());
+        assert!(outbox.next().is_none());
+
+        let _ = remove_dir_all(spill.parent().unwrap());
+    }
+
+    #[test]
+    fn creates_distinct_tokens() {
+        let token = random_token().unwrap();
+
+        assert_eq!(token.len(), 64);
+        assert!(token.bytes().all(|byte| byte.is_ascii_hexdigit()));
+        assert_ne!(token, random_token().unwrap());
+    }
+
+    #[test]
+    fn restores_saved_sessions() {
+        let session = |created| Session {
+            id: Some(String::from("shot")),
+            prefetch: true,
+            addons: Vec::new(),
+            heartbeat: true,
+            blender: None,
+            compression: Some(3),
+            priority: None,
+            user: Some(String::from("alice")),
+            created,
+        };
+        let now = 10 * SESSION_TTL;
+        let saved = serde_json::to_vec(&HashMap::from([
+            (String::from("fresh"), session(now - 60)),
+            (String::from("expired"), session(now - SESSION_TTL)),
+        ]))
+        .unwrap();
+
+        let sessions = load_sessions(&saved, now);
+        assert_eq!(sessions.len(), 1);
+
+        let fresh = &sessions["fresh"];
+        assert_eq!(fresh.id.as_deref(), Some("shot"));
+        assert_eq!(fresh.user.as_deref(), Some("alice"));
+        assert!(fresh.prefetch && fresh.heartbeat);
+        assert_eq!(fresh.compression, Some(3));
+
+        let older = br#"{"old": {"id": null, "prefetch": false, "created": 0}}"#;
+        assert_eq!(load_sessions(older, 1).len(), 1);
+        assert!(load_sessions(b"not json", now).is_empty());
+    }
+
+    #[test]
+    fn fails_bakes_with_unreadable_maps() {
+        let dir = std::env::temp_dir().join(format!("brsp-bake-{}", std::process::id()));
+        create_dir_all(&dir).unwrap();
+        write(dir.join("ao.png"), [1, 2, 3]).unwrap();
+        write(dir.join("normal"), [4]).unwrap();
+
+        let image = |name: &str| BrpyBakedImage {
+            object: String::from("Cube"),
+            map: BakeMap::Ao,
+            image: dir.join(name),
+        };
+
+        let (baked, data) = read_baked(vec![image("ao.png")]).unwrap();
+        assert_eq!((baked[0].size, &baked[0].extension[..]), (3, "png"));
+        assert_eq!(data, [1, 2, 3]);
+        assert!(!dir.join("ao.png").exists());
+
+        assert!(read_baked(vec![image("missing.png")]).is_err());
+        assert!(read_baked(vec![image("normal")]).is_err());
+
+        let _ = remove_dir_all(dir);
+    }
+
+    #[test]
+    fn streams_zeros() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (mut server, _) = listener.accept().unwrap();
+
+        let sender = thread::spawn(move || send_zeros(&mut server, &[1, 2], 200_000));
+        let mut received = Vec::new();
+        client.read_to_end(&mut received).unwrap();
+        sender.join().unwrap().unwrap();
+
+        assert_eq!(received.len(), 200_002);
+        assert_eq!(received[..2], [1, 2]);
+        assert!(received[2..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test]
+    fn reclaims_silent_requesters() {
+        let outbox = Outbox::new(std::env::temp_dir().join("brsp-silent"), OUTBOX_MEMORY);
+        let timeout = Duration::from_millis(20);
+
+        let (requests, received) = mpsc::channel::<Vec<u8>>();
+        assert_eq!(
+            wait_for_request(&received, &outbox, timeout),
+            Err(RecvTimeoutError::Timeout)
+        );
+
+        outbox.push(vec![0; 4], None);
+        let sender = thread::spawn(move || {
+            thread::sleep(timeout * 4);
+            requests.send(vec![1]).unwrap();
+        });
+        assert_eq!(wait_for_request(&received, &outbox, timeout), Ok(vec![1]));
+
+        sender.join().unwrap();
+        assert_eq!(
+            wait_for_request(&received, &outbox, timeout),
+            Err(RecvTimeoutError::Disconnected)
+        );
+    }
+
+    #[test]
+    fn parses_sizes() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("1K"), Ok(1 << 10));
+        assert_eq!(parse_size("2mb"), Ok(2 << 20));
+        assert_eq!(parse_size("1.5GiB"), Ok(3 << 29));
+        assert_eq!(parse_size(" 1T "), Ok(1 << 40));
+    }
+
+    #[test]
+    fn rejects_invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("-1G").is_err());
+        assert!(parse_size("1X").is_err());
+        assert!(parse_size("G").is_err());
+    }
+
+    #[test]
+    fn parses_durations() {
+        assert_eq!(parse_duration("30"), Ok(30));
+        assert_eq!(parse_duration("90s"), Ok(90));
+        assert_eq!(parse_duration("5m"), Ok(5 * 60));
+        assert_eq!(parse_duration("2h"), Ok(2 * 60 * 60));
+        assert_eq!(parse_duration("1d"), Ok(24 * 60 * 60));
+        assert_eq!(parse_duration("1w"), Ok(7 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn rejects_invalid_durations() {
+        assert!(parse_duration("").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("5y").is_err());
+        assert!(parse_duration("-5m").is_err());
+        assert!(parse_duration(&format!("{}w", u64::MAX)).is_err());
+    }
+}
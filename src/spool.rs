@@ -0,0 +1,409 @@
+use crate::{
+    cancel_frame, inherit,
+    logging::{error, log},
+    manifest::Manifest,
+    output::{Color, paint, table},
+    parse_frames, read_header, to_header,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, read, write},
+    io::{ErrorKind, Write},
+    net::{Ipv6Addr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+};
+
+pub const PORT: u16 = 21817;
+const MAX_PAGE_SIZE: usize = 48 * 1024;
+const MAX_SUMMARY_LENGTH: usize = 256;
+
+#[derive(Serialize, Deserialize, Clone)]
+struct Job {
+    id: u64,
+    state: JobState,
+    manifest: Manifest,
+}
+
+/// What `jobs` shows of a job, with long fields shortened to keep pages small.
+#[derive(Serialize, Deserialize)]
+struct JobSummary {
+    id: u64,
+    state: JobState,
+    name: String,
+    frames: String,
+    ips: String,
+}
+
+impl JobSummary {
+    fn new(job: &Job) -> JobSummary {
+        let shorten = |text: &str| {
+            if text.chars().count() <= MAX_SUMMARY_LENGTH {
+                String::from(text)
+            } else {
+                text.chars().take(MAX_SUMMARY_LENGTH).collect::<String>() + "..."
+            }
+        };
+
+        JobSummary {
+            id: job.id,
+            state: job.state,
+            name: shorten(&job.manifest.id),
+            frames: shorten(&job.manifest.frames),
+            ips: shorten(&job.manifest.ips),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+enum JobState {
+    Queued,
+    Running,
+    Done,
+    Failed,
+    Cancelled,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SpoolRequest {
+    Queue {
+        manifest: Box<Manifest>,
+    },
+    Jobs {
+        #[serde(default)]
+        offset: usize,
+    },
+    Cancel {
+        job: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SpoolResponse {
+    Queued {
+        job: u64,
+    },
+    Jobs {
+        jobs: Vec<JobSummary>,
+
+        #[serde(default)]
+        more: bool,
+    },
+    Okay,
+    Fail {
+        message: String,
+    },
+}
+
+struct Spool {
+    file: PathBuf,
+    jobs: Mutex<Vec<Job>>,
+    queued: Condvar,
+    running: Mutex<HashMap<u64, Arc<Mutex<Vec<usize>>>>>,
+}
+
+impl Spool {
+    fn save(&self, jobs: &[Job]) {
+        write(&self.file, serde_json::to_vec_pretty(jobs).unwrap()).unwrap();
+    }
+
+    fn state(&self, id: u64) -> JobState {
+        let jobs = self.jobs.lock().unwrap();
+        jobs.iter().find(|job| job.id == id).unwrap().state
+    }
+
+    fn set_state(&self, id: u64, state: JobState) {
+        let mut jobs = self.jobs.lock().unwrap();
+
+        if let Some(job) = jobs.iter_mut().find(|job| job.id == id)
+            && job.state != JobState::Cancelled
+        {
+            job.state = state;
+        }
+
+        self.save(&jobs);
+    }
+}
+
+pub fn serve(spool_dir: &Path, parallel: usize, port: u16) {
+    create_dir_all(spool_dir).unwrap();
+    let file = spool_dir.join("jobs.json");
+
+    let mut jobs: Vec<Job> = match read(&file) {
+        Ok(jobs) => serde_json::from_slice(&jobs).unwrap(),
+        Err(error) => match error.kind() {
+            ErrorKind::NotFound => Vec::new(),
+            _ => {
+                panic!("{}", error);
+            }
+        },
+    };
+
+    for job in &mut jobs {
+        if job.state == JobState::Running {
+            log!("Requeueing interrupted job {}", job.id);
+            job.state = JobState::Queued;
+        }
+    }
+
+    let spool = Spool {
+        file,
+        jobs: Mutex::new(jobs),
+        queued: Condvar::new(),
+        running: Mutex::new(HashMap::new()),
+    };
+
+    let listener = TcpListener::bind((Ipv6Addr::LOCALHOST, port)).unwrap();
+
+    thread::scope(|scope| {
+        for _ in 0..parallel.max(1) {
+            scope.spawn(inherit(|| {
+                run_jobs(&spool);
+            }));
+        }
+
+        log!("Spooling jobs on port {}", port);
+
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    scope.spawn(|| {
+                        handle_spool_client(stream, &spool);
+                    });
+                }
+                Err(error) => {
+                    error!("Failed to establish new connection: {}", error);
+                }
+            }
+        }
+    });
+}
+
+fn run_jobs(spool: &Spool) {
+    loop {
+        let job = {
+            let mut jobs = spool
+                .queued
+                .wait_while(spool.jobs.lock().unwrap(), |jobs| {
+                    !jobs.iter().any(|job| job.state == JobState::Queued)
+                })
+                .unwrap();
+
+            let job = jobs
+                .iter_mut()
+                .find(|job| job.state == JobState::Queued)
+                .unwrap();
+            job.state = JobState::Running;
+            let job = job.clone();
+
+            spool.save(&jobs);
+            job
+        };
+
+        log!("Running job {} (\"{}\")", job.id, job.manifest.id);
+
+        let frames = match parse_frames(&job.manifest.frames) {
+            Ok(frames) => Arc::new(Mutex::new(frames)),
+            Err(message) => {
+                error!("Job {} failed\nReason: {}", job.id, message);
+                spool.set_state(job.id, JobState::Failed);
+                continue;
+            }
+        };
+        spool.running.lock().unwrap().insert(job.id, frames.clone());
+
+        let result = thread::scope(|scope| {
+            scope
+                .spawn(inherit(|| {
+                    job.manifest
+                        .run(&frames, || spool.state(job.id) == JobState::Cancelled)
+                }))
+                .join()
+        });
+
+        spool.running.lock().unwrap().remove(&job.id);
+
+        match result {
+            Ok(Ok(())) => {
+                log!("Job {} done", job.id);
+                spool.set_state(job.id, JobState::Done);
+            }
+            Ok(Err(message)) => {
+                error!("Job {} failed\nReason: {}", job.id, message);
+                spool.set_state(job.id, JobState::Failed);
+            }
+            Err(_) => {
+                error!("Job {} failed", job.id);
+                spool.set_state(job.id, JobState::Failed);
+            }
+        }
+    }
+}
+
+fn handle_spool_client(mut client: TcpStream, spool: &Spool) {
+    let request = match read_header(&mut client) {
+        Ok(request) => serde_json::from_slice(&request).unwrap(),
+        Err(_) => {
+            return;
+        }
+    };
+
+    let response = match request {
+        SpoolRequest::Queue { manifest } => {
+            let mut jobs = spool.jobs.lock().unwrap();
+            let id = jobs.iter().map(|job| job.id + 1).max().unwrap_or(0);
+
+            log!("Queued job {} (\"{}\")", id, manifest.id);
+            jobs.push(Job {
+                id,
+                state: JobState::Queued,
+                manifest: *manifest,
+            });
+
+            spool.save(&jobs);
+            spool.queued.notify_one();
+
+            SpoolResponse::Queued { job: id }
+        }
+        SpoolRequest::Jobs { offset } => {
+            let jobs = spool.jobs.lock().unwrap();
+
+            let mut page = Vec::new();
+            let mut size = 0;
+            for job in jobs.iter().skip(offset) {
+                let summary = JobSummary::new(job);
+                size += serde_json::to_vec(&summary).unwrap().len() + 1;
+                if size > MAX_PAGE_SIZE {
+                    break;
+                }
+                page.push(summary);
+            }
+
+            SpoolResponse::Jobs {
+                more: offset + page.len() < jobs.len(),
+                jobs: page,
+            }
+        }
+        SpoolRequest::Cancel { job: id } => {
+            let mut jobs = spool.jobs.lock().unwrap();
+
+            match jobs.iter_mut().find(|job| job.id == id) {
+                None => SpoolResponse::Fail {
+                    message: format!("No job with ID {}", id),
+                },
+                Some(job) => match job.state {
+                    JobState::Queued | JobState::Running => {
+                        job.state = JobState::Cancelled;
+
+                        if let Some(frames) = spool.running.lock().unwrap().get(&id) {
+                            frames.lock().unwrap().clear();
+
+                            for ip in job.manifest.ips.split_terminator(',') {
+                                let _ = cancel_frame(ip, &job.manifest.id, None);
+                            }
+                        }
+
+                        log!("Cancelled job {}", id);
+                        spool.save(&jobs);
+
+                        SpoolResponse::Okay
+                    }
+                    _ => SpoolResponse::Fail {
+                        message: format!("Job {} already finished", id),
+                    },
+                },
+            }
+        }
+    };
+
+    let _ = client.write_all(&to_header(serde_json::to_vec(&response).unwrap()));
+}
+
+fn request(port: u16, request: &SpoolRequest) -> SpoolResponse {
+    let mut spool = TcpStream::connect((Ipv6Addr::LOCALHOST, port)).unwrap();
+    spool
+        .write_all(&to_header(serde_json::to_vec(request).unwrap()))
+        .unwrap();
+
+    serde_json::from_slice(&read_header(&mut spool).unwrap()).unwrap()
+}
+
+pub fn queue(manifest: &Path, port: u16) {
+    let manifest = Box::new(Manifest::load(manifest));
+
+    match request(port, &SpoolRequest::Queue { manifest }) {
+        SpoolResponse::Queued { job } => {
+            log!("Queued job {}", job);
+        }
+        SpoolResponse::Fail { message } => {
+            error!("Queueing failed\nReason: {}", message);
+        }
+        _ => {
+            panic!("Unexpected response from spool daemon");
+        }
+    }
+}
+
+pub fn jobs(cancel: Option<u64>, port: u16) {
+    if let Some(job) = cancel {
+        match request(port, &SpoolRequest::Cancel { job }) {
+            SpoolResponse::Okay => {
+                log!("Cancelled job {}", job);
+            }
+            SpoolResponse::Fail { message } => {
+                error!("Cancelling failed\nReason: {}", message);
+            }
+            _ => {
+                panic!("Unexpected response from spool daemon");
+            }
+        }
+
+        return;
+    }
+
+    let mut jobs = Vec::new();
+    loop {
+        match request(port, &SpoolRequest::Jobs { offset: jobs.len() }) {
+            SpoolResponse::Jobs { jobs: page, more } => {
+                jobs.extend(page);
+                if !more {
+                    break;
+                }
+            }
+            _ => {
+                panic!("Unexpected response from spool daemon");
+            }
+        }
+    }
+
+    let rows: Vec<Vec<String>> = jobs
+        .into_iter()
+        .map(|job| {
+            let state = match job.state {
+                JobState::Queued => String::from("queued"),
+                JobState::Running => paint("running", Color::Cyan),
+                JobState::Done => paint("done", Color::Green),
+                JobState::Failed => paint("failed", Color::Red),
+                JobState::Cancelled => paint("cancelled", Color::Yellow),
+            };
+
+            vec![
+                job.id.to_string(),
+                format!("\"{}\"", job.name),
+                job.frames,
+                job.ips,
+                state,
+            ]
+        })
+        .collect();
+
+    println!(
+        "{}",
+        table(&["JOB", "ID", "FRAMES", "SERVERS", "STATE"], &rows)
+    );
+}
@@ -0,0 +1,371 @@
+use crate::{
+    FRAME_RETRIES, FrameRequest, Region, RenderAcceptResponse, RenderResponse, RenderSettings,
+    Request, compression, config, hash, inherit,
+    logging::{error, log},
+    output::{self, Color, paint},
+    paths, pool, protocol, read_header, to_header, transfer, user, write_atomic,
+};
+use clap::Args;
+use image::{DynamicImage, ImageBuffer, imageops};
+use std::{
+    collections::HashMap,
+    fs::{create_dir_all, remove_dir_all},
+    io::Write,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+};
+
+#[derive(Args)]
+pub struct Options {
+    #[arg(value_parser = config::parse_ips)]
+    ips: String,
+
+    #[arg(value_parser = config::parse_output_dir)]
+    output_dir: PathBuf,
+
+    #[arg(value_parser = protocol::parse_id)]
+    id: String,
+
+    #[arg(short, long, default_value_t = 1)]
+    frame: usize,
+
+    #[arg(short, long, default_value = "2x2", value_parser = parse_grid)]
+    tiles: (u32, u32),
+
+    #[command(flatten)]
+    settings: RenderSettings,
+}
+
+struct StillJob<'a> {
+    id: &'a str,
+    frame: usize,
+    columns: u32,
+    rows: u32,
+    settings: &'a RenderSettings,
+    tile_dir: PathBuf,
+    tiles: Mutex<Vec<u32>>,
+    failures: Mutex<HashMap<u32, usize>>,
+    rendered: Mutex<HashMap<u32, PathBuf>>,
+}
+
+impl StillJob<'_> {
+    fn region(&self, tile: u32) -> Region {
+        let (column, row) = (tile % self.columns, tile / self.columns);
+
+        Region {
+            min_x: column as f64 / self.columns as f64,
+            max_x: (column + 1) as f64 / self.columns as f64,
+            min_y: 1.0 - (row + 1) as f64 / self.rows as f64,
+            max_y: 1.0 - row as f64 / self.rows as f64,
+        }
+    }
+
+    fn next_tile(&self) -> Option<u32> {
+        self.tiles.lock().unwrap().pop()
+    }
+
+    fn fail(&self, ip: &str, tile: u32) {
+        let failures = {
+            let mut failures = self.failures.lock().unwrap();
+            let failures = failures.entry(tile).or_default();
+            *failures += 1;
+            *failures
+        };
+
+        let retries = self.settings.retries.unwrap_or(FRAME_RETRIES);
+        if failures <= retries {
+            error!(
+                "{}: {}",
+                output::server(ip),
+                paint(
+                    format!(
+                        "Tile {} failed (attempt {} of {}), requeueing",
+                        tile,
+                        failures,
+                        retries + 1
+                    ),
+                    Color::Red
+                )
+            );
+            self.tiles.lock().unwrap().insert(0, tile);
+        } else {
+            error!(
+                "{}: {}",
+                output::server(ip),
+                paint(
+                    format!("Tile {} failed {} times, giving up", tile, failures),
+                    Color::Red
+                )
+            );
+        }
+    }
+}
+
+fn parse_grid(grid: &str) -> Result<(u32, u32), String> {
+    let (columns, rows) = grid
+        .split_once('x')
+        .ok_or_else(|| String::from("Expected COLUMNSxROWS"))?;
+
+    match (columns.parse(), rows.parse()) {
+        (Ok(columns), Ok(rows)) if columns > 0 && rows > 0 => Ok((columns, rows)),
+        _ => Err(format!("Invalid tile grid \"{}\"", grid)),
+    }
+}
+
+/// Renders one frame split into a grid of border-rendered tiles across all servers and stitches
+/// the tiles into a single image. Returns whether every tile was rendered.
+pub fn render_still(options: Options) -> bool {
+    let Options {
+        ips,
+        output_dir,
+        id,
+        frame,
+        tiles: (columns, rows),
+        settings,
+    } = options;
+
+    let output_dir = match paths::output_dir(&output_dir) {
+        Ok(output_dir) => output_dir,
+        Err(message) => {
+            error!("{}", message);
+            return false;
+        }
+    };
+
+    let tile_dir = output_dir.join(format!(".{}-{:04}.tiles", id, frame));
+    create_dir_all(&tile_dir).unwrap();
+
+    let job = StillJob {
+        id: &id,
+        frame,
+        columns,
+        rows,
+        settings: &settings,
+        tile_dir,
+        tiles: Mutex::new((0..columns * rows).rev().collect()),
+        failures: Mutex::new(HashMap::new()),
+        rendered: Mutex::new(HashMap::new()),
+    };
+
+    log!(
+        "Rendering frame {} of \"{}\" as {} tiles ({}x{})",
+        frame,
+        id,
+        columns * rows,
+        columns,
+        rows
+    );
+
+    thread::scope(|scope| {
+        for ip in ips.split_terminator(',') {
+            let job = &job;
+            scope.spawn(inherit(move || {
+                if let Err(message) = render_tiles(ip, job) {
+                    error!(
+                        "{}: {}\nReason: {}",
+                        output::server(ip),
+                        paint("Stopped rendering tiles", Color::Red),
+                        message
+                    );
+                }
+            }));
+        }
+    });
+
+    let rendered = job.rendered.into_inner().unwrap();
+    let missing: Vec<String> = (0..columns * rows)
+        .filter(|tile| !rendered.contains_key(tile))
+        .map(|tile| tile.to_string())
+        .collect();
+
+    if !missing.is_empty() {
+        error!(
+            "{}\nTiles kept in {}",
+            paint(
+                format!("Tiles {} were not rendered", missing.join(", ")),
+                Color::Red
+            ),
+            job.tile_dir.display()
+        );
+        return false;
+    }
+
+    match stitch(&rendered, columns, rows, &output_dir, frame) {
+        Ok(image) => {
+            log!("Saved frame {} as {}", frame, image.display());
+            let _ = remove_dir_all(&job.tile_dir);
+            true
+        }
+        Err(message) => {
+            error!(
+                "{}\nReason: {}\nTiles kept in {}",
+                paint("Stitching tiles failed", Color::Red),
+                message,
+                job.tile_dir.display()
+            );
+            false
+        }
+    }
+}
+
+fn render_tiles(ip: &str, job: &StillJob) -> Result<(), String> {
+    if job.tiles.lock().unwrap().is_empty() {
+        return Ok(());
+    }
+
+    let mut server = pool::try_checkout(ip, None).map_err(|error| error.to_string())?;
+
+    let request = to_header(
+        serde_json::to_vec(&Request::Render {
+            id: Some(String::from(job.id)),
+            prefetch: false,
+            addons: job.settings.addons.clone(),
+            heartbeat: false,
+            blender: job.settings.blender_version.clone(),
+            compression: compression(),
+            priority: None,
+            user: user(),
+        })
+        .unwrap(),
+    );
+    server
+        .write_all(&request)
+        .map_err(|error| error.to_string())?;
+
+    let response = read_header(&mut server).map_err(|error| error.to_string())?;
+    if let RenderAcceptResponse::Reject { .. } = serde_json::from_slice(&response).unwrap() {
+        return Err(String::from("Render request was rejected"));
+    }
+
+    while let Some(tile) = job.next_tile() {
+        let settings = RenderSettings {
+            region: Some(job.region(tile)),
+            passes: false,
+            ..job.settings.clone()
+        };
+
+        let rendered = render_tile(&mut server, job, tile, settings);
+        match rendered {
+            Ok(Some(image)) => {
+                log!(
+                    "{}: Rendered tile {} of {}",
+                    output::server(ip),
+                    tile + 1,
+                    job.columns * job.rows
+                );
+                job.rendered.lock().unwrap().insert(tile, image);
+            }
+            Ok(None) => job.fail(ip, tile),
+            Err(error) => {
+                job.tiles.lock().unwrap().push(tile);
+                return Err(error.to_string());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn render_tile(
+    server: &mut std::net::TcpStream,
+    job: &StillJob,
+    tile: u32,
+    settings: RenderSettings,
+) -> Result<Option<PathBuf>, std::io::Error> {
+    let request = to_header(
+        serde_json::to_vec(&FrameRequest {
+            id: String::from(job.id),
+            frame: job.frame,
+            settings,
+        })
+        .unwrap(),
+    );
+    server.write_all(&request)?;
+
+    loop {
+        let header = read_header(server)?;
+
+        match serde_json::from_slice(&header).unwrap() {
+            RenderResponse::Okay {
+                size,
+                extension,
+                checksum,
+                compressed,
+                ..
+            } => {
+                let image = transfer::read_exact(&mut *server, size)?;
+                let image = if compressed {
+                    zstd::decode_all(&image[..]).unwrap_or_default()
+                } else {
+                    image.to_vec()
+                };
+                if checksum.is_some_and(|checksum| checksum != hash(&image)) {
+                    return Ok(None);
+                }
+
+                let path = job.tile_dir.join(format!("{:03}.{}", tile, extension));
+                write_atomic(&path, &image)?;
+
+                return Ok(Some(path));
+            }
+            RenderResponse::Fail { .. } => {
+                return Ok(None);
+            }
+            RenderResponse::Progress(_) => {}
+        }
+    }
+}
+
+fn stitch(
+    tiles: &HashMap<u32, PathBuf>,
+    columns: u32,
+    rows: u32,
+    output_dir: &Path,
+    frame: usize,
+) -> Result<PathBuf, String> {
+    let mut images = HashMap::new();
+    for (tile, path) in tiles {
+        let image = image::open(path).map_err(|error| error.to_string())?;
+        images.insert(*tile, image);
+    }
+
+    let widths: Vec<u32> = (0..columns).map(|column| images[&column].width()).collect();
+    let heights: Vec<u32> = (0..rows)
+        .map(|row| images[&(row * columns)].height())
+        .collect();
+
+    let mut canvas = ImageBuffer::new(widths.iter().sum(), heights.iter().sum());
+    for (tile, image) in &images {
+        let (column, row) = (tile % columns, tile / columns);
+        let x: u32 = widths[..column as usize].iter().sum();
+        let y: u32 = heights[..row as usize].iter().sum();
+
+        imageops::replace(&mut canvas, &image.to_rgba32f(), x as i64, y as i64);
+    }
+
+    let first = &images[&0];
+    let stitched = DynamicImage::ImageRgba32F(canvas);
+    let stitched = match first.color() {
+        image::ColorType::Rgb8 | image::ColorType::L8 => {
+            DynamicImage::ImageRgb8(stitched.to_rgb8())
+        }
+        image::ColorType::Rgba8 | image::ColorType::La8 => {
+            DynamicImage::ImageRgba8(stitched.to_rgba8())
+        }
+        image::ColorType::Rgb16 | image::ColorType::L16 => {
+            DynamicImage::ImageRgb16(stitched.to_rgb16())
+        }
+        image::ColorType::Rgba16 | image::ColorType::La16 => {
+            DynamicImage::ImageRgba16(stitched.to_rgba16())
+        }
+        image::ColorType::Rgb32F => DynamicImage::ImageRgb32F(stitched.to_rgb32f()),
+        _ => stitched,
+    };
+
+    let extension = tiles[&0].extension().unwrap().to_str().unwrap();
+    let path = output_dir.join(format!("{:04}.{}", frame, extension));
+    stitched.save(&path).map_err(|error| error.to_string())?;
+
+    Ok(path)
+}
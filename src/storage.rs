@@ -0,0 +1,127 @@
+use std::{
+    io::{Error, ErrorKind},
+    path::Path,
+    process,
+};
+
+pub trait Storage: Send + Sync {
+    fn store(&self, path: &Path) -> Result<(), Error>;
+    fn restore(&self, path: &Path) -> Result<(), Error>;
+    fn restore_dir(&self, path: &Path) -> Result<(), Error>;
+    fn remove_dir(&self, path: &Path) -> Result<(), Error>;
+}
+
+pub struct Local;
+
+impl Storage for Local {
+    fn store(&self, _: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn restore(&self, path: &Path) -> Result<(), Error> {
+        if path.is_file() {
+            Ok(())
+        } else {
+            Err(ErrorKind::NotFound.into())
+        }
+    }
+
+    fn restore_dir(&self, _: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn remove_dir(&self, _: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+pub struct S3 {
+    url: String,
+}
+
+impl S3 {
+    fn object(&self, path: &Path) -> String {
+        format!(
+            "{}/{}",
+            self.url.trim_end_matches('/'),
+            path.to_str().unwrap().replace('\\', "/")
+        )
+    }
+
+    fn aws(&self, args: &[&str]) -> Result<(), Error> {
+        let output = process::Command::new("aws")
+            .arg("s3")
+            .args(args)
+            .arg("--only-show-errors")
+            .output()?;
+
+        if output.status.success() {
+            Ok(())
+        } else {
+            Err(aws_error(String::from_utf8_lossy(&output.stderr).trim()))
+        }
+    }
+}
+
+/// Turns the aws CLI's error output into an error of kind `NotFound` for missing objects.
+fn aws_error(message: &str) -> Error {
+    let kind = if message.contains("(404)") || message.contains("NoSuchKey") {
+        ErrorKind::NotFound
+    } else {
+        ErrorKind::Other
+    };
+
+    Error::new(kind, message)
+}
+
+impl Storage for S3 {
+    fn store(&self, path: &Path) -> Result<(), Error> {
+        self.aws(&["cp", path.to_str().unwrap(), &self.object(path)])
+    }
+
+    fn restore(&self, path: &Path) -> Result<(), Error> {
+        if path.is_file() {
+            return Ok(());
+        }
+
+        self.aws(&["cp", &self.object(path), path.to_str().unwrap()])
+    }
+
+    fn restore_dir(&self, path: &Path) -> Result<(), Error> {
+        self.aws(&["sync", &(self.object(path) + "/"), path.to_str().unwrap()])
+    }
+
+    fn remove_dir(&self, path: &Path) -> Result<(), Error> {
+        self.aws(&["rm", "--recursive", &(self.object(path) + "/")])
+    }
+}
+
+pub fn open(url: Option<&str>) -> Result<Box<dyn Storage>, String> {
+    match url {
+        None => Ok(Box::new(Local)),
+        Some(url) if url.starts_with("s3://") => Ok(Box::new(S3 {
+            url: String::from(url),
+        })),
+        Some(url) => Err(format!("Unsupported storage URL {}", url)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_missing_objects() {
+        let head = "fatal error: An error occurred (404) when calling the HeadObject operation: \
+            Key \"users/shot/blend.blend\" does not exist";
+        let get = "download failed: An error occurred (NoSuchKey) when calling the GetObject \
+            operation: The specified key does not exist.";
+        let denied = "fatal error: An error occurred (403) when calling the HeadObject operation: \
+            Forbidden";
+
+        assert_eq!(aws_error(head).kind(), ErrorKind::NotFound);
+        assert_eq!(aws_error(get).kind(), ErrorKind::NotFound);
+        assert_eq!(aws_error(denied).kind(), ErrorKind::Other);
+        assert_eq!(aws_error(denied).to_string(), denied);
+    }
+}
@@ -0,0 +1,177 @@
+use crate::{
+    RenderResponse, Request, Response, config, format_speed, inherit, output, read_header,
+    to_header, transfer, try_connect, user,
+};
+use clap::Args;
+use std::{
+    io::{self, Read, Write},
+    sync::{
+        Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+const ID: &str = "brsp-stress";
+const STRESS_TTL: u64 = 60 * 60;
+
+#[derive(Args)]
+pub struct Options {
+    #[arg(value_parser = config::parse_ips)]
+    ips: String,
+
+    #[arg(short, long, default_value_t = 4)]
+    concurrency: usize,
+
+    #[arg(short, long, default_value_t = 100)]
+    requests: usize,
+
+    #[arg(long, default_value_t = 1 << 20)]
+    upload_size: usize,
+
+    #[arg(long, default_value_t = 1 << 20)]
+    render_size: usize,
+}
+
+#[derive(Default)]
+struct Stats {
+    latencies: Vec<Duration>,
+    bytes: usize,
+    errors: usize,
+}
+
+pub fn stress(options: Options) {
+    let rows: Vec<Vec<String>> = thread::scope(|scope| {
+        let handles: Vec<_> = options
+            .ips
+            .split_terminator(',')
+            .map(|ip| {
+                let options = &options;
+                scope.spawn(inherit(move || stress_server(ip, options)))
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    println!(
+        "{}",
+        output::table(
+            &["SERVER", "OK", "ERRORS", "P50", "P90", "P99", "THROUGHPUT"],
+            &rows
+        )
+    );
+}
+
+fn stress_server(ip: &str, options: &Options) -> Vec<String> {
+    let next = AtomicUsize::new(0);
+    let stats = Mutex::new(Stats::default());
+    let start = Instant::now();
+
+    thread::scope(|scope| {
+        for _ in 0..options.concurrency.max(1) {
+            scope.spawn(inherit(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= options.requests {
+                        return;
+                    }
+
+                    let started = Instant::now();
+                    let result = if index.is_multiple_of(2) {
+                        upload(ip, options.upload_size)
+                    } else {
+                        null_render(ip, options.render_size)
+                    };
+
+                    let mut stats = stats.lock().unwrap();
+                    match result {
+                        Ok(bytes) => {
+                            stats.latencies.push(started.elapsed());
+                            stats.bytes += bytes;
+                        }
+                        Err(_) => {
+                            stats.errors += 1;
+                        }
+                    }
+                }
+            }));
+        }
+    });
+
+    let elapsed = start.elapsed();
+    let mut stats = stats.into_inner().unwrap();
+    stats.latencies.sort();
+
+    let percentile = |percentile: usize| match stats.latencies.len() {
+        0 => String::from("-"),
+        len => format!(
+            "{:.1}ms",
+            stats.latencies[(len - 1) * percentile / 100].as_secs_f64() * 1000.0
+        ),
+    };
+
+    vec![
+        output::server(ip),
+        stats.latencies.len().to_string(),
+        stats.errors.to_string(),
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        format_speed(stats.bytes, elapsed),
+    ]
+}
+
+fn upload(ip: &str, size: usize) -> Result<usize, String> {
+    let mut server =
+        try_connect(ip, Some(Duration::from_secs(5))).map_err(|error| error.to_string())?;
+
+    let request = to_header(
+        serde_json::to_vec(&Request::Upload {
+            id: String::from(ID),
+            size,
+            ttl: Some(STRESS_TTL),
+            hash: None,
+            blender: None,
+            compressed: false,
+            user: user(),
+        })
+        .unwrap(),
+    );
+
+    server
+        .write_all(&request)
+        .and_then(|()| transfer::send_chunked(io::repeat(0).take(size as u64), &server, |_| {}))
+        .map_err(|error| error.to_string())?;
+    let header = read_header(&mut server).map_err(|error| error.to_string())?;
+
+    match serde_json::from_slice(&header).map_err(|error| error.to_string())? {
+        Response::Okay => Ok(size),
+        Response::Fail { message } => Err(message),
+    }
+}
+
+fn null_render(ip: &str, size: usize) -> Result<usize, String> {
+    let mut server =
+        try_connect(ip, Some(Duration::from_secs(5))).map_err(|error| error.to_string())?;
+
+    let request = to_header(serde_json::to_vec(&Request::NullRender { size }).unwrap());
+    server
+        .write_all(&request)
+        .map_err(|error| error.to_string())?;
+    let header = read_header(&mut server).map_err(|error| error.to_string())?;
+
+    match serde_json::from_slice(&header).map_err(|error| error.to_string())? {
+        RenderResponse::Okay { size, .. } => {
+            transfer::read_exact(&mut server, size).map_err(|error| error.to_string())?;
+            Ok(size)
+        }
+        RenderResponse::Fail { .. } | RenderResponse::Progress(_) => Err(String::from(
+            "Null render refused, the server needs an auth token and allows at most 256 MiB",
+        )),
+    }
+}
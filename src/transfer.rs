@@ -0,0 +1,350 @@
+use std::{
+    cell::Cell,
+    collections::BTreeSet,
+    io::{Error, ErrorKind, Read, Write},
+    ops::{Deref, DerefMut},
+    sync::{Condvar, Mutex},
+};
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub use uring::{read_file, write_all};
+
+const POOL_SIZE: usize = 4;
+pub const CHUNK_SIZE: usize = 1 << 20;
+
+static POOL: Mutex<Vec<Vec<u8>>> = Mutex::new(Vec::new());
+
+pub struct Buffer(Vec<u8>);
+
+impl Buffer {
+    pub fn take(len: usize) -> Buffer {
+        let mut pool = POOL.lock().unwrap();
+        let mut data = match pool.iter().position(|data| data.capacity() >= len) {
+            Some(index) => pool.swap_remove(index),
+            None => pool.pop().unwrap_or_default(),
+        };
+        drop(pool);
+
+        data.clear();
+        data.resize(len, 0);
+        Buffer(data)
+    }
+}
+
+impl Deref for Buffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.0
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.0
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let mut pool = POOL.lock().unwrap();
+        if pool.len() < POOL_SIZE {
+            pool.push(std::mem::take(&mut self.0));
+        }
+    }
+}
+
+pub fn read_exact(mut reader: impl Read, len: usize) -> Result<Buffer, Error> {
+    let mut data = Buffer::take(len);
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+pub fn send_chunked(
+    mut source: impl Read,
+    mut stream: impl Write,
+    mut progress: impl FnMut(usize),
+) -> Result<usize, Error> {
+    let mut buffer = Buffer::take(CHUNK_SIZE);
+    let mut sent = 0;
+
+    loop {
+        let len = source.read(&mut buffer)?;
+        stream.write_all(&(len as u32).to_le_bytes())?;
+
+        if len == 0 {
+            return Ok(sent);
+        }
+
+        stream.write_all(&buffer[..len])?;
+        sent += len;
+        progress(sent);
+    }
+}
+
+pub fn receive_chunked(
+    mut stream: impl Read,
+    mut sink: impl Write,
+    limit: usize,
+) -> Result<usize, Error> {
+    let mut buffer = Buffer::take(CHUNK_SIZE);
+    let mut received = 0;
+
+    loop {
+        let mut len = [0; 4];
+        stream.read_exact(&mut len)?;
+        let len = u32::from_le_bytes(len) as usize;
+
+        if len == 0 {
+            return Ok(received);
+        }
+
+        if len > CHUNK_SIZE || received + len > limit {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Chunk exceeds the announced size",
+            ));
+        }
+
+        stream.read_exact(&mut buffer[..len])?;
+        sink.write_all(&buffer[..len])?;
+        received += len;
+    }
+}
+
+/// A sink that refuses to take more than `limit` bytes, so decompressed uploads cannot grow past
+/// their announced size.
+pub struct Limited<W> {
+    pub inner: W,
+    pub written: usize,
+    limit: usize,
+}
+
+impl<W> Limited<W> {
+    pub fn new(inner: W, limit: usize) -> Limited<W> {
+        Limited {
+            inner,
+            written: 0,
+            limit,
+        }
+    }
+}
+
+impl<W: Write> Write for Limited<W> {
+    fn write(&mut self, data: &[u8]) -> Result<usize, Error> {
+        if self.written + data.len() > self.limit {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                "Data exceeds the announced size",
+            ));
+        }
+
+        let len = self.inner.write(data)?;
+        self.written += len;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> Result<(), Error> {
+        self.inner.flush()
+    }
+}
+
+/// Counts the bytes read from `inner`, for progress reports on streams that are compressed
+/// before sending.
+pub struct Counted<'a, R> {
+    pub inner: R,
+    pub count: &'a Cell<usize>,
+}
+
+impl<R: Read> Read for Counted<'_, R> {
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let len = self.inner.read(buffer)?;
+        self.count.set(self.count.get() + len);
+        Ok(len)
+    }
+}
+
+pub fn checksum(mut reader: impl Read) -> Result<String, Error> {
+    let mut buffer = Buffer::take(CHUNK_SIZE);
+    let mut hasher = blake3::Hasher::new();
+
+    loop {
+        match reader.read(&mut buffer)? {
+            0 => {
+                return Ok(hasher.finalize().to_hex().to_string());
+            }
+            len => {
+                hasher.update(&buffer[..len]);
+            }
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+pub fn read_file(path: &std::path::Path) -> Result<Buffer, Error> {
+    let file = std::fs::File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+
+    read_exact(file, len)
+}
+
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+pub fn write_all(mut stream: &std::net::TcpStream, data: &[u8]) -> Result<(), Error> {
+    stream.write_all(data)
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+mod uring {
+    use super::Buffer;
+    use io_uring::{IoUring, opcode, squeue, types::Fd};
+    use std::{
+        cell::RefCell,
+        fs::File,
+        io::{Error, ErrorKind},
+        net::TcpStream,
+        os::fd::AsRawFd,
+        path::Path,
+    };
+
+    const MAX_LEN: usize = 1 << 30;
+
+    thread_local! {
+        static RING: RefCell<Option<IoUring>> = const { RefCell::new(None) };
+    }
+
+    fn submit(entry: squeue::Entry) -> Result<usize, Error> {
+        RING.with(|ring| {
+            let mut ring = ring.borrow_mut();
+            if ring.is_none() {
+                *ring = Some(IoUring::new(8)?);
+            }
+
+            let ring = ring.as_mut().unwrap();
+
+            unsafe {
+                ring.submission()
+                    .push(&entry)
+                    .map_err(|_| Error::other("io_uring submission queue is full"))?;
+            }
+            ring.submit_and_wait(1)?;
+
+            let result = ring
+                .completion()
+                .next()
+                .ok_or_else(|| Error::other("io_uring completion is missing"))?
+                .result();
+
+            if result < 0 {
+                Err(Error::from_raw_os_error(-result))
+            } else {
+                Ok(result as usize)
+            }
+        })
+    }
+
+    pub fn read_file(path: &Path) -> Result<Buffer, Error> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+
+        let mut data = Buffer::take(len);
+        let mut read = 0;
+
+        while read < len {
+            let chunk = (len - read).min(MAX_LEN);
+            let entry = opcode::Read::new(
+                Fd(file.as_raw_fd()),
+                data[read..].as_mut_ptr(),
+                chunk as u32,
+            )
+            .offset(read as u64)
+            .build();
+
+            match submit(entry)? {
+                0 => {
+                    return Err(ErrorKind::UnexpectedEof.into());
+                }
+                count => read += count,
+            }
+        }
+
+        Ok(data)
+    }
+
+    pub fn write_all(stream: &TcpStream, data: &[u8]) -> Result<(), Error> {
+        let mut sent = 0;
+
+        while sent < data.len() {
+            let chunk = (data.len() - sent).min(MAX_LEN);
+            let entry =
+                opcode::Send::new(Fd(stream.as_raw_fd()), data[sent..].as_ptr(), chunk as u32)
+                    .build();
+
+            match submit(entry)? {
+                0 => {
+                    return Err(ErrorKind::WriteZero.into());
+                }
+                count => sent += count,
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Scheduler {
+    limit: Option<usize>,
+    state: Mutex<SchedulerState>,
+    changed: Condvar,
+}
+
+struct SchedulerState {
+    active: usize,
+    tickets: u64,
+    waiting: BTreeSet<(usize, u64)>,
+}
+
+pub struct Permit<'a>(&'a Scheduler);
+
+impl Scheduler {
+    pub fn new(limit: Option<usize>) -> Scheduler {
+        Scheduler {
+            limit: limit.map(|limit| limit.max(1)),
+            state: Mutex::new(SchedulerState {
+                active: 0,
+                tickets: 0,
+                waiting: BTreeSet::new(),
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    pub fn acquire(&self, frame: usize) -> Permit<'_> {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(limit) = self.limit {
+            let key = (frame, state.tickets);
+            state.tickets += 1;
+            state.waiting.insert(key);
+
+            state = self
+                .changed
+                .wait_while(state, |state| {
+                    state.active >= limit || state.waiting.first() != Some(&key)
+                })
+                .unwrap();
+            state.waiting.remove(&key);
+        }
+
+        state.active += 1;
+        self.changed.notify_all();
+
+        Permit(self)
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.0.state.lock().unwrap().active -= 1;
+        self.0.changed.notify_all();
+    }
+}
@@ -0,0 +1,86 @@
+use crate::logging::{error, log};
+use igd_next::{Gateway, PortMappingProtocol, SearchOptions, search_gateway};
+use std::{
+    net::{SocketAddr, UdpSocket},
+    thread,
+    time::Duration,
+};
+
+const LEASE: u32 = 3600;
+
+pub struct Mapping {
+    gateway: Gateway,
+    local: SocketAddr,
+    port: u16,
+}
+
+impl Mapping {
+    pub fn new(port: u16) -> Result<Mapping, String> {
+        let gateway = search_gateway(SearchOptions::default())
+            .map_err(|error| format!("No UPnP gateway found: {}", error))?;
+
+        let local = {
+            let socket = UdpSocket::bind("0.0.0.0:0").map_err(|error| error.to_string())?;
+            socket
+                .connect(gateway.addr)
+                .map_err(|error| error.to_string())?;
+
+            SocketAddr::new(socket.local_addr().unwrap().ip(), port)
+        };
+
+        let mapping = Mapping {
+            gateway,
+            local,
+            port,
+        };
+        mapping.add()?;
+
+        let external = mapping
+            .gateway
+            .get_external_ip()
+            .map_err(|error| format!("Could not get external address: {}", error))?;
+        log!(
+            "Mapped external address {} to {}",
+            SocketAddr::new(external, port),
+            local
+        );
+
+        Ok(mapping)
+    }
+
+    fn add(&self) -> Result<(), String> {
+        self.gateway
+            .add_port(
+                PortMappingProtocol::TCP,
+                self.port,
+                self.local,
+                LEASE,
+                "brsp",
+            )
+            .map_err(|error| format!("Port mapping failed: {}", error))
+    }
+
+    pub fn refresh(&self) {
+        loop {
+            thread::sleep(Duration::from_secs(u64::from(LEASE / 2)));
+
+            if let Err(message) = self.add() {
+                error!("Refreshing port mapping failed\nReason: {}", message);
+            }
+        }
+    }
+
+    pub fn release(&self) {
+        match self
+            .gateway
+            .remove_port(PortMappingProtocol::TCP, self.port)
+        {
+            Ok(()) => {
+                log!("Released port mapping for port {}", self.port);
+            }
+            Err(error) => {
+                error!("Releasing port mapping failed\nReason: {}", error);
+            }
+        }
+    }
+}